@@ -1,26 +1,84 @@
-use std::path::PathBuf;
+use std::{
+	cell::OnceCell,
+	collections::{
+		BTreeMap,
+		HashSet,
+	},
+	path::PathBuf,
+	process::ExitCode,
+};
 
-use anyhow::Result;
+use anyhow::{
+	Result,
+	bail,
+};
 use clap::Parser;
+use colored::Colorize;
+use dialoguer::{
+	Confirm,
+	theme::ColorfulTheme,
+};
 use tracing::{
+	debug,
 	error,
 	info,
+	warn,
 };
 
 use crate::{
+	backup,
 	cli::{
+		BackupsCommand,
+		CacheCommand,
 		Cli,
 		Commands,
+		VscCommand,
+	},
+	config::{
+		self,
+		Config,
+		ConflictStrategy,
+		Package,
+	},
+	depends,
+	doctor::{
+		self,
+		Severity,
+	},
+	generation,
+	hooks::{
+		self,
+		Hook,
+	},
+	linker::{
+		self,
+		LinkStatusKind,
+		Transaction,
 	},
-	config,
 	monitor,
+	ownership,
+	repl,
+	setup,
+	state::{
+		self,
+		State,
+	},
+	utils::current_os,
+	vsc::{
+		self,
+		Versions,
+	},
 };
 
 /// Main application controller.
 pub struct App {
-	config_path: PathBuf,
-	command:     Commands,
-	_dry_run:    bool,
+	config_path:       PathBuf,
+	command:           Commands,
+	dry_run:           bool,
+	no_hooks:          bool,
+	profile_override:  Option<String>,
+	conflict_strategy: Option<ConflictStrategy>,
+	versions:          OnceCell<Versions>,
 }
 
 impl Default for App {
@@ -42,307 +100,1181 @@ impl App {
 		Self {
 			config_path,
 			command: cli.command,
-			_dry_run: cli.dry_run,
+			dry_run: cli.dry_run,
+			no_hooks: cli.no_hooks,
+			profile_override: cli.profile,
+			conflict_strategy: cli.conflict_strategy,
+			versions: OnceCell::new(),
 		}
 	}
 
-	pub fn run(&self) -> Result<()> {
-		config::initialize_config(self.config_path.to_str().unwrap())?;
-		info!("Loaded config from {}", self.config_path.display());
-		match &self.command {
+	/// Runs the command. `status` exits with code 2 when any package is out
+	/// of sync; errors are left to the caller.
+	pub fn run(&self) -> Result<ExitCode> {
+		let result = match &self.command {
+			Commands::List => self.handle_list(),
+			Commands::Status { json } => return self.handle_status(*json),
+			Commands::Link { packages, force } => {
+				self.handle_link(packages, *force)
+			}
+			Commands::Remove { packages } => self.handle_remove(packages),
+			Commands::Adopt { packages, force } => {
+				self.handle_adopt(packages, *force)
+			}
+			Commands::UseProfile { name } => self.handle_use_profile(name),
+			Commands::Vsc {
+				command: Some(VscCommand::List { package }),
+				..
+			} => self.handle_vsc_list(package.as_deref()),
+			Commands::Vsc {
+				package: Some(package),
+				version: Some(version),
+				no_apply,
+				..
+			} => self.handle_vsc(package, version, *no_apply),
+			Commands::Vsc { .. } => unreachable!("enforced by clap"),
+			Commands::Generations => Self::handle_generations(),
+			Commands::Rollback { generation } => {
+				self.handle_rollback(*generation)
+			}
+			Commands::Backups { command } => self.handle_backups(command),
+			Commands::Cache { command } => self.handle_cache(command),
+			Commands::Prune { yes } => self.handle_prune(*yes),
+			Commands::Doctor => return self.handle_doctor(),
+			Commands::Init { repo, dest } => {
+				setup::init(repo.clone(), dest.clone())?;
+				Ok(())
+			}
+			Commands::Interactive => {
+				repl::run()?;
+				Ok(())
+			}
 			Commands::Start => {
-				monitor::start()?;
+				self.preload_config();
+				monitor::start()
 			}
 			Commands::Run => {
 				// This is the entry point for the detached Windows process
-				monitor::run_service_loop()?;
+				self.preload_config();
+				monitor::run_service_loop()
+			}
+			Commands::Stop => monitor::stop(),
+		};
+		result.map(|()| ExitCode::SUCCESS)
+	}
+
+	/// Loads the config for the daemon, which watches its source directory
+	/// to keep the version cache warm.
+	fn preload_config(&self) {
+		let path = self.config_path.to_str().unwrap();
+		if let Err(e) = config::initialize_config(path) {
+			warn!(
+				"cannot load the config, not watching the source directory: \
+				 {e:#}"
+			);
+		}
+	}
+
+	fn load_config_and_state(&self) -> Result<(&'static Config, State)> {
+		let cfg =
+			config::initialize_config(self.config_path.to_str().unwrap())?;
+		debug!("Loaded config from {}", self.config_path.display());
+		let state = match state::load_state(&state::default_state_path()) {
+			Ok(s) => s,
+			Err(e) => {
+				warn!("Failed to load state file: {}", e);
+				if self.dry_run {
+					info!("Using default state for dry run");
+				} else {
+					warn!("Using default state, previous settings may be lost");
+				}
+				State::default()
+			}
+		};
+		Ok((cfg, state))
+	}
+
+	/// The versions in the source directory, discovered once per command.
+	/// A dry run leaves the version cache as it is.
+	fn versions(&self, cfg: &Config) -> Result<&Versions> {
+		if let Some(versions) = self.versions.get() {
+			return Ok(versions);
+		}
+		let versions = vsc::discover(cfg, self.dry_run)?;
+		Ok(self.versions.get_or_init(|| versions))
+	}
+
+	fn handle_list(&self) -> Result<()> {
+		let (cfg, _) = self.load_config_and_state()?;
+		if cfg.packages.is_empty() {
+			println!("No packages defined in {}", self.config_path.display());
+		} else {
+			for name in cfg.packages.keys() {
+				println!("{name}");
+			}
+		}
+		Ok(())
+	}
+
+	fn handle_status(&self, json: bool) -> Result<ExitCode> {
+		let (cfg, state) = self.load_config_and_state()?;
+		// Packages for other systems are reported rather than hidden.
+		let selected =
+			profile_packages(cfg, self.profile_override.as_deref(), &state);
+		let owned = ownership::load()?;
+		let versions = self.versions(cfg)?;
+		let statuses: Vec<_> = selected
+			.iter()
+			.map(|(name, pkg)| {
+				linker::status_package(name, pkg, cfg, versions, &state, &owned)
+			})
+			.collect();
+		let code = if statuses.iter().all(|stat| stat.kind.is_in_sync()) {
+			ExitCode::SUCCESS
+		} else {
+			ExitCode::from(2)
+		};
+
+		if json {
+			#[derive(serde::Serialize)]
+			struct Item<'a> {
+				name:    &'a str,
+				status:  &'a str,
+				source:  String,
+				target:  String,
+				files:   &'a [linker::FileStatus],
+				version: Option<&'a linker::VersionChoice>,
+			}
+			let out: Vec<_> = statuses
+				.iter()
+				.map(|stat| Item {
+					name:    &stat.package,
+					status:  stat.kind.as_str(),
+					source:  stat.source.display().to_string(),
+					target:  stat.target.display().to_string(),
+					files:   &stat.files,
+					version: stat.version.as_ref(),
+				})
+				.collect();
+			println!("{}", serde_json::to_string_pretty(&out)?);
+			return Ok(code);
+		}
+
+		for stat in &statuses {
+			let path = match stat.kind {
+				LinkStatusKind::SourceMissing => &stat.source,
+				_ => &stat.target,
+			};
+			println!(
+				"{}: {} -> {}",
+				stat.package.bold(),
+				colored_status(stat.kind),
+				path.display()
+			);
+			// Say which version is used, and draw attention to fallbacks.
+			if let Some(choice) = &stat.version {
+				let line = choice.to_string();
+				if choice.version.is_none() || choice.fallback.is_some() {
+					println!("    version {}", line.yellow());
+				} else {
+					println!("    version {line}");
+				}
+			}
+			// Tree packages: point at the files that need attention.
+			if stat.files.len() > 1 {
+				for file in stat.files.iter().filter(|f| !f.status.is_in_sync())
+				{
+					println!(
+						"    {} {}",
+						colored_status(file.status),
+						file.target.display()
+					);
+				}
+			}
+		}
+		println!(
+			"monitor: {}",
+			if monitor::status() {
+				"running"
+			} else {
+				"stopped"
+			}
+		);
+		Ok(code)
+	}
+
+	fn handle_prune(&self, yes: bool) -> Result<()> {
+		let (cfg, state) = self.load_config_and_state()?;
+		let versions = self.versions(cfg)?;
+		let mut tx = Transaction::begin(self.dry_run)?;
+		let orphans =
+			linker::find_orphans(cfg, versions, &state, tx.ownership());
+		for link in linker::stale_records(tx.ownership()) {
+			debug!("forgetting {}, which is gone", link.display());
+			tx.forget(&link);
+		}
+
+		let (managed, unmanaged): (Vec<_>, Vec<_>) =
+			orphans.iter().partition(|o| o.package.is_some());
+		for orphan in &unmanaged {
+			println!(
+				"{}: {} -> {} (not managed by rinkle, left in place)",
+				"unmanaged".dimmed(),
+				orphan.link.display(),
+				orphan.points_to.display()
+			);
+		}
+		for orphan in &managed {
+			let kind = if orphan.dangling {
+				"dangling".red()
+			} else {
+				"orphan".yellow()
+			};
+			println!(
+				"{kind}: {} -> {} ({})",
+				orphan.link.display(),
+				orphan.points_to.display(),
+				orphan.package.as_deref().unwrap_or_default()
+			);
+		}
+		if managed.is_empty() {
+			println!("No orphaned links");
+			tx.commit()?;
+			return Ok(());
+		}
+		if !yes && !self.dry_run {
+			if !atty::is(atty::Stream::Stdin) {
+				bail!(
+					"stdin is not a terminal; pass --yes to remove these links"
+				);
+			}
+			let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+				.with_prompt(format!("Remove {} link(s)?", managed.len()))
+				.interact()?;
+			if !confirmed {
+				println!("Nothing removed");
+				return Ok(());
+			}
+		}
+
+		for orphan in &managed {
+			if let Err(e) = tx.remove_symlink(&orphan.link) {
+				tx.rollback();
+				bail!("pruning failed, all changes rolled back: {e}");
+			}
+		}
+		if self.dry_run {
+			tx.commit()?;
+			return Ok(());
+		}
+		let owned = tx.ownership().clone();
+		tx.commit()?;
+		generation::record("prune", &state, &owned)?;
+		println!("removed {} link(s)", managed.len());
+		Ok(())
+	}
+
+	fn handle_doctor(&self) -> Result<ExitCode> {
+		let (cfg, state) = self.load_config_and_state()?;
+		// An unusable template is among the problems reported.
+		let versions = self.versions(cfg).cloned().unwrap_or_default();
+		let diagnostics = doctor::diagnose(cfg, &versions, &state);
+		for d in &diagnostics {
+			let severity = d.severity.to_string();
+			let severity = match d.severity {
+				Severity::Error => severity.red().bold(),
+				Severity::Warning => severity.yellow().bold(),
+			};
+			println!("{severity}: {}", d.message);
+			if let Some(hint) = &d.hint {
+				println!("  {} {hint}", "hint:".dimmed());
 			}
-			Commands::Stop => monitor::stop()?,
-			Commands::Status => {
-				let running = monitor::status();
-				println!(
-					"monitor: {}",
-					if running { "running" } else { "stopped" }
+		}
+
+		let count = |severity| {
+			diagnostics
+				.iter()
+				.filter(|d| d.severity == severity)
+				.count()
+		};
+		let (errors, warnings) =
+			(count(Severity::Error), count(Severity::Warning));
+		if diagnostics.is_empty() {
+			println!("{}", "no problems found".green());
+		} else {
+			println!("{errors} error(s), {warnings} warning(s)");
+		}
+		Ok(if errors == 0 {
+			ExitCode::SUCCESS
+		} else {
+			ExitCode::FAILURE
+		})
+	}
+
+	/// Runs `action` for every selected package inside a single transaction.
+	///
+	/// Every package is checked against the `[policy]` section first. The
+	/// first failure (or Ctrl-C) rolls back everything done so far, so the
+	/// targets are left exactly as they were before the command.
+	fn process_packages<F>(
+		&self,
+		packages: &[String],
+		action_name: &str,
+		(pre, post): (Hook, Hook),
+		action: F,
+	) -> Result<()>
+	where
+		F: Fn(
+			&str,
+			&Package,
+			&Config,
+			&Versions,
+			&State,
+			Option<&str>,
+			&mut Transaction,
+		) -> Result<(), linker::LinkError>,
+	{
+		let (cfg, state) = self.load_config_and_state()?;
+		let versions = self.versions(cfg)?;
+		let bar = if packages.len() > 1 {
+			indicatif::ProgressBar::new(packages.len() as u64)
+		} else {
+			indicatif::ProgressBar::hidden()
+		};
+		bar.set_style(
+			indicatif::ProgressStyle::default_bar()
+				.template(
+					"{spinner:.green} [{elapsed_precise}] \
+					 [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}",
+				)?
+				.progress_chars("#>-"),
+		);
+
+		for raw in packages {
+			let (name, version) = parse_pkg_and_version(raw);
+			if let Some(pkg) = cfg.packages.get(&name) {
+				linker::check_policy(
+					&name,
+					pkg,
+					cfg,
+					versions,
+					&state,
+					version.as_deref(),
+				)?;
+			}
+		}
+
+		let mut tx = Transaction::begin(self.dry_run)?;
+		let mut done = Vec::new();
+		for raw in packages {
+			let (name, version) = parse_pkg_and_version(raw);
+			bar.set_message(name.clone());
+			tx.set_package(Some(&name));
+			let Some(pkg) = cfg.packages.get(&name) else {
+				bar.suspend(|| warn!("unknown package: {name}"));
+				bar.inc(1);
+				continue;
+			};
+			// Keep the bar out of the way of conflict prompts.
+			let result = bar.suspend(|| -> Result<()> {
+				let version = version.as_deref();
+				self.run_hook(pre, &name, pkg, cfg, &state, version)?;
+				Transaction::check_interrupted()?;
+				action(&name, pkg, cfg, versions, &state, version, &mut tx)?;
+				Ok(())
+			});
+			if let Err(e) = result {
+				bar.abandon();
+				tx.rollback();
+				bail!(
+					"{action_name} {name} failed, all changes rolled back: {e}"
 				);
 			}
-			_ => (),
+			done.push((name, version));
+			bar.inc(1);
+		}
+		print_adopted(tx.adopted(), self.dry_run);
+		if self.dry_run {
+			tx.commit()?;
+		} else {
+			let owned = tx.ownership().clone();
+			tx.commit()?;
+			generation::record(action_name, &state, &owned)?;
+		}
+		bar.finish_with_message("Done");
+
+		// The changes are kept even if a post hook fails.
+		let mut failed = 0;
+		for (name, version) in done {
+			let pkg = &cfg.packages[&name];
+			let result = self.run_hook(
+				post,
+				&name,
+				pkg,
+				cfg,
+				&state,
+				version.as_deref(),
+			);
+			if let Err(e) = result {
+				error!("{e}");
+				failed += 1;
+			}
+		}
+		if failed > 0 {
+			bail!("{failed} {post} hook(s) failed");
+		}
+		Ok(())
+	}
+
+	/// Runs `hook` of a package, unless hooks are disabled or this is a dry
+	/// run.
+	fn run_hook(
+		&self,
+		hook: Hook,
+		name: &str,
+		pkg: &Package,
+		cfg: &Config,
+		state: &State,
+		version: Option<&str>,
+	) -> Result<()> {
+		let Some(command) = hook.command(&pkg.hooks) else {
+			return Ok(());
+		};
+		if self.no_hooks {
+			debug!("skipping {hook} hook of {name}");
+			return Ok(());
+		}
+		if self.dry_run {
+			println!("[dry-run] run {hook} hook of {name}: {command}");
+			return Ok(());
+		}
+		let versions = self.versions(cfg)?;
+		hooks::run(hook, name, pkg, cfg, versions, state, version)?;
+		Ok(())
+	}
+
+	fn handle_link(&self, packages: &[String], force: bool) -> Result<()> {
+		let selected = self.link_list(packages)?;
+		self.check_overlaps(&selected, force)?;
+		self.process_packages(
+			&selected,
+			"link",
+			(Hook::PreLink, Hook::PostLink),
+			|name, pkg, cfg, versions, state, ver, tx| {
+				linker::link_package(
+					name,
+					pkg,
+					cfg,
+					versions,
+					state,
+					(ver, self.conflict_strategy),
+					tx,
+				)
+			},
+		)
+	}
+
+	fn handle_adopt(&self, packages: &[String], force: bool) -> Result<()> {
+		let selected = self.link_list(packages)?;
+		self.check_overlaps(&selected, force)?;
+		self.process_packages(
+			&selected,
+			"adopt",
+			(Hook::PreLink, Hook::PostLink),
+			|name, pkg, cfg, versions, state, ver, tx| {
+				linker::link_package(
+					name,
+					pkg,
+					cfg,
+					versions,
+					state,
+					(ver, Some(ConflictStrategy::Adopt)),
+					tx,
+				)
+			},
+		)
+	}
+
+	fn handle_remove(&self, packages: &[String]) -> Result<()> {
+		let selected = self.remove_list(packages)?;
+		self.process_packages(
+			&selected,
+			"remove",
+			(Hook::PreRemove, Hook::PostRemove),
+			|name, pkg, cfg, versions, state, ver, tx| {
+				linker::remove_package(name, pkg, cfg, versions, state, ver, tx)
+			},
+		)
+	}
+
+	/// Refuses to link `packages` if their targets, or those of the active
+	/// profile, collide or nest, unless `force` is set.
+	fn check_overlaps(&self, packages: &[String], force: bool) -> Result<()> {
+		let (cfg, state) = self.load_config_and_state()?;
+		let mut planned: BTreeMap<String, Option<String>> =
+			select_packages(cfg, self.profile_override.as_deref(), &state)
+				.into_iter()
+				.map(|(name, _)| (name.to_string(), None))
+				.collect();
+		for raw in packages {
+			let (name, version) = parse_pkg_and_version(raw);
+			planned.insert(name, version);
+		}
+		let planned: Vec<_> = planned
+			.iter()
+			.filter_map(|(name, version)| {
+				let pkg = cfg.packages.get(name)?;
+				Some((name.as_str(), pkg, version.as_deref()))
+			})
+			.collect();
+
+		let overlaps =
+			linker::find_overlaps(&planned, cfg, self.versions(cfg)?, &state);
+		if overlaps.is_empty() {
+			return Ok(());
+		}
+		for overlap in &overlaps {
+			warn!("{overlap}");
+		}
+		if !force {
+			bail!(
+				"{} overlapping package target(s), nothing was changed; fix \
+				 the targets or pass --force",
+				overlaps.len()
+			);
+		}
+		Ok(())
+	}
+
+	fn resolve_package_list(
+		&self,
+		packages: &[String],
+		cfg: &Config,
+		state: &State,
+	) -> Vec<String> {
+		if packages.is_empty() {
+			select_packages(cfg, self.profile_override.as_deref(), state)
+				.into_iter()
+				.map(|(n, _)| n.to_string())
+				.collect()
+		} else {
+			packages.to_vec()
+		}
+	}
+
+	/// The packages to link, their dependencies included, each after the
+	/// packages it depends on.
+	fn link_list(&self, packages: &[String]) -> Result<Vec<String>> {
+		let (cfg, state) = self.load_config_and_state()?;
+		let profile = self.profile_override.as_deref();
+		let (names, versions) =
+			split_versions(&self.resolve_package_list(packages, cfg, &state));
+		let in_profile: HashSet<&str> = profile_packages(cfg, profile, &state)
+			.into_iter()
+			.map(|(name, _)| name)
+			.collect();
+		let os = current_os();
+		let order = depends::link_order(cfg, &names, |name| {
+			let pkg = &cfg.packages[name];
+			if !pkg.os.is_empty() && !pkg.os.iter().any(|v| v == os) {
+				Some(format!(
+					"not applied on {os} (os = {})",
+					pkg.os.join(", ")
+				))
+			} else if !in_profile.contains(name) {
+				Some(format!(
+					"not selected by profile '{}'",
+					effective_profile(profile, &state)
+				))
+			} else {
+				None
+			}
+		})?;
+		let found = self.versions(cfg)?;
+		for name in &order {
+			if let Some(pkg) = cfg.packages.get(name) {
+				let version = versions.get(name).map(String::as_str);
+				linker::check_version(name, pkg, cfg, found, &state, version)?;
+			}
+		}
+		Ok(with_versions(order, &versions))
+	}
+
+	/// The packages to remove, each before the packages it depends on.
+	fn remove_list(&self, packages: &[String]) -> Result<Vec<String>> {
+		let (cfg, state) = self.load_config_and_state()?;
+		let (names, versions) =
+			split_versions(&self.resolve_package_list(packages, cfg, &state));
+		Ok(with_versions(
+			depends::remove_order(cfg, &names)?,
+			&versions,
+		))
+	}
+
+	fn handle_use_profile(&self, name: &str) -> Result<()> {
+		let (cfg, mut st) = self.load_config_and_state()?;
+		if name != "default" && !cfg.profiles.contains_key(name) {
+			warn!("profile '{name}' is not defined in the config");
+		}
+		if self.dry_run {
+			println!("[dry-run] set active profile to {name}");
+			return Ok(());
+		}
+		st.active_profile = Some(name.to_string());
+		state::save_state(&state::default_state_path(), &st)?;
+		generation::record("use-profile", &st, &ownership::load()?)?;
+		println!("active profile set to {name}");
+		Ok(())
+	}
+
+	fn handle_vsc(
+		&self,
+		package: &str,
+		version: &str,
+		no_apply: bool,
+	) -> Result<()> {
+		let (cfg, st) = self.load_config_and_state()?;
+		let pkg = cfg.packages.get(package).cloned().unwrap_or_else(|| {
+			warn!("package '{package}' is not defined in the config");
+			Package::default()
+		});
+		linker::check_version(
+			package,
+			&pkg,
+			cfg,
+			self.versions(cfg)?,
+			&st,
+			Some(version),
+		)?;
+		let mut pinned = st.clone();
+		pinned
+			.pinned_versions
+			.insert(package.to_string(), version.to_string());
+		let linked =
+			ownership::load()?
+				.packages
+				.get(package)
+				.is_some_and(|owned| {
+					!owned.links.is_empty() || !owned.files.is_empty()
+				});
+		if !no_apply && linked && cfg.packages.contains_key(package) {
+			return self.apply_pin(package, &pkg, cfg, &st, &pinned);
+		}
+		if self.dry_run {
+			println!("[dry-run] pin {package} -> {version}");
+			return Ok(());
+		}
+		state::save_state(&state::default_state_path(), &pinned)?;
+		println!("pinned {package} -> {version}");
+		Ok(())
+	}
+
+	/// Moves the links of a linked package to the version pinned in `new` in
+	/// one transaction, records the pin and reports the links that changed.
+	fn apply_pin(
+		&self,
+		name: &str,
+		pkg: &Package,
+		cfg: &Config,
+		old: &State,
+		new: &State,
+	) -> Result<()> {
+		let version = &new.pinned_versions[name];
+		let versions = self.versions(cfg)?;
+		linker::check_policy(name, pkg, cfg, versions, new, None)?;
+		self.run_hook(Hook::PreLink, name, pkg, cfg, new, None)?;
+		let mut tx = Transaction::begin(self.dry_run)?;
+		tx.set_package(Some(name));
+		let before = tx.ownership().packages.get(name).cloned();
+		let result = linker::switch_version(
+			name,
+			pkg,
+			cfg,
+			versions,
+			(old, new),
+			self.conflict_strategy,
+			&mut tx,
+		);
+		if let Err(e) = result {
+			tx.rollback();
+			bail!(
+				"switching {name} to {version} failed, all changes rolled \
+				 back: {e}"
+			);
+		}
+		if self.dry_run {
+			tx.commit()?;
+			println!("[dry-run] pin {name} -> {version}");
+			return Ok(());
+		}
+		let owned = tx.ownership().clone();
+		tx.commit()?;
+		state::save_state(&state::default_state_path(), new)?;
+		generation::record("vsc", new, &owned)?;
+		println!("pinned {name} -> {version}");
+		print_changed_links(
+			&before.unwrap_or_default(),
+			&owned.packages.get(name).cloned().unwrap_or_default(),
+		);
+		// The links stay switched even if the hook fails.
+		self.run_hook(Hook::PostLink, name, pkg, cfg, new, None)?;
+		Ok(())
+	}
+
+	fn handle_vsc_list(&self, package: Option<&str>) -> Result<()> {
+		let (cfg, state) = self.load_config_and_state()?;
+		let versions = self.versions(cfg)?;
+		let owned = ownership::load()?;
+		let default = Package::default();
+		let names: Vec<&str> = match package {
+			Some(name) => vec![name],
+			None => cfg.packages.keys().map(String::as_str).collect(),
+		};
+		let mut listed = false;
+		for name in names {
+			let pkg = cfg.packages.get(name).unwrap_or(&default);
+			let Some(found) = versions.of(name, pkg) else {
+				if package.is_some() {
+					bail!(
+						"no versions of '{name}' found in {} (check \
+						 vsc.template)",
+						linker::source_root(cfg).display()
+					);
+				}
+				continue;
+			};
+			// A spec such as `latest` is shown next to the version it picks.
+			let mark = |label: &str, spec: Option<&String>| {
+				let spec = spec?;
+				let version =
+					vsc::spec::resolve(spec, found, &cfg.vsc.aliases)?;
+				Some(if version == spec {
+					(version, label.to_string())
+				} else {
+					(version, format!("{label} as {spec}"))
+				})
+			};
+			let pinned = mark("pinned", state.pinned_versions.get(name));
+			let default_version = mark(
+				"default",
+				pkg.default_version
+					.as_ref()
+					.or(cfg.vsc.default_version.as_ref()),
+			);
+			let active = linker::linked_version(name, found, &owned)
+				.map(|version| (version, "active".to_string()));
+			println!("{}", name.bold());
+			for version in vsc::spec::ordered(found) {
+				let marks: Vec<&str> = [&pinned, &default_version, &active]
+					.into_iter()
+					.flatten()
+					.filter(|(marked, _)| *marked == version)
+					.map(|(_, label)| label.as_str())
+					.collect();
+				if marks.is_empty() {
+					println!("  {version}");
+				} else {
+					println!("  {version} ({})", marks.join(", "));
+				}
+			}
+			listed = true;
+		}
+		if !listed {
+			println!(
+				"No versioned packages found in {}",
+				linker::source_root(cfg).display()
+			);
+		}
+		Ok(())
+	}
+
+	fn handle_cache(&self, command: &CacheCommand) -> Result<()> {
+		let path = vsc::cache::cache_path();
+		match command {
+			CacheCommand::Show => {
+				let cache = vsc::cache::load_from(&path);
+				if cache.entries.is_empty() {
+					println!(
+						"The version cache at {} is empty",
+						path.display()
+					);
+				}
+				for entry in &cache.entries {
+					let packages = &entry.versions.packages;
+					println!(
+						"{} ({}): {} package(s), {} version(s), {}",
+						entry.source_dir.display(),
+						entry.template,
+						packages.len(),
+						packages.values().map(BTreeMap::len).sum::<usize>(),
+						if entry.is_fresh() { "fresh" } else { "stale" }
+					);
+				}
+			}
+			CacheCommand::Clear if self.dry_run => {
+				println!("[dry-run] delete {}", path.display());
+			}
+			CacheCommand::Clear => {
+				if vsc::cache::clear(&path)? {
+					println!("Cleared {}", path.display());
+				} else {
+					println!("The version cache is already empty");
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn handle_backups(&self, command: &BackupsCommand) -> Result<()> {
+		match command {
+			BackupsCommand::List => {
+				let entries = backup::list()?;
+				if entries.is_empty() {
+					println!(
+						"No backups in {}",
+						backup::backups_dir().display()
+					);
+				}
+				for entry in &entries {
+					let created = entry.created().map_or_else(
+						|| entry.created_at.clone(),
+						|t| t.format("%Y-%m-%d %H:%M:%S").to_string(),
+					);
+					println!(
+						"{}  {}  {:<12} {}",
+						entry.id.bold(),
+						created,
+						entry.package.as_deref().unwrap_or("-"),
+						entry.original.display()
+					);
+				}
+				Ok(())
+			}
+			BackupsCommand::Restore { id, force } => {
+				self.restore_backup(id, *force)
+			}
+			BackupsCommand::Prune { older_than } => {
+				let Some(cutoff) =
+					chrono::Local::now().checked_sub_signed(*older_than)
+				else {
+					bail!("--older-than is out of range");
+				};
+				let mut pruned = 0;
+				for entry in backup::list()? {
+					if entry.created().is_none_or(|t| t >= cutoff) {
+						continue;
+					}
+					if self.dry_run {
+						println!("[dry-run] delete backup {}", entry.id);
+					} else {
+						backup::delete(&entry)?;
+						println!(
+							"deleted backup {} of {}",
+							entry.id,
+							entry.original.display()
+						);
+					}
+					pruned += 1;
+				}
+				if !self.dry_run {
+					println!("pruned {pruned} backups");
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn restore_backup(&self, id: &str, force: bool) -> Result<()> {
+		let entry = backup::load(id)?;
+		let original = &entry.original;
+		let mut tx = Transaction::begin(self.dry_run)?;
+		let result = (|| {
+			match std::fs::symlink_metadata(original) {
+				Err(_) => {
+					if let Some(parent) = original.parent() {
+						tx.create_dir_all(parent)?;
+					}
+				}
+				Ok(meta) if meta.file_type().is_symlink() => {
+					tx.remove_symlink(original)?;
+				}
+				Ok(_) if force => tx.backup(original)?,
+				Ok(_) => {
+					return Err(linker::LinkError::Conflict(format!(
+						"{} exists; pass --force to back it up and restore \
+						 {id} in its place",
+						original.display()
+					)));
+				}
+			}
+			tx.rename(&entry.data(), original)
+		})();
+		if let Err(e) = result {
+			tx.rollback();
+			bail!("restoring backup {id} failed: {e}");
+		}
+		tx.commit()?;
+		if self.dry_run {
+			return Ok(());
+		}
+		backup::delete(&entry)?;
+		println!("restored {} from backup {id}", original.display());
+		Ok(())
+	}
+
+	fn handle_generations() -> Result<()> {
+		let generations = generation::list()?;
+		let Some(current) = generations.last().map(|g| g.number) else {
+			println!("No generations recorded yet");
+			return Ok(());
+		};
+		for generation in &generations {
+			let created_at =
+				chrono::DateTime::parse_from_rfc3339(&generation.created_at)
+					.map_or_else(
+						|_| generation.created_at.clone(),
+						|t| t.format("%Y-%m-%d %H:%M:%S").to_string(),
+					);
+			let line = format!(
+				"{:>4}  {}  {:<12} profile={} links={} files={}",
+				generation.number,
+				created_at,
+				generation.command,
+				generation.active_profile.as_deref().unwrap_or("default"),
+				generation.links.len(),
+				generation.files.len(),
+			);
+			if generation.number == current {
+				println!("{}  {}", line.bold(), "(current)".green());
+			} else {
+				println!("{line}");
+			}
 		}
 		Ok(())
 	}
+
+	fn handle_rollback(&self, number: Option<u64>) -> Result<()> {
+		let (cfg, mut st) = self.load_config_and_state()?;
+		let generations = generation::list()?;
+		if generations.is_empty() {
+			bail!(generation::GenerationError::NoPrevious);
+		}
+		let target = match number {
+			Some(n) => generation::load(n)?,
+			None => generations
+				.iter()
+				.rev()
+				.nth(1)
+				.cloned()
+				.ok_or(generation::GenerationError::NoPrevious)?,
+		};
+		for record in &target.links {
+			let package = record.package.as_deref().unwrap_or("unknown");
+			linker::check_target(package, &record.link, cfg)?;
+		}
+		for record in &target.files {
+			linker::check_target(&record.package, &record.path, cfg)?;
+		}
+
+		let mut tx = Transaction::begin(self.dry_run)?;
+		if let Err(e) = generation::reconcile(
+			&target,
+			self.conflict_strategy
+				.or(cfg.global.conflict_strategy)
+				.unwrap_or_default(),
+			&mut tx,
+		) {
+			tx.rollback();
+			bail!(
+				"rollback to generation {} failed, all changes rolled back: \
+				 {e}",
+				target.number
+			);
+		}
+		let owned = tx.ownership().clone();
+		tx.commit()?;
+		if self.dry_run {
+			println!("[dry-run] restore generation {}", target.number);
+			return Ok(());
+		}
+
+		st.active_profile.clone_from(&target.active_profile);
+		st.pinned_versions =
+			target.pinned_versions.clone().into_iter().collect();
+		state::save_state(&state::default_state_path(), &st)?;
+		generation::record(
+			&format!("rollback {}", target.number),
+			&st,
+			&owned,
+		)?;
+		println!("rolled back to generation {}", target.number);
+		Ok(())
+	}
+}
+
+/// Selects the packages of the effective profile that apply to this OS.
+fn select_packages<'a>(
+	cfg: &'a Config,
+	profile: Option<&str>,
+	state: &State,
+) -> Vec<(&'a str, &'a Package)> {
+	let os = current_os();
+	profile_packages(cfg, profile, state)
+		.into_iter()
+		.filter(|(_, pkg)| pkg.os.is_empty() || pkg.os.iter().any(|v| v == os))
+		.collect()
+}
+
+/// The `--profile` override, then the active profile from the state file,
+/// then `default`.
+fn effective_profile<'a>(
+	profile: Option<&'a str>,
+	state: &'a State,
+) -> &'a str {
+	profile
+		.or(state.active_profile.as_deref())
+		.unwrap_or("default")
+}
+
+/// Selects the packages of the effective profile. If that profile is not
+/// defined, all packages are selected.
+fn profile_packages<'a>(
+	cfg: &'a Config,
+	profile: Option<&str>,
+	state: &State,
+) -> Vec<(&'a str, &'a Package)> {
+	let active_tags: Option<HashSet<&str>> = cfg
+		.profiles
+		.get(effective_profile(profile, state))
+		.map(|tags| tags.iter().map(String::as_str).collect());
+	cfg.packages
+		.iter()
+		.filter(|(_, pkg)| {
+			active_tags.as_ref().is_none_or(|set| {
+				pkg.tags.iter().any(|t| set.contains(t.as_str()))
+			})
+		})
+		.map(|(n, p)| (n.as_str(), p))
+		.collect()
+}
+
+fn colored_status(kind: LinkStatusKind) -> colored::ColoredString {
+	let label = kind.as_str();
+	match kind {
+		LinkStatusKind::Ok => label.green(),
+		LinkStatusKind::Missing |
+		LinkStatusKind::PartiallyLinked |
+		LinkStatusKind::Unmanaged |
+		LinkStatusKind::SourceUpdated => label.yellow(),
+		LinkStatusKind::Dangling |
+		LinkStatusKind::ModifiedLocally |
+		LinkStatusKind::WrongTarget |
+		LinkStatusKind::NotSymlink |
+		LinkStatusKind::SourceMissing => label.red(),
+		LinkStatusKind::OsFilteredOut => label.dimmed(),
+	}
+}
+
+/// Lists the source files changed by adopting targets, for review in git.
+fn print_adopted(adopted: &[linker::AdoptedFile], dry_run: bool) {
+	if adopted.is_empty() {
+		return;
+	}
+	let prefix = if dry_run { "[dry-run] " } else { "" };
+	println!(
+		"{prefix}adopted into the repo (review with `git diff` before \
+		 committing):"
+	);
+	for file in adopted {
+		let label = if file.overwritten {
+			"overwritten".yellow()
+		} else {
+			"new".green()
+		};
+		println!("  {label:<12} {}", file.source.display());
+	}
 }
 
-// impl App {
-// 	pub fn new(cli: &Cli) -> Self {
-// 		let config_path = cli
-// 			.config
-// 			.clone()
-// 			.unwrap_or_else(config::loader::default_config_path);
-// 		Self {
-// 			config_path,
-// 			dry_run: cli.dry_run,
-// 			profile_override: cli.profile.clone(),
-// 		}
-// 	}
-
-// 	pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
-// 		match command {
-// 			Commands::List => self.handle_list(),
-// 			Commands::Status { json } => self.handle_status(*json),
-// 			Commands::Link { packages } => self.handle_link(packages),
-// 			Commands::Remove { packages } => self.handle_remove(packages),
-// 			Commands::UseProfile { name } => self.handle_use_profile(name),
-// 			Commands::Vsc { package, version } => self.handle_vsc(package, version),
-// 			Commands::Init { repo, dest } => self.handle_init(repo, dest),
-// 			Commands::Interactive => self.handle_interactive(),
-// 			Commands::Start => self.handle_start(),
-// 			Commands::Stop => self.handle_stop(),
-// 		}
-// 	}
-
-// 	fn load_config_and_state(
-// 		&self,
-// 	) -> Result<(config::entity::Config, state::State), Box<dyn
-// std::error::Error>> { 		let cfg =
-// config::loader::load_config(&self.config_path)?; 		let state = match
-// state::load_state(&state::default_state_path()) { 			Ok(s) => s,
-// 			Err(e) => {
-// 				tracing::warn!("Failed to load state file: {}", e);
-// 				if self.dry_run {
-// 					tracing::info!("Using default state for dry run");
-// 				} else {
-// 					tracing::warn!("Using default state, previous settings may be lost");
-// 				}
-// 				state::State::default()
-// 			}
-// 		};
-// 		Ok((cfg, state))
-// 	}
-
-// 	fn handle_list(&self) -> Result<(), Box<dyn std::error::Error>> {
-// 		let (cfg, _) = self.load_config_and_state()?;
-// 		if cfg.packages.is_empty() {
-// 			println!("No packages defined in {}", self.config_path.display());
-// 		} else {
-// 			for name in cfg.packages.keys() {
-// 				println!("{}", name);
-// 			}
-// 		}
-// 		Ok(())
-// 	}
-
-// 	fn handle_status(&self, json: bool) -> Result<(), Box<dyn
-// std::error::Error>> { 		let (cfg, state) = self.load_config_and_state()?;
-// 		info!("Loaded {} packages", cfg.packages.len());
-// 		let filtered = select_packages(&cfg, self.profile_override.as_deref(),
-// Some(&state)); 		if json {
-// 			self.output_status_json(&filtered, &cfg, &state)?;
-// 		} else {
-// 			self.output_status_text(&filtered, &cfg, &state)?;
-// 		}
-// 		Ok(())
-// 	}
-
-// 	fn output_status_text(
-// 		&self,
-// 		pkgs: &[(&str, &config::entity::Package)],
-// 		cfg: &config::entity::Config,
-// 		st: &state::State,
-// 	) -> Result<(), Box<dyn std::error::Error>> {
-// 		for (name, pkg) in pkgs.iter() {
-// 			match linker::status_package(name, pkg, cfg, st) {
-// 				Ok(stat) => {
-// 					let status_col = match stat.kind {
-// 						linker::LinkStatusKind::Ok => "ok".green(),
-// 						linker::LinkStatusKind::BrokenSymlink => "broken".red(),
-// 						linker::LinkStatusKind::NotSymlink => "not-symlink".yellow(),
-// 						linker::LinkStatusKind::Missing => "missing".dimmed(),
-// 					};
-// 					println!("{}: {} -> {}", name.bold(), status_col,
-// stat.target.display()); 				}
-// 				Err(e) => eprintln!("{}: {}", name.bold(), format!("error: {e}").red()),
-// 			}
-// 		}
-// 		Ok(())
-// 	}
-
-// 	fn output_status_json(
-// 		&self,
-// 		pkgs: &[(&str, &config::entity::Package)],
-// 		cfg: &config::entity::Config,
-// 		st: &state::State,
-// 	) -> Result<(), Box<dyn std::error::Error>> {
-// 		#[derive(serde::Serialize)]
-// 		struct Item<'a> {
-// 			name: &'a str,
-// 			status: &'a str,
-// 			target: String,
-// 		}
-// 		let mut out = Vec::new();
-// 		for (name, pkg) in pkgs.iter() {
-// 			match linker::status_package(name, pkg, cfg, st) {
-// 				Ok(stat) => {
-// 					let status_str = match stat.kind {
-// 						linker::LinkStatusKind::Ok => "ok",
-// 						linker::LinkStatusKind::BrokenSymlink => "broken",
-// 						linker::LinkStatusKind::NotSymlink => "not-symlink",
-// 						linker::LinkStatusKind::Missing => "missing",
-// 					};
-// 					out.push(Item { name, status: status_str, target:
-// stat.target.display().to_string() }); 				}
-// 				Err(_) => out.push(Item { name, status: "error", target: String::new()
-// }), 			}
-// 		}
-// 		let json = serde_json::to_string_pretty(&out)?;
-// 		println!("{}", json);
-// 		Ok(())
-// 	}
-
-// 	fn process_packages<F>(
-// 		&self,
-// 		packages: &[String],
-// 		action_name: &str,
-// 		action: F,
-// 	) -> Result<(), Box<dyn std::error::Error>>
-// 	where
-// 		F: Fn(
-// 			&str,
-// 			&config::entity::Package,
-// 			&config::entity::Config,
-// 			&state::State,
-// 			Option<&str>,
-// 		) -> Result<(), crate::error::Error>,
-// 	{
-// 		let (cfg, state) = self.load_config_and_state()?;
-// 		let selected = self.resolve_package_list(packages, &cfg, &state);
-// 		let bar = if selected.len() > 1 {
-// indicatif::ProgressBar::new(selected.len() as u64) } else {
-// indicatif::ProgressBar::hidden() }; 		bar.set_style(
-// 			indicatif::ProgressStyle::default_bar()
-// 				.template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}]
-// {pos}/{len} ({eta}) {msg}") 				.unwrap()
-// 				.progress_chars("#>-"),
-// 		);
-// 		for raw in selected {
-// 			let (name, ver_override) = parse_pkg_and_version(&raw);
-// 			bar.set_message(name.clone());
-// 			match cfg.packages.get(&name) {
-// 				Some(pkg) => {
-// 					if let Err(e) = action(&name, pkg, &cfg, &state,
-// ver_override.as_deref()) { 						error!("{action_name} {name} failed: {e}");
-// 					}
-// 				}
-// 				None => eprintln!("unknown package: {name}"),
-// 			}
-// 			bar.inc(1);
-// 		}
-// 		bar.finish_with_message("Done");
-// 		Ok(())
-// 	}
-
-// 	fn handle_link(&self, packages: &[String]) -> Result<(), Box<dyn
-// std::error::Error>> { 		self.process_packages(packages, "link", |name, pkg,
-// cfg, state, ver| { 			linker::link_package(name, pkg, cfg, state, ver,
-// self.dry_run) 		})
-// 	}
-
-// 	fn handle_remove(&self, packages: &[String]) -> Result<(), Box<dyn
-// std::error::Error>> { 		self.process_packages(packages, "remove", |name, pkg,
-// cfg, state, ver| { 			linker::remove_package(name, pkg, cfg, state, ver,
-// self.dry_run) 		})
-// 	}
-
-// 	fn resolve_package_list(
-// 		&self,
-// 		packages: &[String],
-// 		cfg: &config::entity::Config,
-// 		state: &state::State,
-// 	) -> Vec<String> {
-// 		if packages.is_empty() {
-// 			select_packages(cfg, self.profile_override.as_deref(), Some(state))
-// 				.into_iter()
-// 				.map(|(n, _)| n.to_string())
-// 				.collect()
-// 		} else {
-// 			packages.to_vec()
-// 		}
-// 	}
-
-// 	fn handle_use_profile(&self, name: &str) -> Result<(), Box<dyn
-// std::error::Error>> { 		let mut st =
-// state::load_state(&state::default_state_path()).unwrap_or_default();
-// 		st.active_profile = Some(name.to_string());
-// 		state::save_state(&state::default_state_path(), &st)?;
-// 		println!("active profile set to {name}");
-// 		Ok(())
-// 	}
-
-// 	fn handle_vsc(&self, package: &str, version: &str) -> Result<(), Box<dyn
-// std::error::Error>> { 		let mut st =
-// state::load_state(&state::default_state_path()).unwrap_or_default();
-// 		st.pinned_versions.insert(package.to_string(), version.to_string());
-// 		state::save_state(&state::default_state_path(), &st)?;
-// 		println!("pinned {package} -> {version}");
-// 		Ok(())
-// 	}
-
-// 	fn handle_init(
-// 		&self,
-// 		repo: &Option<String>,
-// 		dest: &Option<PathBuf>,
-// 	) -> Result<(), Box<dyn std::error::Error>> {
-// 		setup::init(repo.clone(), dest.clone())?;
-// 		Ok(())
-// 	}
-
-// 	fn handle_interactive(&self) -> Result<(), Box<dyn std::error::Error>> {
-// 		repl::run()?;
-// 		Ok(())
-// 	}
-
-// 	fn handle_start(&self) -> Result<(), Box<dyn std::error::Error>> {
-// 		let (cfg, _state) = self.load_config_and_state()?;
-// 		// This blocks (MVP). User can Ctrl+C.
-// 		monitor::run_foreground(&cfg)?;
-// 		Ok(())
-// 	}
-
-// 	fn handle_stop(&self) -> Result<(), Box<dyn std::error::Error>> {
-// 		monitor::stop()?;
-// 		Ok(())
-// 	}
-// }
-
-// fn select_packages<'a>(
-// 	cfg: &'a config::entity::Config,
-// 	profile: Option<&str>,
-// 	state: Option<&state::State>,
-// ) -> Vec<(&'a str, &'a config::entity::Package)> {
-// 	let effective_profile = profile
-// 		.or_else(|| state.and_then(|s| s.active_profile.as_deref()))
-// 		.unwrap_or("default");
-// 	let active_tags: Option<&Vec<String>> = cfg.profiles.get(effective_profile);
-// 	let os = current_os();
-// 	if let Some(tags) = active_tags {
-// 		let set: std::collections::HashSet<&str> = tags.iter().map(|s|
-// s.as_str()).collect(); 		cfg.packages
-// 			.iter()
-// 			.filter(|(_n, pkg)| pkg.tags.iter().any(|t| set.contains(t.as_str())))
-// 			.filter(|(_n, pkg)| pkg.os.is_empty() || pkg.os.iter().any(|v| v == os))
-// 			.map(|(n, p)| (n.as_str(), p))
-// 			.collect()
-// 	} else {
-// 		cfg.packages
-// 			.iter()
-// 			.filter(|(_n, pkg)| pkg.os.is_empty() || pkg.os.iter().any(|v| v == os))
-// 			.map(|(n, p)| (n.as_str(), p))
-// 			.collect()
-// 	}
-// }
-
-// fn parse_pkg_and_version(input: &str) -> (String, Option<String>) {
-// 	if let Some((name, ver)) = input.split_once('@') { (name.to_string(),
-// Some(ver.to_string())) } else { (input.to_string(), None) } }
-
-// fn current_os() -> &'static str {
-// 	if cfg!(target_os = "linux") { "linux" } else if cfg!(target_os = "macos") {
-// "macos" } else { "other" } }
+/// Lists the paths of a package that were placed, re-pointed or removed
+/// between `before` and `after`.
+fn print_changed_links(before: &ownership::Owned, after: &ownership::Owned) {
+	let sources = |owned: &ownership::Owned| -> BTreeMap<PathBuf, PathBuf> {
+		owned
+			.links
+			.clone()
+			.into_iter()
+			.chain(
+				owned
+					.files
+					.iter()
+					.map(|(path, file)| (path.clone(), file.source.clone())),
+			)
+			.collect()
+	};
+	let (before, after) = (sources(before), sources(after));
+	let mut changes = Vec::new();
+	for (path, to) in &after {
+		match before.get(path) {
+			Some(from) if from == to => {}
+			Some(from) => changes.push(format!(
+				"  {}: {} -> {}",
+				path.display(),
+				from.display(),
+				to.display()
+			)),
+			None => changes.push(format!(
+				"  {}: {} {}",
+				path.display(),
+				"new".green(),
+				to.display()
+			)),
+		}
+	}
+	for path in before.keys().filter(|path| !after.contains_key(*path)) {
+		changes.push(format!("  {}: {}", path.display(), "removed".red()));
+	}
+	if changes.is_empty() {
+		println!("no links changed");
+	} else {
+		println!("{} link(s) changed:", changes.len());
+		for change in changes {
+			println!("{change}");
+		}
+	}
+}
+
+/// Splits `name@version` entries into the names and their pinned versions.
+fn split_versions(raw: &[String]) -> (Vec<String>, BTreeMap<String, String>) {
+	let mut names = Vec::new();
+	let mut versions = BTreeMap::new();
+	for raw in raw {
+		let (name, version) = parse_pkg_and_version(raw);
+		if let Some(version) = version {
+			versions.insert(name.clone(), version);
+		}
+		names.push(name);
+	}
+	(names, versions)
+}
+
+/// Puts the versions taken off by [`split_versions`] back on.
+fn with_versions(
+	names: Vec<String>,
+	versions: &BTreeMap<String, String>,
+) -> Vec<String> {
+	names
+		.into_iter()
+		.map(|name| match versions.get(&name) {
+			Some(version) => format!("{name}@{version}"),
+			None => name,
+		})
+		.collect()
+}
+
+/// Splits `name@version` into its parts.
+fn parse_pkg_and_version(input: &str) -> (String, Option<String>) {
+	input.split_once('@').map_or_else(
+		|| (input.to_string(), None),
+		|(name, ver)| (name.to_string(), Some(ver.to_string())),
+	)
+}
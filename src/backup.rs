@@ -0,0 +1,234 @@
+//! The managed backup store.
+//!
+//! Files displaced by the `backup` conflict strategy are moved into
+//! `$XDG_DATA_HOME/rinkle/backups` (override with `RINKLE_DATA_DIR`). Each
+//! entry is a directory named after its id holding a `manifest.toml`, which
+//! records where the file came from, and the file itself as `data`.
+
+use std::{
+	fs,
+	io,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use chrono::{
+	DateTime,
+	Local,
+	TimeDelta,
+};
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use thiserror::Error;
+use tracing::warn;
+
+const MANIFEST: &str = "manifest.toml";
+const DATA: &str = "data";
+
+/// Errors that can occur while using the backup store.
+#[derive(Debug, Error)]
+pub enum BackupError {
+	/// No backup with the given id exists.
+	#[error("backup '{0}' not found")]
+	NotFound(String),
+	/// An I/O error occurred while reading or writing the store.
+	#[error("io error: {0}")]
+	Io(#[from] io::Error),
+	/// A manifest could not be parsed.
+	#[error("toml parse error: {0}")]
+	TomlDe(#[from] toml::de::Error),
+	/// A manifest could not be serialized.
+	#[error("toml serialize error: {0}")]
+	TomlSer(#[from] toml::ser::Error),
+}
+
+/// The manifest of one backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+	/// Unique id, `YYYYMMDD-HHMMSS-N`.
+	pub id:         String,
+	/// Where the file was before it was backed up.
+	pub original:   PathBuf,
+	/// The package being linked when the file was displaced, if any.
+	pub package:    Option<String>,
+	/// RFC 3339 timestamp of the backup.
+	pub created_at: String,
+}
+
+impl BackupEntry {
+	/// Creates the manifest for a new backup of `original` with a fresh id.
+	pub fn new(original: &Path, package: Option<String>) -> Self {
+		let now = Local::now();
+		let stamp = now.format("%Y%m%d-%H%M%S");
+		let root = backups_dir();
+		let mut n = 1;
+		while root.join(format!("{stamp}-{n}")).exists() {
+			n += 1;
+		}
+		Self {
+			id: format!("{stamp}-{n}"),
+			original: original.to_path_buf(),
+			package,
+			created_at: now.to_rfc3339(),
+		}
+	}
+
+	/// The directory holding this entry.
+	pub fn dir(&self) -> PathBuf {
+		backups_dir().join(&self.id)
+	}
+
+	/// The backed up file or directory.
+	pub fn data(&self) -> PathBuf {
+		self.dir().join(DATA)
+	}
+
+	/// When the backup was taken, if the timestamp can be parsed.
+	pub fn created(&self) -> Option<DateTime<Local>> {
+		DateTime::parse_from_rfc3339(&self.created_at)
+			.ok()
+			.map(|t| t.with_timezone(&Local))
+	}
+}
+
+/// Returns rinkle's data directory.
+pub fn data_dir() -> PathBuf {
+	// Allow tests / callers to override the location for isolation.
+	if let Ok(custom) = std::env::var("RINKLE_DATA_DIR") {
+		return PathBuf::from(custom);
+	}
+	dirs::data_dir()
+		.unwrap_or_else(std::env::temp_dir)
+		.join("rinkle")
+}
+
+/// Returns the directory of the backup store.
+pub fn backups_dir() -> PathBuf {
+	data_dir().join("backups")
+}
+
+/// Moves `path` into the store under `entry`.
+pub fn store(entry: &BackupEntry, path: &Path) -> Result<(), BackupError> {
+	let dir = entry.dir();
+	fs::create_dir_all(&dir)?;
+	fs::write(dir.join(MANIFEST), toml::to_string_pretty(entry)?)?;
+	move_path(path, &entry.data())?;
+	Ok(())
+}
+
+/// Loads every backup, oldest first. Backups whose manifest cannot be read
+/// are skipped with a warning.
+pub fn list() -> Result<Vec<BackupEntry>, BackupError> {
+	let Ok(dirs) = fs::read_dir(backups_dir()) else {
+		return Ok(Vec::new());
+	};
+	let mut entries = Vec::new();
+	for dir in dirs {
+		let manifest = dir?.path().join(MANIFEST);
+		if !manifest.is_file() {
+			continue;
+		}
+		let entry = fs::read_to_string(&manifest)
+			.map_err(BackupError::from)
+			.and_then(|content| Ok(toml::from_str::<BackupEntry>(&content)?));
+		match entry {
+			Ok(entry) => entries.push(entry),
+			Err(e) => warn!("skipping backup {}: {e}", manifest.display()),
+		}
+	}
+	entries
+		.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+	Ok(entries)
+}
+
+/// Loads the backup with the given id.
+pub fn load(id: &str) -> Result<BackupEntry, BackupError> {
+	let manifest = backups_dir().join(id).join(MANIFEST);
+	if id.contains(['/', '\\']) || !manifest.is_file() {
+		return Err(BackupError::NotFound(id.to_string()));
+	}
+	Ok(toml::from_str(&fs::read_to_string(manifest)?)?)
+}
+
+/// Deletes a backup from the store.
+pub fn delete(entry: &BackupEntry) -> Result<(), BackupError> {
+	fs::remove_dir_all(entry.dir())?;
+	Ok(())
+}
+
+/// Moves `from` to `to`, falling back to copy and delete when they are on
+/// different filesystems.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+	match fs::rename(from, to) {
+		Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+			copy_recursive(from, to)?;
+			if fs::symlink_metadata(from)?.is_dir() {
+				fs::remove_dir_all(from)
+			} else {
+				fs::remove_file(from)
+			}
+		}
+		result => result,
+	}
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+	let meta = fs::symlink_metadata(from)?;
+	if meta.file_type().is_symlink() {
+		crate::linker::create_symlink(&fs::read_link(from)?, to)
+	} else if meta.is_dir() {
+		fs::create_dir(to)?;
+		for entry in fs::read_dir(from)? {
+			let entry = entry?;
+			copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+		}
+		Ok(())
+	} else {
+		fs::copy(from, to).map(|_| ())
+	}
+}
+
+/// Parses an age such as `30d`, `12h`, `2w` or `45m`.
+pub fn parse_age(s: &str) -> Result<TimeDelta, String> {
+	let s = s.trim();
+	let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let (amount, unit) = s.split_at(split);
+	let amount: i64 = amount
+		.parse()
+		.map_err(|e| format!("invalid age '{s}', expected e.g. 30d: {e}"))?;
+	let age = match unit {
+		"m" => TimeDelta::try_minutes(amount),
+		"h" => TimeDelta::try_hours(amount),
+		"d" | "" => TimeDelta::try_days(amount),
+		"w" => TimeDelta::try_weeks(amount),
+		_ => {
+			return Err(format!(
+				"invalid unit '{unit}' in '{s}', use m, h, d or w"
+			));
+		}
+	};
+	age.ok_or_else(|| format!("age '{s}' is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_age_units() {
+		assert_eq!(parse_age("30d"), Ok(TimeDelta::days(30)));
+		assert_eq!(parse_age("12h"), Ok(TimeDelta::hours(12)));
+		assert_eq!(parse_age("2w"), Ok(TimeDelta::weeks(2)));
+		assert_eq!(parse_age("7"), Ok(TimeDelta::days(7)));
+		assert!(parse_age("d").is_err());
+		assert!(parse_age("3y").is_err());
+		assert_eq!(
+			parse_age("9999999999999w"),
+			Err("age '9999999999999w' is out of range".to_string())
+		);
+	}
+}
@@ -1,10 +1,16 @@
 use std::path::PathBuf;
 
+use chrono::TimeDelta;
 use clap::{
 	Parser,
 	Subcommand,
 };
 
+use crate::{
+	backup,
+	config::ConflictStrategy,
+};
+
 /// A symlink farm manager for your dotfiles.
 ///
 /// Rinkle helps manage distinct sets of software and/or data located in
@@ -28,6 +34,10 @@ pub struct Cli {
 	#[arg(long, global = true, default_value_t = false)]
 	pub dry_run: bool,
 
+	/// Do not run package hooks.
+	#[arg(long, global = true, default_value_t = false)]
+	pub no_hooks: bool,
+
 	/// Override the active profile for a single command.
 	///
 	/// This is useful for temporarily linking or checking the status of a
@@ -35,6 +45,13 @@ pub struct Cli {
 	#[arg(long, global = true)]
 	pub profile: Option<String>,
 
+	/// Override how existing files at a target are handled.
+	///
+	/// Takes precedence over the package's and the global
+	/// `conflict_strategy`.
+	#[arg(long, global = true, value_enum)]
+	pub conflict_strategy: Option<ConflictStrategy>,
+
 	/// The subcommand to execute.
 	#[command(subcommand)]
 	pub command: Commands,
@@ -46,7 +63,11 @@ pub enum Commands {
 	/// List all packages defined in the configuration file.
 	List,
 	/// Show the link status of packages for the active profile.
-	Status,
+	Status {
+		/// Print the status as JSON instead of colored text.
+		#[arg(long, default_value_t = false)]
+		json: bool,
+	},
 	/// Create symlinks for packages.
 	///
 	/// If no package names are provided, this command will link all packages
@@ -54,6 +75,10 @@ pub enum Commands {
 	Link {
 		/// The specific packages to link. Supports `package@version` syntax.
 		packages: Vec<String>,
+		/// Go ahead even if package targets collide or are nested inside
+		/// each other.
+		#[arg(long)]
+		force:    bool,
 	},
 	/// Remove symlinks for packages.
 	///
@@ -63,6 +88,20 @@ pub enum Commands {
 		/// The specific packages to remove.
 		packages: Vec<String>,
 	},
+	/// Move existing files at package targets into the repo and link them
+	/// back.
+	///
+	/// Like `link` with `--conflict-strategy adopt`: whatever is found at a
+	/// target replaces the corresponding source file. The overwritten source
+	/// files are listed so they can be reviewed with `git diff`.
+	Adopt {
+		/// The specific packages to adopt.
+		packages: Vec<String>,
+		/// Go ahead even if package targets collide or are nested inside
+		/// each other.
+		#[arg(long)]
+		force:    bool,
+	},
 	/// Set the active profile.
 	///
 	/// The active profile determines which packages are processed by default
@@ -74,12 +113,68 @@ pub enum Commands {
 	/// Pin a specific version for a version-controlled package.
 	///
 	/// This pins the version in the state file, so it will be used by default
-	/// in subsequent commands.
+	/// in subsequent commands. The version must have been found in the source
+	/// directory; `rk vsc list` shows those that were. If the package is
+	/// linked, its links are switched to the version right away.
+	#[command(
+		args_conflicts_with_subcommands = true,
+		subcommand_negates_reqs = true
+	)]
 	Vsc {
+		#[command(subcommand)]
+		command:  Option<VscCommand>,
 		/// The name of the package to pin.
-		package: String,
+		#[arg(required = true)]
+		package:  Option<String>,
 		/// The version to pin (e.g., "stable", "nightly", "v1.2.3").
-		version: String,
+		#[arg(required = true)]
+		version:  Option<String>,
+		/// Only record the pin; leave the links of the package as they are
+		/// until the next `rk link`.
+		#[arg(long)]
+		no_apply: bool,
+	},
+	/// List recorded generations.
+	///
+	/// A generation is recorded after every `link`, `adopt`, `remove`,
+	/// `prune`, `use-profile`, `vsc` and `rollback` that changes the managed
+	/// links, profile or pinned versions.
+	Generations,
+	/// Restore the links, active profile and pinned versions of a previous
+	/// generation.
+	///
+	/// The restored setup is recorded as a new generation, so a rollback can
+	/// itself be rolled back.
+	Rollback {
+		/// The generation to restore. Defaults to the one before the current.
+		generation: Option<u64>,
+	},
+	/// Remove links into the source directory that no package owns.
+	///
+	/// Finds links left behind by renamed or removed packages, and dangling
+	/// links into the repo, lists them and removes them after confirmation.
+	Prune {
+		/// Remove without asking.
+		#[arg(long, short)]
+		yes: bool,
+	},
+	/// Check the configuration and environment for problems.
+	///
+	/// Reports profile tags no package has, packages no profile selects,
+	/// missing sources, an invalid `vsc.template`, unknown `os` values,
+	/// paths that cannot be expanded, target directories that cannot hold
+	/// symlinks and files left behind by a dead monitor. Exits with code 1 if
+	/// any error is found.
+	Doctor,
+	/// Manage files moved aside by the `backup` conflict strategy.
+	Backups {
+		#[command(subcommand)]
+		command: BackupsCommand,
+	},
+	/// Inspect or clear the cache of discovered versions.
+	Cache {
+		#[command(subcommand)]
+		command: CacheCommand,
 	},
 	/// Initialize a new rinkle setup in the current directory.
 	///
@@ -105,3 +200,49 @@ pub enum Commands {
 	#[command(hide = true)]
 	Run,
 }
+
+/// Subcommands of `rk backups`.
+#[derive(Debug, Subcommand)]
+pub enum BackupsCommand {
+	/// List the backups in the store, oldest first.
+	List,
+	/// Move a backup back to its original location.
+	///
+	/// A symlink at the original location is replaced. Any other existing
+	/// file is only replaced with `--force`, after being backed up itself.
+	Restore {
+		/// The id of the backup, as shown by `rk backups list`.
+		id:    String,
+		/// Back up and replace an existing file at the original location.
+		#[arg(long, default_value_t = false)]
+		force: bool,
+	},
+	/// Delete backups older than the given age.
+	Prune {
+		/// Minimum age of the backups to delete, e.g. `30d`, `12h` or `2w`.
+		#[arg(long, value_parser = backup::parse_age)]
+		older_than: TimeDelta,
+	},
+}
+
+/// Subcommands of `rk vsc`.
+#[derive(Debug, Subcommand)]
+pub enum VscCommand {
+	/// List the versions found in the source directory.
+	///
+	/// Each version is marked as pinned, default or active (the one the
+	/// package's links point into).
+	List {
+		/// Only list the versions of this package.
+		package: Option<String>,
+	},
+}
+
+/// Subcommands of `rk cache`.
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+	/// Show the cached discovery results and whether they are still fresh.
+	Show,
+	/// Delete the cache. It is rebuilt by the next command that needs it.
+	Clear,
+}
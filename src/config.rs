@@ -1,5 +1,8 @@
 use std::{
-	collections::HashMap,
+	collections::{
+		BTreeMap,
+		HashMap,
+	},
 	path::PathBuf,
 	sync::OnceLock,
 };
@@ -7,59 +10,138 @@ use std::{
 use anyhow::Result;
 use directories_next::BaseDirs;
 use realme::prelude::*;
-use serde::Deserialize;
+use serde::{
+	Deserialize,
+	Serialize,
+};
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Global {
 	/// The root directory where the source dotfiles are located.
-	pub source_dir: Option<String>,
+	pub source_dir:        Option<String>,
 	/// The default directory where symlinks will be created.
-	pub target_dir: Option<String>,
+	pub target_dir:        Option<String>,
 	/// The default strategy to use when a symlink target already exists.
-	// #[serde(default = "default_conflict_strategy")]
-	// pub conflict_strategy: ConflictStrategy,
-	/// A list of glob patterns to ignore when linking. (Not yet implemented)
+	/// Falls back to [`ConflictStrategy::Backup`] when unset.
+	pub conflict_strategy: Option<ConflictStrategy>,
+	/// The default style of the symlinks created. Falls back to
+	/// [`LinkStyle::Absolute`] when unset.
+	pub link_style:        Option<LinkStyle>,
+	/// Gitignore-style patterns, relative to `source_dir`, for files that are
+	/// never linked.
 	#[serde(default)]
-	pub ignore:     Vec<String>,
+	pub ignore:            Vec<String>,
 }
 
 /// Represents the `[vsc]` (Version Selection Control) section of the config.
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Vsc {
 	/// A regex used to identify versioned packages from directory names.
-	/// It must contain a capture group named `version`.
+	/// It must contain a capture group named `version`, and may name the
+	/// package with a `package` group. Defaults to
+	/// [`DEFAULT_TEMPLATE`](crate::vsc::DEFAULT_TEMPLATE).
 	pub template:        Option<String>,
 	/// The default version to use for packages if not otherwise specified.
 	pub default_version: Option<String>,
+	/// Names for versions or ranges, e.g. `lts = "v0.9"`, usable wherever a
+	/// version is.
+	#[serde(default)]
+	pub aliases:         BTreeMap<String, String>,
+	/// Versions tried in order when the one asked for is not found.
+	#[serde(default)]
+	pub fallback:        Vec<String>,
+	/// Fail instead of falling back when the version asked for is not found.
+	#[serde(default)]
+	pub strict:          bool,
 }
 
-/// Represents the `[profiles]` section of the config.
+/// Represents the `[policy]` section: where rinkle may create links.
+///
+/// Every target and source is checked against it before a command touches the
+/// filesystem.
 #[derive(Debug, Clone, Deserialize, Default)]
-pub struct Profiles {
-	/// The default profile, a list of tags to activate when no other profile
-	/// is active.
+pub struct Policy {
+	/// Directories targets must lie inside. Any directory is fine when empty.
+	#[serde(default)]
+	pub allowed_roots:       Vec<String>,
+	/// Directories targets must never lie inside. Falls back to
+	/// [`DEFAULT_FORBIDDEN_ROOTS`] when unset.
+	pub forbidden_roots:     Option<Vec<String>>,
+	/// Whether targets may lie outside the home directory. Defaults to `true`.
+	pub allow_outside_home:  Option<bool>,
+	/// Whether a package `source` may leave `global.source_dir` through `..`.
 	#[serde(default)]
-	pub default: Vec<String>,
-	// Additional named profiles are captured by serde's flatten attribute in
-	// the `Config` struct.
+	pub allow_source_escape: bool,
 }
 
+/// System directories rinkle refuses to link into unless
+/// `policy.forbidden_roots` says otherwise.
+pub const DEFAULT_FORBIDDEN_ROOTS: &[&str] = &[
+	"/bin", "/boot", "/dev", "/etc", "/lib", "/proc", "/sbin", "/sys", "/usr",
+];
+
 /// Represents a single package defined under the `[packages]` section.
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Package {
 	/// Overrides the package's source path relative to `global.source_dir`.
-	pub source:          Option<String>,
+	pub source:            Option<String>,
 	/// Overrides the package's target path, making it an absolute path.
-	pub target:          Option<String>,
+	pub target:            Option<String>,
 	/// A list of operating systems this package should be applied on.
 	/// (e.g., "linux", "macos")
 	#[serde(default)]
-	pub os:              Vec<String>,
+	pub os:                Vec<String>,
 	/// A list of tags used to group this package into profiles.
 	#[serde(default)]
-	pub tags:            Vec<String>,
+	pub tags:              Vec<String>,
+	/// Packages linked before this one, and pulled in when it is linked.
+	#[serde(default)]
+	pub depends:           Vec<String>,
 	/// A package-specific default version.
-	pub default_version: Option<String>,
+	pub default_version:   Option<String>,
+	/// Overrides `vsc.fallback` for this package; `[]` disables it.
+	pub fallback:          Option<Vec<String>>,
+	/// How the package is linked into its target.
+	#[serde(default)]
+	pub mode:              LinkMode,
+	/// Explicit mappings placed instead of the whole source at `target`. The
+	/// same source may be mapped to several targets.
+	#[serde(default)]
+	pub files:             Vec<FileMapping>,
+	/// Whether the package is deployed as symlinks, copies or hard links.
+	#[serde(default)]
+	pub deploy:            Deploy,
+	/// Overrides `global.conflict_strategy` for this package.
+	pub conflict_strategy: Option<ConflictStrategy>,
+	/// Overrides `global.link_style` for this package.
+	pub link_style:        Option<LinkStyle>,
+	/// Gitignore-style patterns, relative to the package source, for files
+	/// that are never linked.
+	#[serde(default)]
+	pub ignore:            Vec<String>,
+	/// Commands run before and after the package is linked or removed.
+	#[serde(default)]
+	pub hooks:             Hooks,
+}
+
+/// The `hooks` table of a package. Each hook is a shell command.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct Hooks {
+	pub pre_link:    Option<String>,
+	pub post_link:   Option<String>,
+	pub pre_remove:  Option<String>,
+	pub post_remove: Option<String>,
+	/// Seconds a hook may run before it is killed. Defaults to 60.
+	pub timeout:     Option<u64>,
+}
+
+/// One entry of a package's `files` list.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileMapping {
+	/// Path relative to the package source.
+	pub source: String,
+	/// Path relative to `global.target_dir`.
+	pub target: String,
 }
 
 /// The top-level structure representing the entire `rinkle.toml` configuration.
@@ -71,31 +153,85 @@ pub struct Config {
 	/// Version Selection Control configuration.
 	#[serde(default)]
 	pub vsc:      Vsc,
+	/// Restrictions on where links may be created.
+	#[serde(default)]
+	pub policy:   Policy,
 	/// A map of profile names to lists of tags.
 	#[serde(default)]
 	pub profiles: HashMap<String, Vec<String>>, // profile name -> tags
 	/// A map of package names to their configurations.
 	#[serde(default)]
-	pub packages: HashMap<String, Package>,
+	pub packages: BTreeMap<String, Package>,
 }
 
 /// Defines the strategy for handling conflicts when a target file already
 /// exists.
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(
+	Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default, clap::ValueEnum,
+)]
 #[serde(rename_all = "lowercase")]
-#[derive(Default)]
 pub enum ConflictStrategy {
 	/// Do not create the symlink and print a warning.
 	Skip,
 	/// Remove the existing file/directory before creating the symlink.
 	Overwrite,
-	/// Rename the existing file/directory with a `.bak` suffix.
+	/// Move the existing file/directory into the backup store.
 	#[default]
 	Backup,
-	/// Prompt the user for action. (Not yet implemented)
+	/// Move the existing file/directory into the package source, then link
+	/// it back.
+	Adopt,
+	/// Ask the user whether to overwrite the existing file/directory.
 	Prompt,
 }
 
+/// Defines how a package's source is mirrored into its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum LinkMode {
+	/// Link the whole package source as a single symlink at the target. A
+	/// directory source with ignored entries is mirrored as in [`Tree`]
+	/// instead, with a warning when that replaces an existing link.
+	///
+	/// [`Tree`]: LinkMode::Tree
+	#[default]
+	Direct,
+	/// Mirror the package's file tree into the target, GNU Stow style.
+	/// Directories owned by a single package are folded into one symlink and
+	/// unfolded into real directories when another package shares them.
+	Tree,
+}
+
+/// Defines what rinkle places at a package's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum Deploy {
+	/// Symlink the source, as laid out by `mode`.
+	#[default]
+	Symlink,
+	/// Copy every file of the source. Copies are tracked by content hash so
+	/// local edits and source updates can be told apart.
+	Copy,
+	/// Hard link every file of the source, for programs that refuse
+	/// symlinks. Source and target must be on the same filesystem.
+	Hardlink,
+}
+
+/// Defines what path is stored in the symlinks rinkle creates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[derive(Default)]
+pub enum LinkStyle {
+	/// Store the absolute path of the source.
+	#[default]
+	Absolute,
+	/// Store the path of the source relative to the link's directory, so links
+	/// survive moving the home directory and the repository together.
+	Relative,
+}
+
 pub static CFG: OnceLock<Config> = OnceLock::new();
 
 pub static CONFIG_FILE_NAME: &str = "rinkle.toml";
@@ -104,14 +240,11 @@ pub static CONFIG_FILE_DIR: &str = "rinkle";
 pub fn initialize_config(
 	config_path: &str,
 ) -> Result<&'static Config, anyhow::Error> {
-	let config = CFG.get_or_init(|| {
-		Config::load_config(config_path).expect("Failed to load config")
-	});
-	Ok(config)
-}
-
-pub fn get_config() -> &'static Config {
-	CFG.get().expect("Failed to get global config")
+	if let Some(config) = CFG.get() {
+		return Ok(config);
+	}
+	let config = Config::load_config(config_path)?;
+	Ok(CFG.get_or_init(|| config))
 }
 
 impl Config {
@@ -136,8 +269,4 @@ impl Config {
 			})
 			.ok_or_else(|| anyhow::anyhow!("Cannot determine config path"))
 	}
-
-	pub const fn get_package_source_paths(&self) -> Vec<PathBuf> {
-		Vec::new()
-	}
 }
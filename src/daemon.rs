@@ -10,6 +10,7 @@ mod unix;
 #[cfg(unix)]
 pub use self::unix::{
 	get_running_pid,
+	is_running,
 	start,
 	stop,
 };
@@ -19,6 +20,7 @@ mod windows;
 #[cfg(windows)]
 pub use self::windows::{
 	get_running_pid,
+	is_running,
 	start,
 	stop,
 };
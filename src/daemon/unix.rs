@@ -68,6 +68,12 @@ pub fn stop(pid: Pid) -> Result<()> {
 	Ok(())
 }
 
+/// Whether a process with the given PID exists.
+pub fn is_running(pid: u32) -> bool {
+	// `kill -0` checks for process existence.
+	signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
 /// Gets the PID of the running daemon, cleaning up stale files if it's dead.
 pub fn get_running_pid() -> Result<Option<Pid>> {
 	let pid_file = pid_path();
@@ -78,7 +84,7 @@ pub fn get_running_pid() -> Result<Option<Pid>> {
 	let pid_str =
 		fs::read_to_string(&pid_file).context("Failed to read PID file")?;
 	let pid_val = i32::from_str(pid_str.trim())
-		.map_err(|_| anyhow!("Invalid PID '{pid_str}' in file"))?;
+		.map_err(|e| anyhow!("Invalid PID '{pid_str}' in file: {e}"))?;
 	let pid = Pid::from_raw(pid_val);
 
 	// `kill -0` checks for process existence.
@@ -75,6 +75,13 @@ pub fn stop(pid: Pid) -> Result<()> {
 	Ok(())
 }
 
+/// Whether a process with the given PID exists.
+pub fn is_running(pid: u32) -> bool {
+	let mut system = System::new_all();
+	system.refresh_processes();
+	system.process(Pid::from(pid as usize)).is_some()
+}
+
 /// Gets the PID of the running process, cleaning up stale files if it's dead.
 pub fn get_running_pid() -> Result<Option<Pid>> {
 	let pid_file = paths::pid_path();
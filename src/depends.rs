@@ -0,0 +1,204 @@
+//! Ordering of packages by their `depends` lists.
+//!
+//! Linking applies every package after the packages it depends on and pulls
+//! in dependencies that were not asked for; removal runs in the reverse order
+//! and only ever touches the packages asked for. Packages are visited in name
+//! order, so the result does not depend on the order of the config.
+
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// Errors that can occur while ordering packages by their dependencies.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DependencyError {
+	/// A package depends on a package that is not defined.
+	#[error("package '{package}' depends on unknown package '{dependency}'")]
+	Unknown {
+		package:    String,
+		dependency: String,
+	},
+	/// The dependencies of some packages form a cycle.
+	#[error("dependency cycle: {}", .0.join(" -> "))]
+	Cycle(Vec<String>),
+	/// A dependency cannot be pulled in, e.g. because of its `os` list.
+	#[error("package '{package}' depends on '{dependency}', which is {reason}")]
+	Excluded {
+		package:    String,
+		dependency: String,
+		reason:     String,
+	},
+}
+
+/// Orders `names` for linking: every package comes after its dependencies.
+///
+/// Dependencies missing from `names` are pulled in, unless `excluded`
+/// returns why they cannot be.
+pub fn link_order(
+	cfg: &Config,
+	names: &[String],
+	excluded: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<String>, DependencyError> {
+	let requested: BTreeSet<&str> = names.iter().map(String::as_str).collect();
+	let mut sorter = Sorter::new(cfg);
+	for name in &requested {
+		sorter.visit(name, &|package, dependency| {
+			if requested.contains(dependency) {
+				return Ok(());
+			}
+			match excluded(dependency) {
+				Some(reason) => Err(DependencyError::Excluded {
+					package: package.to_string(),
+					dependency: dependency.to_string(),
+					reason,
+				}),
+				None => Ok(()),
+			}
+		})?;
+	}
+	Ok(sorter.order)
+}
+
+/// Orders `names` for removal: every package comes before the packages it
+/// depends on. Nothing outside `names` is added.
+pub fn remove_order(
+	cfg: &Config,
+	names: &[String],
+) -> Result<Vec<String>, DependencyError> {
+	let requested: BTreeSet<&str> = names.iter().map(String::as_str).collect();
+	let mut sorter = Sorter::new(cfg);
+	for name in &requested {
+		sorter.visit(name, &|_, _| Ok(()))?;
+	}
+	Ok(sorter
+		.order
+		.into_iter()
+		.rev()
+		.filter(|name| requested.contains(name.as_str()))
+		.collect())
+}
+
+/// A depth-first topological sort.
+struct Sorter<'a> {
+	cfg:   &'a Config,
+	/// Packages in dependency order.
+	order: Vec<String>,
+	/// The path of packages being visited, to report cycles.
+	stack: Vec<String>,
+}
+
+type Check<'c> = dyn Fn(&str, &str) -> Result<(), DependencyError> + 'c;
+
+impl<'a> Sorter<'a> {
+	const fn new(cfg: &'a Config) -> Self {
+		Self {
+			cfg,
+			order: Vec::new(),
+			stack: Vec::new(),
+		}
+	}
+
+	fn visit(
+		&mut self,
+		name: &str,
+		check: &Check,
+	) -> Result<(), DependencyError> {
+		if self.order.iter().any(|n| n == name) {
+			return Ok(());
+		}
+		if let Some(start) = self.stack.iter().position(|n| n == name) {
+			let mut cycle = self.stack[start..].to_vec();
+			cycle.push(name.to_string());
+			return Err(DependencyError::Cycle(cycle));
+		}
+		// Unknown packages asked for are reported by the caller.
+		if let Some(pkg) = self.cfg.packages.get(name) {
+			self.stack.push(name.to_string());
+			for dependency in &pkg.depends {
+				if !self.cfg.packages.contains_key(dependency) {
+					return Err(DependencyError::Unknown {
+						package:    name.to_string(),
+						dependency: dependency.clone(),
+					});
+				}
+				check(name, dependency)?;
+				self.visit(dependency, check)?;
+			}
+			self.stack.pop();
+		}
+		self.order.push(name.to_string());
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Package;
+
+	fn config(graph: &[(&str, &[&str])]) -> Config {
+		let mut cfg = Config::default();
+		for (name, depends) in graph {
+			cfg.packages.insert((*name).to_string(), Package {
+				depends: depends.iter().map(ToString::to_string).collect(),
+				..Package::default()
+			});
+		}
+		cfg
+	}
+
+	fn names(names: &[&str]) -> Vec<String> {
+		names.iter().map(ToString::to_string).collect()
+	}
+
+	#[test]
+	fn orders_pulls_in_and_reports_cycles() {
+		let cfg = config(&[
+			("zsh", &["zsh-plugins", "fonts"]),
+			("zsh-plugins", &["git"]),
+			("fonts", &[]),
+			("git", &[]),
+			("nvim", &["fonts"]),
+		]);
+		let none = |_: &str| None;
+
+		assert_eq!(
+			link_order(&cfg, &names(&["zsh"]), none).unwrap(),
+			names(&["git", "zsh-plugins", "fonts", "zsh"])
+		);
+		assert_eq!(
+			link_order(&cfg, &names(&["zsh", "nvim"]), none).unwrap(),
+			names(&["fonts", "nvim", "git", "zsh-plugins", "zsh"])
+		);
+		assert_eq!(
+			remove_order(&cfg, &names(&["fonts", "zsh", "nvim"])).unwrap(),
+			names(&["zsh", "nvim", "fonts"])
+		);
+
+		let excluded = |name: &str| (name == "git").then(|| "excluded".into());
+		assert_eq!(
+			link_order(&cfg, &names(&["zsh"]), excluded),
+			Err(DependencyError::Excluded {
+				package:    "zsh-plugins".into(),
+				dependency: "git".into(),
+				reason:     "excluded".into(),
+			})
+		);
+		assert!(link_order(&cfg, &names(&["zsh", "git"]), excluded).is_ok());
+
+		let cfg = config(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])]);
+		assert_eq!(
+			link_order(&cfg, &names(&["b"]), none)
+				.unwrap_err()
+				.to_string(),
+			"dependency cycle: b -> c -> a -> b"
+		);
+		let cfg = config(&[("a", &["nope"])]);
+		assert!(matches!(
+			remove_order(&cfg, &names(&["a"])),
+			Err(DependencyError::Unknown { .. })
+		));
+	}
+}
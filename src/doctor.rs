@@ -0,0 +1,479 @@
+//! Configuration and environment checks behind `rk doctor`.
+//!
+//! Serde only rejects configs that do not parse. The checks here catch the
+//! mistakes that parse fine but make rinkle silently do nothing or the wrong
+//! thing: tags no package has, packages no profile selects, missing sources,
+//! a broken `vsc.template`, misspelled `os` values, paths that cannot be
+//! expanded, broken `depends` lists and packages the `[policy]` section rules
+//! out. The environment checks make sure links can actually be created and
+//! that no dead daemon left its pid file or socket behind.
+
+use std::{
+	collections::{
+		BTreeSet,
+		HashSet,
+	},
+	fmt,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use crate::{
+	config::Config,
+	daemon,
+	depends,
+	linker,
+	state::State,
+	utils::{
+		KNOWN_OS,
+		pid_path,
+		socket_path,
+	},
+	vsc::{
+		self,
+		Versions,
+		VscError,
+	},
+};
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	/// Something rinkle will fail on or silently get wrong.
+	Error,
+	/// Probably a mistake, but rinkle can carry on.
+	Warning,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Error => "error",
+			Self::Warning => "warning",
+		})
+	}
+}
+
+/// A problem found by a check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub message:  String,
+	/// What to do about it.
+	pub hint:     Option<String>,
+}
+
+impl Diagnostic {
+	fn error(message: impl Into<String>) -> Self {
+		Self {
+			severity: Severity::Error,
+			message:  message.into(),
+			hint:     None,
+		}
+	}
+
+	fn warning(message: impl Into<String>) -> Self {
+		Self {
+			severity: Severity::Warning,
+			message:  message.into(),
+			hint:     None,
+		}
+	}
+
+	fn hint(mut self, hint: impl Into<String>) -> Self {
+		self.hint = Some(hint.into());
+		self
+	}
+}
+
+/// Runs every check, errors first. Packages are resolved against `versions`.
+pub fn diagnose(
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+) -> Vec<Diagnostic> {
+	let mut out = Vec::new();
+	check_profiles(cfg, &mut out);
+	check_vsc_template(cfg, &mut out);
+	check_vsc_aliases(cfg, &mut out);
+	check_packages(cfg, versions, state, &mut out);
+	check_depends(cfg, &mut out);
+	check_targets(cfg, versions, state, &mut out);
+	check_daemon(&mut out);
+	out.sort_by_key(|d| d.severity);
+	out
+}
+
+fn check_profiles(cfg: &Config, out: &mut Vec<Diagnostic>) {
+	let package_tags: HashSet<&str> = cfg
+		.packages
+		.values()
+		.flat_map(|pkg| pkg.tags.iter().map(String::as_str))
+		.collect();
+	let profile_tags: HashSet<&str> = cfg
+		.profiles
+		.values()
+		.flat_map(|tags| tags.iter().map(String::as_str))
+		.collect();
+
+	let mut profiles: Vec<_> = cfg.profiles.iter().collect();
+	profiles.sort_unstable_by_key(|(name, _)| *name);
+	for (profile, tags) in profiles {
+		for tag in tags.iter().filter(|t| !package_tags.contains(t.as_str())) {
+			out.push(
+				Diagnostic::warning(format!(
+					"profile '{profile}' references tag '{tag}', which no \
+					 package has"
+				))
+				.hint("fix the spelling or tag a package with it"),
+			);
+		}
+	}
+
+	if cfg.profiles.is_empty() {
+		return;
+	}
+	for (name, pkg) in &cfg.packages {
+		if !pkg.tags.iter().any(|t| profile_tags.contains(t.as_str())) {
+			out.push(
+				Diagnostic::warning(format!(
+					"package '{name}' is not selected by any profile"
+				))
+				.hint(
+					"give it a tag used by a profile, or it is only linked \
+					 when named explicitly",
+				),
+			);
+		}
+	}
+}
+
+fn check_vsc_template(cfg: &Config, out: &mut Vec<Diagnostic>) {
+	if cfg.vsc.template.is_none() {
+		return;
+	}
+	match vsc::template(cfg) {
+		Err(e @ VscError::NoVersionGroup(_)) => {
+			out.push(
+				Diagnostic::error(e.to_string())
+					.hint("name the group, e.g. '^nvim@(?P<version>.+)$'"),
+			);
+		}
+		Err(e) => out.push(Diagnostic::error(e.to_string())),
+		Ok(_) => {}
+	}
+}
+
+fn check_vsc_aliases(cfg: &Config, out: &mut Vec<Diagnostic>) {
+	for (alias, target) in &cfg.vsc.aliases {
+		if cfg.vsc.aliases.contains_key(target) {
+			out.push(
+				Diagnostic::warning(format!(
+					"vsc alias '{alias}' refers to alias '{target}', which is \
+					 not followed"
+				))
+				.hint(format!(
+					"set '{alias}' to the version '{target}' stands for"
+				)),
+			);
+		}
+	}
+}
+
+fn check_packages(
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	out: &mut Vec<Diagnostic>,
+) {
+	let mut roots_ok = true;
+	for (key, value) in [
+		("global.source_dir", &cfg.global.source_dir),
+		("global.target_dir", &cfg.global.target_dir),
+	] {
+		if let Some(raw) = value {
+			roots_ok &= check_expandable(key, raw, out);
+		}
+	}
+
+	for (name, pkg) in &cfg.packages {
+		for os in pkg.os.iter().filter(|os| !KNOWN_OS.contains(&os.as_str())) {
+			out.push(
+				Diagnostic::warning(format!(
+					"package '{name}' has unknown os '{os}', so it is never \
+					 linked"
+				))
+				.hint(format!("use one of: {}", KNOWN_OS.join(", "))),
+			);
+		}
+
+		let mut paths_ok = roots_ok;
+		for (field, value) in [("source", &pkg.source), ("target", &pkg.target)]
+		{
+			if let Some(raw) = value {
+				paths_ok &= check_expandable(
+					&format!("packages.{name}.{field}"),
+					raw,
+					out,
+				);
+			}
+		}
+		for (i, file) in pkg.files.iter().enumerate() {
+			for (field, raw) in
+				[("source", &file.source), ("target", &file.target)]
+			{
+				paths_ok &= check_expandable(
+					&format!("packages.{name}.files[{i}].{field}"),
+					raw,
+					out,
+				);
+			}
+		}
+		if !paths_ok {
+			continue;
+		}
+		if let Err(e) =
+			linker::check_policy(name, pkg, cfg, versions, state, None)
+		{
+			out.push(
+				Diagnostic::error(e.to_string())
+					.hint("change the package or relax the [policy] section"),
+			);
+		}
+		let mappings =
+			linker::resolve_mappings(name, pkg, cfg, versions, state, None);
+		for (source, _) in mappings {
+			if fs::symlink_metadata(&source).is_ok() {
+				continue;
+			}
+			out.push(
+				Diagnostic::error(format!(
+					"package '{name}': source {} does not exist",
+					source.display()
+				))
+				.hint("create it, set `source`, or remove the package"),
+			);
+		}
+	}
+}
+
+fn check_depends(cfg: &Config, out: &mut Vec<Diagnostic>) {
+	let names: Vec<_> = cfg.packages.keys().cloned().collect();
+	if let Err(e) = depends::link_order(cfg, &names, |_| None) {
+		out.push(
+			Diagnostic::error(e.to_string())
+				.hint("fix the `depends` lists of the packages involved"),
+		);
+	}
+}
+
+/// Reports `raw` if `~` or a `$VAR` in it cannot be expanded.
+fn check_expandable(key: &str, raw: &str, out: &mut Vec<Diagnostic>) -> bool {
+	match shellexpand::full(raw) {
+		Err(e) => {
+			out.push(
+				Diagnostic::error(format!(
+					"{key} '{raw}' cannot be expanded: {e}"
+				))
+				.hint("set the variable or use a literal path"),
+			);
+			false
+		}
+		Ok(expanded) if expanded.starts_with('~') => {
+			out.push(Diagnostic::error(format!(
+				"{key} '{raw}' cannot be expanded: the home directory is \
+				 unknown"
+			)));
+			false
+		}
+		Ok(_) => true,
+	}
+}
+
+/// Makes sure links can be created in every directory rinkle links into.
+fn check_targets(
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	out: &mut Vec<Diagnostic>,
+) {
+	let mut dirs = BTreeSet::new();
+	dirs.insert(linker::target_root(cfg));
+	for (name, pkg) in &cfg.packages {
+		for (_, target) in
+			linker::resolve_mappings(name, pkg, cfg, versions, state, None)
+		{
+			if let Some(parent) = target.parent() {
+				dirs.insert(parent.to_path_buf());
+			}
+		}
+	}
+	// Missing directories are created on link; what matters is whether their
+	// nearest existing ancestor can be written to.
+	let dirs: BTreeSet<_> = dirs.iter().filter_map(|d| existing(d)).collect();
+	for dir in dirs {
+		if let Err(diagnostic) = probe(&dir) {
+			out.push(diagnostic);
+		}
+	}
+}
+
+fn existing(path: &Path) -> Option<PathBuf> {
+	path.ancestors().find(|p| p.is_dir()).map(Path::to_path_buf)
+}
+
+/// Creates and removes a file and a symlink to it in `dir`.
+fn probe(dir: &Path) -> Result<(), Diagnostic> {
+	let file = dir.join(format!(".rinkle-doctor-{}", std::process::id()));
+	let link = file.with_extension("link");
+	fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(&file)
+		.map_err(|e| {
+			Diagnostic::error(format!("cannot write to {}: {e}", dir.display()))
+				.hint("fix its permissions or choose another target_dir")
+		})?;
+	let linked = linker::create_symlink(&file, &link);
+	let _ = fs::remove_file(&link);
+	let _ = fs::remove_file(&file);
+	linked.map_err(|e| {
+		Diagnostic::error(format!(
+			"cannot create symlinks in {}: {e}",
+			dir.display()
+		))
+		.hint("the filesystem does not support symlinks")
+	})
+}
+
+/// Looks for a pid file or socket left behind by a daemon that died.
+fn check_daemon(out: &mut Vec<Diagnostic>) {
+	let pid_file = pid_path();
+	let socket = socket_path();
+	let alive = match fs::read_to_string(&pid_file) {
+		Err(_) => false,
+		Ok(raw) => match raw.trim().parse::<u32>() {
+			Err(e) => {
+				out.push(
+					Diagnostic::warning(format!(
+						"pid file {} is invalid: {e}",
+						pid_file.display()
+					))
+					.hint("delete it"),
+				);
+				false
+			}
+			Ok(pid) if daemon::is_running(pid) => true,
+			Ok(pid) => {
+				out.push(
+					Diagnostic::warning(format!(
+						"stale pid file {} for dead process {pid}",
+						pid_file.display()
+					))
+					.hint("run `rk stop` to clean it up"),
+				);
+				false
+			}
+		},
+	};
+	if !alive && fs::symlink_metadata(&socket).is_ok() {
+		out.push(
+			Diagnostic::warning(format!(
+				"stale daemon socket {}",
+				socket.display()
+			))
+			.hint("delete it before running `rk start`"),
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::config::{
+		Package,
+		Vsc,
+	};
+
+	#[test]
+	fn config_checks() {
+		let src = tempdir().unwrap();
+		let dst = tempdir().unwrap();
+		fs::create_dir(src.path().join("zsh")).unwrap();
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(src.path().display().to_string());
+		cfg.global.target_dir = Some(dst.path().display().to_string());
+		cfg.profiles
+			.insert("work".into(), vec!["shell".into(), "typo".into()]);
+		cfg.packages.insert("zsh".into(), Package {
+			tags: vec!["shell".into()],
+			depends: vec!["fontz".into()],
+			..Package::default()
+		});
+		cfg.packages.insert("nvim".into(), Package {
+			os: vec!["linx".into()],
+			target: Some("$RINKLE_DOCTOR_UNSET/nvim".into()),
+			..Package::default()
+		});
+		cfg.vsc = Vsc {
+			template: Some("^nvim@(.+)$".into()),
+			default_version: None,
+			aliases: [("lts", "v0.9"), ("old", "lts")]
+				.into_iter()
+				.map(|(alias, target)| (alias.into(), target.into()))
+				.collect(),
+			..Vsc::default()
+		};
+
+		let messages: Vec<_> =
+			diagnose(&cfg, &Versions::default(), &State::default())
+				.into_iter()
+				.filter(|d| {
+					!d.message.contains("daemon") && !d.message.contains("pid")
+				})
+				.map(|d| (d.severity, d.message))
+				.collect();
+		assert_eq!(messages, vec![
+			(
+				Severity::Error,
+				"vsc.template '^nvim@(.+)$' has no 'version' capture group"
+					.to_string()
+			),
+			(
+				Severity::Error,
+				"packages.nvim.target '$RINKLE_DOCTOR_UNSET/nvim' cannot be \
+				 expanded: error looking key 'RINKLE_DOCTOR_UNSET' up: \
+				 environment variable not found"
+					.to_string()
+			),
+			(
+				Severity::Error,
+				"package 'zsh' depends on unknown package 'fontz'".to_string()
+			),
+			(
+				Severity::Warning,
+				"profile 'work' references tag 'typo', which no package has"
+					.to_string()
+			),
+			(
+				Severity::Warning,
+				"package 'nvim' is not selected by any profile".to_string()
+			),
+			(
+				Severity::Warning,
+				"vsc alias 'old' refers to alias 'lts', which is not followed"
+					.to_string()
+			),
+			(
+				Severity::Warning,
+				"package 'nvim' has unknown os 'linx', so it is never linked"
+					.to_string()
+			),
+		]);
+	}
+}
@@ -0,0 +1,400 @@
+//! Numbered generations of the managed setup.
+//!
+//! Every successful `link`, `adopt`, `remove`, `prune`, `use-profile`, `vsc`
+//! version switch and `rollback` records a generation in the `generations/`
+//! directory next to `state.toml`. A generation captures the symlinks rinkle
+//! owns, the files it copied or hard linked with the hashes of their content,
+//! the active profile and the pinned versions, so that any earlier setup can be
+//! restored with `rk rollback`. Only the content is not kept: a copied file is
+//! restored from its source, and left out if the source has changed since.
+
+use std::{
+	collections::{
+		BTreeMap,
+		HashMap,
+	},
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use thiserror::Error;
+use tracing::{
+	info,
+	warn,
+};
+
+use crate::{
+	config::{
+		ConflictStrategy,
+		Deploy,
+	},
+	linker::{
+		self,
+		LinkError,
+		Transaction,
+	},
+	ownership::{
+		DeployedFile,
+		Ownership,
+	},
+	state::{
+		self,
+		State,
+	},
+};
+
+/// Errors that can occur while reading or writing generations.
+#[derive(Debug, Error)]
+pub enum GenerationError {
+	/// The requested generation does not exist.
+	#[error("generation {0} not found")]
+	NotFound(u64),
+	/// There is no generation before the current one to roll back to.
+	#[error("no previous generation to roll back to")]
+	NoPrevious,
+	/// An I/O error occurred while reading or writing a generation file.
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+	/// A generation file could not be parsed.
+	#[error("toml parse error: {0}")]
+	TomlDe(#[from] toml::de::Error),
+	/// A generation could not be serialized.
+	#[error("toml serialize error: {0}")]
+	TomlSer(#[from] toml::ser::Error),
+}
+
+/// A symlink owned by rinkle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRecord {
+	pub link:      PathBuf,
+	pub points_to: PathBuf,
+	/// The package the link was created for; unknown in generations
+	/// recorded before ownership was tracked.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub package:   Option<String>,
+}
+
+/// A file rinkle copied or hard linked into place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+	pub path:    PathBuf,
+	pub source:  PathBuf,
+	/// SHA-256 of the content deployed, in hex.
+	pub hash:    String,
+	pub deploy:  Deploy,
+	pub package: String,
+}
+
+impl FileRecord {
+	fn deployed(&self) -> DeployedFile {
+		DeployedFile {
+			source: self.source.clone(),
+			hash:   self.hash.clone(),
+			deploy: self.deploy,
+		}
+	}
+}
+
+/// A snapshot of the managed setup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+	/// Sequential generation number, starting at 1.
+	pub number:          u64,
+	/// RFC 3339 timestamp of when the generation was recorded.
+	pub created_at:      String,
+	/// The command that produced the generation.
+	pub command:         String,
+	/// The active profile at the time.
+	pub active_profile:  Option<String>,
+	/// Pinned package versions at the time.
+	#[serde(default)]
+	pub pinned_versions: BTreeMap<String, String>,
+	/// Every symlink rinkle owned at the time.
+	#[serde(default)]
+	pub links:           Vec<LinkRecord>,
+	/// Every file rinkle copied or hard linked at the time.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub files:           Vec<FileRecord>,
+}
+
+impl Generation {
+	/// Returns the links as a map of link path to destination.
+	pub fn link_map(&self) -> BTreeMap<PathBuf, PathBuf> {
+		self.links
+			.iter()
+			.map(|r| (r.link.clone(), r.points_to.clone()))
+			.collect()
+	}
+
+	/// Whether this generation describes the same setup.
+	fn same_setup(
+		&self,
+		state: &State,
+		links: &[LinkRecord],
+		files: &[FileRecord],
+	) -> bool {
+		self.active_profile == state.active_profile &&
+			self.pinned_versions == sorted(&state.pinned_versions) &&
+			self.links == links &&
+			self.files == files
+	}
+}
+
+/// Returns the directory holding generation files.
+pub fn generations_dir() -> PathBuf {
+	state::state_file("generations")
+}
+
+/// Loads every generation, oldest first.
+pub fn list() -> Result<Vec<Generation>, GenerationError> {
+	list_in(&generations_dir())
+}
+
+/// Loads the generation with the given number.
+pub fn load(number: u64) -> Result<Generation, GenerationError> {
+	let path = generations_dir().join(format!("{number}.toml"));
+	if !path.exists() {
+		return Err(GenerationError::NotFound(number));
+	}
+	Ok(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Records a new generation holding the links and files in `owned`, unless
+/// it would be identical to the latest one.
+pub fn record(
+	command: &str,
+	state: &State,
+	owned: &Ownership,
+) -> Result<Option<Generation>, GenerationError> {
+	record_in(&generations_dir(), command, state, owned)
+}
+
+/// Restores the links and files of `target` on the filesystem.
+///
+/// Links and files owned according to the transaction's ownership database
+/// but absent from `target` are removed, then those of `target` are
+/// (re)created for the packages that created them. Existing files in the way
+/// are handled with `strategy`. Files whose source no longer has the content
+/// recorded are left out with a warning.
+pub fn reconcile(
+	target: &Generation,
+	strategy: ConflictStrategy,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let wanted = target.link_map();
+	let current: BTreeMap<PathBuf, PathBuf> = tx
+		.ownership()
+		.links()
+		.map(|(_, link, to)| (link.to_path_buf(), to.to_path_buf()))
+		.collect();
+	// Deepest paths first so links inside unfolded directories go before
+	// their parents.
+	for (link, points_to) in current.iter().rev() {
+		if wanted.get(link) == Some(points_to) {
+			continue;
+		}
+		if fs::read_link(link).is_ok_and(|p| p == *points_to) {
+			tx.remove_symlink(link)?;
+		}
+	}
+	let current: Vec<(PathBuf, DeployedFile)> = tx
+		.ownership()
+		.files()
+		.map(|(_, path, file)| (path.to_path_buf(), file.clone()))
+		.collect();
+	for (path, file) in &current {
+		let kept = target
+			.files
+			.iter()
+			.any(|record| record.path == *path && record.deployed() == *file);
+		if !kept {
+			let dir = path.parent().unwrap_or(Path::new("."));
+			linker::remove_deployed(path, file, dir, tx)?;
+		}
+	}
+
+	for LinkRecord {
+		link,
+		points_to,
+		package,
+	} in &target.links
+	{
+		Transaction::check_interrupted()?;
+		tx.set_package(package.as_deref());
+		match fs::symlink_metadata(link) {
+			Err(_) => {}
+			Ok(meta) if meta.file_type().is_symlink() => {
+				if fs::read_link(link)? == *points_to {
+					tx.claim(link, points_to);
+					continue;
+				}
+				tx.remove_symlink(link)?;
+			}
+			Ok(meta) if meta.is_dir() && is_empty_tree(link) => {
+				remove_empty_tree(link, tx)?;
+			}
+			Ok(_) => {
+				if !linker::handle_conflict(points_to, link, strategy, tx)? {
+					warn!("skipped {}", link.display());
+					continue;
+				}
+			}
+		}
+		tx.symlink(points_to, link)?;
+	}
+	for record in &target.files {
+		Transaction::check_interrupted()?;
+		tx.set_package(Some(&record.package));
+		linker::restore_deployed(
+			&record.path,
+			&record.deployed(),
+			strategy,
+			tx,
+		)?;
+	}
+	tx.set_package(None);
+	Ok(())
+}
+
+fn list_in(dir: &Path) -> Result<Vec<Generation>, GenerationError> {
+	let Ok(entries) = fs::read_dir(dir) else {
+		return Ok(Vec::new());
+	};
+	let mut generations = Vec::new();
+	for entry in entries {
+		let path = entry?.path();
+		if path.extension().is_some_and(|e| e == "toml") {
+			generations.push(toml::from_str::<Generation>(
+				&fs::read_to_string(&path)?,
+			)?);
+		}
+	}
+	generations.sort_by_key(|g| g.number);
+	Ok(generations)
+}
+
+fn record_in(
+	dir: &Path,
+	command: &str,
+	state: &State,
+	owned: &Ownership,
+) -> Result<Option<Generation>, GenerationError> {
+	let mut links: Vec<_> = owned
+		.links()
+		.map(|(package, link, points_to)| LinkRecord {
+			link:      link.to_path_buf(),
+			points_to: points_to.to_path_buf(),
+			package:   Some(package.to_string()),
+		})
+		.collect();
+	links.sort_by(|a, b| a.link.cmp(&b.link));
+	let mut files: Vec<_> = owned
+		.files()
+		.map(|(package, path, file)| FileRecord {
+			path:    path.to_path_buf(),
+			source:  file.source.clone(),
+			hash:    file.hash.clone(),
+			deploy:  file.deploy,
+			package: package.to_string(),
+		})
+		.collect();
+	files.sort_by(|a, b| a.path.cmp(&b.path));
+	let latest = list_in(dir)?.pop();
+	if latest
+		.as_ref()
+		.is_some_and(|g| g.same_setup(state, &links, &files))
+	{
+		return Ok(None);
+	}
+
+	let generation = Generation {
+		number: latest.map_or(1, |g| g.number + 1),
+		created_at: chrono::Local::now().to_rfc3339(),
+		command: command.to_string(),
+		active_profile: state.active_profile.clone(),
+		pinned_versions: sorted(&state.pinned_versions),
+		links,
+		files,
+	};
+	fs::create_dir_all(dir)?;
+	fs::write(
+		dir.join(format!("{}.toml", generation.number)),
+		toml::to_string_pretty(&generation)?,
+	)?;
+	info!("recorded generation {}", generation.number);
+	Ok(Some(generation))
+}
+
+fn sorted(map: &HashMap<String, String>) -> BTreeMap<String, String> {
+	map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+/// A directory containing nothing but (possibly nested) empty directories,
+/// as left behind when unfolded tree links are removed.
+fn is_empty_tree(dir: &Path) -> bool {
+	fs::read_dir(dir).is_ok_and(|entries| {
+		entries
+			.flatten()
+			.all(|e| linker::is_real_dir(&e.path()) && is_empty_tree(&e.path()))
+	})
+}
+
+fn remove_empty_tree(
+	dir: &Path,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	for entry in fs::read_dir(dir)? {
+		remove_empty_tree(&entry?.path(), tx)?;
+	}
+	tx.remove_dir(dir)
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn record_numbers_and_deduplicates() {
+		let tmp = tempdir().unwrap();
+		let dir = tmp.path().join("generations");
+		let mut state = State::default();
+		let mut owned = Ownership::default();
+		owned.add_link("a", Path::new("/t/a"), Path::new("/s/a"));
+
+		let first = record_in(&dir, "link", &state, &owned).unwrap().unwrap();
+		assert_eq!(first.number, 1);
+		assert!(record_in(&dir, "link", &state, &owned).unwrap().is_none());
+
+		state.active_profile = Some("work".into());
+		let second = record_in(&dir, "use-profile", &state, &owned)
+			.unwrap()
+			.unwrap();
+		assert_eq!(second.number, 2);
+
+		owned.add_file("b", Path::new("/t/b"), DeployedFile {
+			source: PathBuf::from("/s/b"),
+			hash:   "00".into(),
+			deploy: Deploy::Copy,
+		});
+		let third = record_in(&dir, "link", &state, &owned).unwrap().unwrap();
+		assert_eq!(third.files[0].package, "b");
+
+		let all = list_in(&dir).unwrap();
+		assert_eq!(all.len(), 3);
+		assert_eq!(all[1].active_profile.as_deref(), Some("work"));
+		assert_eq!(all[0].links, vec![LinkRecord {
+			link:      PathBuf::from("/t/a"),
+			points_to: PathBuf::from("/s/a"),
+			package:   Some("a".into()),
+		}]);
+	}
+}
@@ -0,0 +1,379 @@
+//! Commands run before and after a package is linked or removed.
+//!
+//! A hook runs through `sh -c` in the package source with `RINKLE_PACKAGE`,
+//! `RINKLE_SOURCE`, `RINKLE_TARGET` and `RINKLE_VERSION` describing the
+//! package. Everything it prints is logged line by line. A hook that exits
+//! with an error fails the command, and one that outlives its timeout is
+//! killed together with every process it started.
+
+use std::{
+	fmt,
+	io::{
+		self,
+		BufRead,
+		BufReader,
+		Read,
+	},
+	process::{
+		Child,
+		Command,
+		ExitStatus,
+		Stdio,
+	},
+	thread::{
+		self,
+		JoinHandle,
+	},
+	time::{
+		Duration,
+		Instant,
+	},
+};
+
+use thiserror::Error;
+use tracing::{
+	info,
+	warn,
+};
+
+use crate::{
+	config::{
+		Config,
+		Hooks,
+		Package,
+	},
+	linker,
+	state::State,
+	vsc::Versions,
+};
+
+/// How long a hook may run unless the package sets `hooks.timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_mins(1);
+
+/// How long the output of a hook that exited is still read. A process it
+/// left running in the background may hold the output open for good.
+const OUTPUT_GRACE: Duration = Duration::from_millis(300);
+
+/// The points of a command at which hooks run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+	PreLink,
+	PostLink,
+	PreRemove,
+	PostRemove,
+}
+
+impl Hook {
+	/// The command configured for this hook, if any.
+	pub fn command(self, hooks: &Hooks) -> Option<&str> {
+		match self {
+			Self::PreLink => hooks.pre_link.as_deref(),
+			Self::PostLink => hooks.post_link.as_deref(),
+			Self::PreRemove => hooks.pre_remove.as_deref(),
+			Self::PostRemove => hooks.post_remove.as_deref(),
+		}
+	}
+}
+
+impl fmt::Display for Hook {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::PreLink => "pre_link",
+			Self::PostLink => "post_link",
+			Self::PreRemove => "pre_remove",
+			Self::PostRemove => "post_remove",
+		})
+	}
+}
+
+/// Errors that can occur while running a hook.
+#[derive(Debug, Error)]
+pub enum HookError {
+	/// The shell could not be started.
+	#[error("{hook} hook of '{package}' could not be run: {source}")]
+	Spawn {
+		package: String,
+		hook:    Hook,
+		source:  io::Error,
+	},
+	/// The hook exited with an error.
+	#[error("{hook} hook of '{package}' failed: {status}")]
+	Failed {
+		package: String,
+		hook:    Hook,
+		status:  ExitStatus,
+	},
+	/// The hook ran past its timeout and was killed.
+	#[error("{hook} hook of '{package}' timed out after {}s", .timeout.as_secs())]
+	TimedOut {
+		package: String,
+		hook:    Hook,
+		timeout: Duration,
+	},
+}
+
+/// Runs `hook` of a package, if it has one, and waits for it.
+pub fn run(
+	hook: Hook,
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Result<(), HookError> {
+	let Some(command) = hook.command(&pkg.hooks) else {
+		return Ok(());
+	};
+	let (source, target) =
+		linker::resolve_paths(name, pkg, cfg, versions, state, version);
+	let version =
+		linker::selected_version(name, pkg, cfg, versions, state, version);
+	let timeout = pkg
+		.hooks
+		.timeout
+		.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+	info!("running {hook} hook of {name}: {command}");
+
+	let mut cmd = Command::new("sh");
+	cmd.arg("-c")
+		.arg(command)
+		.env("RINKLE_PACKAGE", name)
+		.env("RINKLE_SOURCE", &source)
+		.env("RINKLE_TARGET", &target)
+		.env("RINKLE_VERSION", version.unwrap_or_default())
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped());
+	// In a group of its own, so that a timeout kills what it started too.
+	#[cfg(unix)]
+	std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+	if let Some(dir) = [source, linker::source_root(cfg)]
+		.into_iter()
+		.find(|dir| dir.is_dir())
+	{
+		cmd.current_dir(dir);
+	}
+	let spawn_error = |source| HookError::Spawn {
+		package: name.to_string(),
+		hook,
+		source,
+	};
+	let mut child = cmd.spawn().map_err(spawn_error)?;
+	let prefix = format!("{name} {hook}");
+	let readers = [
+		child
+			.stdout
+			.take()
+			.map(|out| log_lines(out, prefix.clone(), false)),
+		child.stderr.take().map(|err| log_lines(err, prefix, true)),
+	];
+
+	let started = Instant::now();
+	let status = loop {
+		if let Some(status) = child.try_wait().map_err(spawn_error)? {
+			break status;
+		}
+		if started.elapsed() >= timeout {
+			kill_group(&mut child);
+			return Err(HookError::TimedOut {
+				package: name.to_string(),
+				hook,
+				timeout,
+			});
+		}
+		thread::sleep(Duration::from_millis(20));
+	};
+	let exited = Instant::now();
+	for reader in readers.into_iter().flatten() {
+		while !reader.is_finished() && exited.elapsed() < OUTPUT_GRACE {
+			thread::sleep(Duration::from_millis(10));
+		}
+		if reader.is_finished() {
+			let _ = reader.join();
+		}
+	}
+	if status.success() {
+		Ok(())
+	} else {
+		Err(HookError::Failed {
+			package: name.to_string(),
+			hook,
+			status,
+		})
+	}
+}
+
+/// Kills the hook and every process in its group.
+fn kill_group(child: &mut Child) {
+	#[cfg(unix)]
+	{
+		use nix::{
+			sys::signal::{
+				Signal,
+				killpg,
+			},
+			unistd::Pid,
+		};
+		let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+	}
+	let _ = child.kill();
+	let _ = child.wait();
+}
+
+/// Logs every line read from `out`, as warnings if `is_stderr`.
+fn log_lines(
+	out: impl Read + Send + 'static,
+	prefix: String,
+	is_stderr: bool,
+) -> JoinHandle<()> {
+	thread::spawn(move || {
+		for line in BufReader::new(out).lines().map_while(Result::ok) {
+			if is_stderr {
+				warn!("{prefix}: {line}");
+			} else {
+				info!("{prefix}: {line}");
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn runs_with_package_environment_and_times_out() {
+		let tmp = tempdir().unwrap();
+		let out = tmp.path().join("out");
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(tmp.path().display().to_string());
+		cfg.global.target_dir = Some("/home/me".into());
+		let mut pkg = Package {
+			hooks: Hooks {
+				post_link: Some(format!(
+					"echo \"$RINKLE_PACKAGE $RINKLE_TARGET $RINKLE_VERSION \
+					 $(pwd -P)\" > {}",
+					out.display()
+				)),
+				pre_remove: Some("exit 3".into()),
+				post_remove: Some("sleep 5".into()),
+				..Hooks::default()
+			},
+			..Package::default()
+		};
+		let versions = Versions::default();
+		let state = State::default();
+
+		run(Hook::PreLink, "tmux", &pkg, &cfg, &versions, &state, None)
+			.unwrap();
+		run(
+			Hook::PostLink,
+			"tmux",
+			&pkg,
+			&cfg,
+			&versions,
+			&state,
+			Some("3.4"),
+		)
+		.unwrap();
+		assert_eq!(
+			std::fs::read_to_string(&out).unwrap().trim(),
+			format!(
+				"tmux /home/me/tmux 3.4 {}",
+				tmp.path().canonicalize().unwrap().display()
+			)
+		);
+
+		let failed =
+			run(Hook::PreRemove, "tmux", &pkg, &cfg, &versions, &state, None);
+		assert!(matches!(failed, Err(HookError::Failed { .. })));
+		pkg.hooks.timeout = Some(1);
+		let started = Instant::now();
+		let slow = run(
+			Hook::PostRemove,
+			"tmux",
+			&pkg,
+			&cfg,
+			&versions,
+			&state,
+			None,
+		);
+		assert!(matches!(slow, Err(HookError::TimedOut { .. })));
+		assert!(started.elapsed() < Duration::from_secs(5));
+
+		pkg.hooks.post_remove = None;
+		run(
+			Hook::PostRemove,
+			"tmux",
+			&pkg,
+			&cfg,
+			&versions,
+			&state,
+			None,
+		)
+		.unwrap();
+	}
+
+	#[test]
+	fn background_children_hold_up_neither_success_nor_timeout() {
+		let tmp = tempdir().unwrap();
+		let pid_file = tmp.path().join("pid");
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(tmp.path().display().to_string());
+		let mut pkg = Package {
+			hooks: Hooks {
+				post_link: Some("sleep 5 &".into()),
+				post_remove: Some(format!(
+					"sleep 30 & echo $! > {}; wait",
+					pid_file.display()
+				)),
+				..Hooks::default()
+			},
+			..Package::default()
+		};
+		let versions = Versions::default();
+		let state = State::default();
+
+		// With the default timeout, a background child holding the output
+		// open does not hold up a hook that exited.
+		let started = Instant::now();
+		run(Hook::PostLink, "fish", &pkg, &cfg, &versions, &state, None)
+			.unwrap();
+		assert!(started.elapsed() < Duration::from_secs(3));
+
+		pkg.hooks.timeout = Some(1);
+		let slow = run(
+			Hook::PostRemove,
+			"fish",
+			&pkg,
+			&cfg,
+			&versions,
+			&state,
+			None,
+		);
+		assert!(matches!(slow, Err(HookError::TimedOut { .. })));
+		#[cfg(unix)]
+		{
+			use nix::{
+				sys::signal,
+				unistd::Pid,
+			};
+			let pid: i32 = std::fs::read_to_string(&pid_file)
+				.unwrap()
+				.trim()
+				.parse()
+				.unwrap();
+			let started = Instant::now();
+			while signal::kill(Pid::from_raw(pid), None).is_ok() {
+				assert!(
+					started.elapsed() < Duration::from_secs(2),
+					"the background child survived the timeout"
+				);
+				thread::sleep(Duration::from_millis(20));
+			}
+		}
+	}
+}
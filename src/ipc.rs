@@ -63,13 +63,13 @@ pub enum Response {
 // --- Client-side Logic ---
 
 /// Sends a single request to the daemon and waits for a response.
-pub fn send_request(socket_path: &Path, request: Request) -> Result<Response> {
+pub fn send_request(socket_path: &Path, request: &Request) -> Result<Response> {
 	let name = socket_path.to_fs_name::<GenericFilePath>()?;
 	let mut conn = LocalSocketStream::connect(name)
 		.context("Failed to connect to daemon socket")?;
 
 	// Serialize and send the request.
-	let request_json = serde_json::to_string(&request)?;
+	let request_json = serde_json::to_string(request)?;
 	conn.write_all(request_json.as_bytes())?;
 	// Add a newline to signal end of message.
 	conn.write_all(b"\n")?;
@@ -131,7 +131,6 @@ where
 			Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
 				// No incoming connection, wait a bit before checking again.
 				std::thread::sleep(std::time::Duration::from_millis(100));
-				continue;
 			}
 			Err(e) => {
 				return Err(e.into());
@@ -1,13 +1,20 @@
 mod app;
+mod backup;
 mod cli;
 mod config;
 mod daemon;
+mod depends;
+mod doctor;
+mod generation;
+mod hooks;
 mod ipc;
 mod linker;
 mod monitor;
+mod ownership;
 mod repl;
 mod setup;
 mod state;
 mod utils;
+mod vsc;
 
 pub use app::App;
@@ -0,0 +1,122 @@
+//! Adopting existing files into the repository, like `stow --adopt`.
+//!
+//! The file found at a target is moved into the package source, replacing
+//! what was there, so the caller can link it back. When both sides are
+//! directories their contents are merged instead: entries from the target
+//! win, entries only present in the repository are kept.
+
+use std::{
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use tracing::info;
+
+use super::{
+	LinkError,
+	Transaction,
+	tree::is_real_dir,
+};
+
+/// A source path whose contents were changed by adopting a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdoptedFile {
+	/// The path inside the package source.
+	pub source:      PathBuf,
+	/// Whether an existing, different source file was overwritten (as
+	/// opposed to a new file being added to the repository).
+	pub overwritten: bool,
+}
+
+/// Moves `target` into `source`, leaving `target` free for a link.
+pub(super) fn adopt(
+	source: &Path,
+	target: &Path,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	if is_real_dir(source) && is_real_dir(target) {
+		for entry in fs::read_dir(target)? {
+			let entry = entry?;
+			adopt(&source.join(entry.file_name()), &entry.path(), tx)?;
+		}
+		return tx.remove_dir(target);
+	}
+
+	let exists = fs::symlink_metadata(source).is_ok();
+	if !exists || !same_contents(source, target) {
+		tx.record_adopted(AdoptedFile {
+			source:      source.to_path_buf(),
+			overwritten: exists,
+		});
+	}
+	if exists {
+		tx.discard(source)?;
+	}
+	tx.rename(target, source)?;
+	if !tx.dry_run() {
+		info!("adopted {} into {}", target.display(), source.display());
+	}
+	Ok(())
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+	let is_file = |p: &Path| fs::symlink_metadata(p).is_ok_and(|m| m.is_file());
+	is_file(a) &&
+		is_file(b) &&
+		matches!((fs::read(a), fs::read(b)), (Ok(x), Ok(y)) if x == y)
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn adopt_merges_directories() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src/kitty");
+		let dst = tmp.path().join("dst/kitty");
+		fs::create_dir_all(&src).unwrap();
+		fs::create_dir_all(&dst).unwrap();
+		fs::write(src.join("kitty.conf"), "repo").unwrap();
+		fs::write(src.join("theme.conf"), "theme").unwrap();
+		fs::write(src.join("same.conf"), "same").unwrap();
+		fs::write(dst.join("kitty.conf"), "local").unwrap();
+		fs::write(dst.join("same.conf"), "same").unwrap();
+		fs::write(dst.join("extra.conf"), "extra").unwrap();
+
+		let mut tx = Transaction::new(None, false);
+		adopt(&src, &dst, &mut tx).unwrap();
+		let mut adopted = tx.adopted().to_vec();
+		adopted.sort_by(|a, b| a.source.cmp(&b.source));
+		assert_eq!(adopted, vec![
+			AdoptedFile {
+				source:      src.join("extra.conf"),
+				overwritten: false,
+			},
+			AdoptedFile {
+				source:      src.join("kitty.conf"),
+				overwritten: true,
+			},
+		]);
+		tx.commit().unwrap();
+
+		assert!(!dst.exists());
+		assert_eq!(
+			fs::read_to_string(src.join("kitty.conf")).unwrap(),
+			"local"
+		);
+		assert_eq!(
+			fs::read_to_string(src.join("theme.conf")).unwrap(),
+			"theme"
+		);
+		assert_eq!(
+			fs::read_to_string(src.join("extra.conf")).unwrap(),
+			"extra"
+		);
+	}
+}
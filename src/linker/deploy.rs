@@ -0,0 +1,437 @@
+//! Copy and hard link deployment.
+//!
+//! A package with `deploy = "copy"` or `deploy = "hardlink"` places every file
+//! of its source at the matching path below its target instead of linking it.
+//! Each file is recorded in the ownership database with the hash of the
+//! content deployed, which tells a target edited in place (*modified
+//! locally*) apart from a source that changed since (*source updated*). A hard
+//! link that is still intact is always in sync; one broken by a program
+//! replacing the file is compared like a copy.
+
+use std::{
+	fs,
+	io::{
+		self,
+		Read,
+	},
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use sha2::{
+	Digest,
+	Sha256,
+};
+use tracing::{
+	debug,
+	warn,
+};
+
+use super::{
+	FileStatus,
+	IgnoreRules,
+	LinkError,
+	LinkStatusKind,
+	Transaction,
+	handle_conflict,
+	tree::{
+		is_real_dir,
+		sorted_entries,
+	},
+};
+use crate::{
+	config::{
+		ConflictStrategy,
+		Deploy,
+	},
+	ownership::{
+		DeployedFile,
+		Ownership,
+	},
+};
+
+/// Returns the SHA-256 of the content of `path`, in hex.
+pub(super) fn hash_file(path: &Path) -> io::Result<String> {
+	let mut file = fs::File::open(path)?;
+	let mut hasher = Sha256::new();
+	let mut buf = [0; 8192];
+	loop {
+		let n = file.read(&mut buf)?;
+		if n == 0 {
+			break;
+		}
+		hasher.update(&buf[..n]);
+	}
+	Ok(hasher.finalize().iter().fold(
+		String::with_capacity(64),
+		|mut out, byte| {
+			use std::fmt::Write;
+			let _ = write!(out, "{byte:02x}");
+			out
+		},
+	))
+}
+
+/// Pairs every file of `src` that is not ignored with its path below `dst`.
+/// A file source maps to `dst` itself.
+pub(super) fn deploy_pairs(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+) -> Vec<(PathBuf, PathBuf)> {
+	let mut pairs = Vec::new();
+	if is_real_dir(src) {
+		collect_pairs(src, dst, rules, &mut pairs);
+	} else {
+		pairs.push((src.to_path_buf(), dst.to_path_buf()));
+	}
+	pairs
+}
+
+fn collect_pairs(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	pairs: &mut Vec<(PathBuf, PathBuf)>,
+) {
+	let Ok(entries) = sorted_entries(src) else {
+		return;
+	};
+	for (child_src, name) in entries {
+		let child_dst = dst.join(&name);
+		let is_dir = is_real_dir(&child_src);
+		if rules.is_ignored(&child_src, is_dir) {
+			continue;
+		}
+		if is_dir {
+			collect_pairs(&child_src, &child_dst, rules, pairs);
+		} else {
+			pairs.push((child_src, child_dst));
+		}
+	}
+}
+
+/// The paths a copied or hard-linked package places below `dst`, flagged
+/// `true` for directories.
+pub(super) fn deploy_claims(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+) -> Vec<(PathBuf, bool)> {
+	let mut claims = Vec::new();
+	for (_, target) in deploy_pairs(src, dst, rules) {
+		for dir in target.ancestors().skip(1) {
+			if !dir.starts_with(dst) || claims.contains(&(dir.into(), true)) {
+				break;
+			}
+			claims.push((dir.to_path_buf(), true));
+		}
+		claims.push((target, false));
+	}
+	claims
+}
+
+/// Reports the status of every file `src` deploys below `dst`.
+pub(super) fn deploy_status(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	owned: &Ownership,
+) -> Vec<FileStatus> {
+	deploy_pairs(src, dst, rules)
+		.into_iter()
+		.map(|(source, target)| FileStatus {
+			status: file_status(&source, &target, owned),
+			source,
+			target,
+		})
+		.collect()
+}
+
+/// Compares the deployed file at `target` with its record and with
+/// `source`.
+fn file_status(
+	source: &Path,
+	target: &Path,
+	owned: &Ownership,
+) -> LinkStatusKind {
+	let Ok(meta) = fs::symlink_metadata(target) else {
+		return LinkStatusKind::Missing;
+	};
+	let Some((_, record)) = owned.deployed(target) else {
+		return LinkStatusKind::Unmanaged;
+	};
+	if !meta.is_file() {
+		return LinkStatusKind::ModifiedLocally;
+	}
+	if record.deploy == Deploy::Hardlink && same_file(source, target) {
+		return LinkStatusKind::Ok;
+	}
+	match hash_file(target) {
+		Ok(hash) if hash == record.hash => {}
+		_ => return LinkStatusKind::ModifiedLocally,
+	}
+	let in_sync = record.deploy == Deploy::Copy &&
+		hash_file(source).is_ok_and(|hash| hash == record.hash);
+	if in_sync {
+		LinkStatusKind::Ok
+	} else {
+		LinkStatusKind::SourceUpdated
+	}
+}
+
+/// Deploys every file of `src` below `dst`.
+///
+/// Missing files are created and files whose source changed are refreshed.
+/// A file modified locally is only replaced if a conflict strategy was
+/// `requested` for this run; other existing files are handled with
+/// `strategy`, unless they already hold the right content.
+pub(super) fn deploy(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	deploy: Deploy,
+	requested: Option<ConflictStrategy>,
+	strategy: ConflictStrategy,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	// The package used to be symlinked.
+	if fs::symlink_metadata(dst).is_ok_and(|m| m.file_type().is_symlink()) &&
+		tx.owner(dst)
+			.is_some_and(|owner| Some(owner) == tx.package())
+	{
+		tx.remove_symlink(dst)?;
+	}
+
+	for (source, target) in deploy_pairs(src, dst, rules) {
+		let place = match file_status(&source, &target, tx.ownership()) {
+			LinkStatusKind::Missing => true,
+			LinkStatusKind::Ok => {
+				tx.claim_file(&source, &target, deploy)?;
+				false
+			}
+			LinkStatusKind::SourceUpdated => {
+				tx.discard(&target)?;
+				true
+			}
+			LinkStatusKind::ModifiedLocally => {
+				let Some(requested) = requested else {
+					return Err(LinkError::Conflict(format!(
+						"{} was modified locally; pass --conflict-strategy to \
+						 replace or adopt it",
+						target.display()
+					)));
+				};
+				handle_conflict(&source, &target, requested, tx)?
+			}
+			_ if holds(&source, &target, deploy) => {
+				tx.claim_file(&source, &target, deploy)?;
+				false
+			}
+			_ => handle_conflict(&source, &target, strategy, tx)?,
+		};
+		if !place {
+			continue;
+		}
+		if deploy == Deploy::Hardlink {
+			tx.hard_link(&source, &target)?;
+		} else {
+			tx.copy_file(&source, &target)?;
+		}
+	}
+	Ok(())
+}
+
+/// Removes the files `package` deployed below `dst`, leaving files modified
+/// locally in place, then the directories it created that are left empty.
+pub(super) fn undeploy(
+	package: &str,
+	dst: &Path,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let Some(owned) = tx.ownership().packages.get(package) else {
+		return Ok(());
+	};
+	let stash_dir = dst.parent().unwrap_or(dst);
+	let files: Vec<_> = owned
+		.files
+		.iter()
+		.filter(|(path, _)| path.starts_with(dst))
+		.map(|(path, file)| (path.clone(), file.clone()))
+		.collect();
+	let mut dirs: Vec<_> = owned
+		.dirs
+		.iter()
+		.filter(|dir| dir.starts_with(dst))
+		.cloned()
+		.collect();
+
+	for (path, file) in files {
+		// Stashed outside `dst` so its directories can go.
+		remove_deployed(&path, &file, stash_dir, tx)?;
+	}
+
+	// Deepest first, so parents are empty by the time they are reached.
+	dirs.sort_unstable_by(|a, b| b.cmp(a));
+	for dir in dirs {
+		let empty = fs::read_dir(&dir).is_ok_and(|mut e| e.next().is_none());
+		if empty {
+			tx.remove_dir(&dir)?;
+		} else {
+			debug!("keeping {}, which is not empty", dir.display());
+		}
+	}
+	Ok(())
+}
+
+/// Removes the file deployed at `path` as `file` records, keeping it in
+/// `stash_dir` until the transaction commits. A file modified locally is left
+/// in place and forgotten.
+pub fn remove_deployed(
+	path: &Path,
+	file: &DeployedFile,
+	stash_dir: &Path,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let unchanged = fs::symlink_metadata(path).is_ok_and(|m| m.is_file()) &&
+		((file.deploy == Deploy::Hardlink && same_file(&file.source, path)) ||
+			hash_file(path).is_ok_and(|hash| hash == file.hash));
+	if unchanged {
+		return tx.discard_into(path, stash_dir);
+	}
+	if fs::symlink_metadata(path).is_ok() {
+		warn!(
+			"{} was modified locally, leaving it in place",
+			path.display()
+		);
+	}
+	tx.forget(path);
+	Ok(())
+}
+
+/// Places the file `file` records at `path` again, provided its source still
+/// has the content recorded, and returns whether it did. Other files in the
+/// way are handled with `strategy`.
+pub fn restore_deployed(
+	path: &Path,
+	file: &DeployedFile,
+	strategy: ConflictStrategy,
+	tx: &mut Transaction,
+) -> Result<bool, LinkError> {
+	let exists = fs::symlink_metadata(path).is_ok();
+	if exists && hash_file(path).is_ok_and(|hash| hash == file.hash) {
+		tx.claim_file(&file.source, path, file.deploy)?;
+		return Ok(true);
+	}
+	if !hash_file(&file.source).is_ok_and(|hash| hash == file.hash) {
+		warn!(
+			"{} has changed since, not restoring {}",
+			file.source.display(),
+			path.display()
+		);
+		return Ok(false);
+	}
+	if exists && !handle_conflict(&file.source, path, strategy, tx)? {
+		return Ok(false);
+	}
+	if file.deploy == Deploy::Hardlink {
+		tx.hard_link(&file.source, path)?;
+	} else {
+		tx.copy_file(&file.source, path)?;
+	}
+	Ok(true)
+}
+
+/// Whether `target` already holds what deploying `source` would put there.
+fn holds(source: &Path, target: &Path, deploy: Deploy) -> bool {
+	if !fs::symlink_metadata(target).is_ok_and(|m| m.is_file()) {
+		return false;
+	}
+	match deploy {
+		Deploy::Hardlink => same_file(source, target),
+		_ => matches!(
+			(hash_file(source), hash_file(target)),
+			(Ok(a), Ok(b)) if a == b
+		),
+	}
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+	use std::os::unix::fs::MetadataExt;
+	match (fs::metadata(a), fs::metadata(b)) {
+		(Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+		_ => false,
+	}
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &Path, _: &Path) -> bool {
+	false
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::config::{
+		Config,
+		Package,
+	};
+
+	#[test]
+	fn copies_track_local_edits_and_source_updates() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src");
+		let dst = tmp.path().join("dst");
+		fs::create_dir_all(src.join("sub")).unwrap();
+		fs::write(src.join("a"), "a").unwrap();
+		fs::write(src.join("sub/b"), "b").unwrap();
+		let rules =
+			IgnoreRules::new(&Config::default(), &Package::default(), &src);
+		let strategy = ConflictStrategy::Backup;
+		let mut tx = Transaction::new(None, false);
+		tx.set_package(Some("pkg"));
+		let statuses = |tx: &Transaction| -> Vec<_> {
+			deploy_status(&src, &dst, &rules, tx.ownership())
+				.into_iter()
+				.map(|f| f.status)
+				.collect()
+		};
+
+		deploy(&src, &dst, &rules, Deploy::Copy, None, strategy, &mut tx)
+			.unwrap();
+		assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "b");
+		assert_eq!(statuses(&tx), [LinkStatusKind::Ok, LinkStatusKind::Ok]);
+
+		fs::write(src.join("a"), "new a").unwrap();
+		fs::write(dst.join("sub/b"), "local b").unwrap();
+		assert_eq!(statuses(&tx), [
+			LinkStatusKind::SourceUpdated,
+			LinkStatusKind::ModifiedLocally
+		]);
+		let refused =
+			deploy(&src, &dst, &rules, Deploy::Copy, None, strategy, &mut tx);
+		assert!(matches!(refused, Err(LinkError::Conflict(_))));
+		assert_eq!(fs::read_to_string(dst.join("a")).unwrap(), "new a");
+
+		let skip = Some(ConflictStrategy::Skip);
+		deploy(&src, &dst, &rules, Deploy::Copy, skip, strategy, &mut tx)
+			.unwrap();
+		assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "local b");
+
+		undeploy("pkg", &dst, &mut tx).unwrap();
+		assert!(!dst.join("a").exists());
+		assert!(dst.join("sub/b").exists());
+		assert!(
+			tx.ownership()
+				.packages
+				.get("pkg")
+				.is_none_or(|owned| { owned.files.is_empty() })
+		);
+		tx.commit().unwrap();
+	}
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// Errors that can occur while resolving, linking or removing packages.
+#[derive(Debug, Error)]
+pub enum LinkError {
+	/// The resolved source path of a package does not exist.
+	#[error("source missing: {}", .0.display())]
+	SourceMissing(PathBuf),
+	/// A conflicting file exists at the target path and was not resolved.
+	#[error("conflict: {0}")]
+	Conflict(String),
+	/// A package would touch a path the `[policy]` section rules out.
+	#[error("package '{package}': {} {reason}", .path.display())]
+	Policy {
+		package: String,
+		path:    PathBuf,
+		reason:  String,
+	},
+	/// The version asked for a package was not found in the source directory.
+	#[error(
+		"package '{package}' has no version '{version}' ({}; see `rk vsc list {package}`)",
+		if .available.is_empty() {
+			"none were found in the source directory".to_string()
+		} else {
+			format!("available: {}", .available.join(", "))
+		}
+	)]
+	UnknownVersion {
+		package:   String,
+		version:   String,
+		available: Vec<String>,
+	},
+	/// The user chose to abort at a conflict prompt.
+	#[error("aborted by user")]
+	Aborted,
+	/// Another run holds the transaction lock.
+	#[error("another rinkle run is in progress ({} is locked)", .0.display())]
+	Locked(PathBuf),
+	/// The run was interrupted by Ctrl-C and has been rolled back.
+	#[error("interrupted by user")]
+	Interrupted,
+	/// The transaction journal could not be read or written.
+	#[error("journal error: {0}")]
+	Journal(#[from] serde_json::Error),
+	/// A file could not be moved into the backup store.
+	#[error("backup error: {0}")]
+	Backup(#[from] crate::backup::BackupError),
+	/// The ownership database could not be read or written.
+	#[error("ownership database error: {0}")]
+	Ownership(#[from] crate::ownership::OwnershipError),
+	/// An I/O error occurred while touching the filesystem.
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+}
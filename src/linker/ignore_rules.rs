@@ -0,0 +1,213 @@
+//! Gitignore-style filtering of package sources.
+//!
+//! Patterns come from three places, applied in increasing order of precedence:
+//! `global.ignore` (relative to `global.source_dir`), the package's own
+//! `ignore` list (relative to the package source) and `.rinkleignore` files,
+//! where a file deeper in the tree overrides the ones above it. Within each
+//! set the last matching pattern wins, so `!pattern` re-includes a path.
+
+use std::{
+	cell::RefCell,
+	collections::HashMap,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use ignore::{
+	Match,
+	gitignore::{
+		Gitignore,
+		GitignoreBuilder,
+	},
+};
+use tracing::warn;
+
+use super::{
+	source_root,
+	tree::is_real_dir,
+};
+use crate::config::{
+	Config,
+	Package,
+};
+
+/// Name of the per-directory ignore file. It is never linked itself.
+const IGNORE_FILE: &str = ".rinkleignore";
+
+/// The ignore rules that apply to one package.
+pub(super) struct IgnoreRules {
+	/// `global.ignore` followed by the package's `ignore` list.
+	matchers:    Vec<Gitignore>,
+	/// `.rinkleignore` files are looked up in every directory between here and
+	/// the path being checked.
+	source_root: PathBuf,
+	/// Parsed `.rinkleignore` files by directory.
+	nested:      RefCell<HashMap<PathBuf, Option<Gitignore>>>,
+	/// Results of [`IgnoreRules::ignores_within`] by directory.
+	within:      RefCell<HashMap<PathBuf, bool>>,
+}
+
+impl IgnoreRules {
+	pub(super) fn new(cfg: &Config, pkg: &Package, source: &Path) -> Self {
+		let source_root = source_root(cfg);
+		let matchers = vec![
+			build(&source_root, &cfg.global.ignore),
+			build(source, &pkg.ignore),
+		];
+		Self {
+			matchers,
+			source_root,
+			nested: RefCell::default(),
+			within: RefCell::default(),
+		}
+	}
+
+	/// Whether `path` is excluded from linking.
+	pub(super) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+		if path.file_name().is_some_and(|n| n == IGNORE_FILE) {
+			return true;
+		}
+
+		let mut ignored = false;
+		for matcher in &self.matchers {
+			if path.starts_with(matcher.path()) {
+				apply(&mut ignored, &matcher.matched(path, is_dir));
+			}
+		}
+
+		let Some(parent) = path.parent() else {
+			return ignored;
+		};
+		let mut dirs: Vec<_> = parent
+			.ancestors()
+			.take_while(|d| d.starts_with(&self.source_root))
+			.collect();
+		dirs.reverse();
+		for dir in dirs {
+			if let Some(matcher) = self.nested_matcher(dir) {
+				apply(&mut ignored, &matcher.matched(path, is_dir));
+			}
+		}
+		ignored
+	}
+
+	/// Whether anything below `dir` is excluded, in which case `dir` must be
+	/// mirrored rather than linked as a whole.
+	///
+	/// The answer is remembered for every directory, so walking a tree and
+	/// asking at each level reads every directory once.
+	pub(super) fn ignores_within(&self, dir: &Path) -> bool {
+		if let Some(&found) = self.within.borrow().get(dir) {
+			return found;
+		}
+		let found = fs::read_dir(dir).is_ok_and(|entries| {
+			entries.flatten().any(|entry| {
+				let path = entry.path();
+				let is_dir = is_real_dir(&path);
+				self.is_ignored(&path, is_dir) ||
+					(is_dir && self.ignores_within(&path))
+			})
+		});
+		self.within.borrow_mut().insert(dir.to_path_buf(), found);
+		found
+	}
+
+	fn nested_matcher(&self, dir: &Path) -> Option<Gitignore> {
+		self.nested
+			.borrow_mut()
+			.entry(dir.to_path_buf())
+			.or_insert_with(|| {
+				let file = dir.join(IGNORE_FILE);
+				if !file.is_file() {
+					return None;
+				}
+				let mut builder = GitignoreBuilder::new(dir);
+				if let Some(e) = builder.add(&file) {
+					warn!("{}: {e}", file.display());
+				}
+				builder.build().ok()
+			})
+			.clone()
+	}
+}
+
+fn build(root: &Path, patterns: &[String]) -> Gitignore {
+	let mut builder = GitignoreBuilder::new(root);
+	for pattern in patterns {
+		if let Err(e) = builder.add_line(None, pattern) {
+			warn!("invalid ignore pattern '{pattern}': {e}");
+		}
+	}
+	builder.build().unwrap_or_else(|e| {
+		warn!("ignore patterns for {} disabled: {e}", root.display());
+		Gitignore::empty()
+	})
+}
+
+/// A later match overrides an earlier one; no match keeps the earlier result.
+const fn apply<T>(ignored: &mut bool, m: &Match<T>) {
+	match m {
+		Match::None => {}
+		Match::Ignore(_) => *ignored = true,
+		Match::Whitelist(_) => *ignored = false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::config::Global;
+
+	#[test]
+	fn gitignore_semantics() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path();
+		let pkg_dir = src.join("nvim");
+		fs::create_dir_all(pkg_dir.join("lua/plugins")).unwrap();
+		fs::write(pkg_dir.join(IGNORE_FILE), "*.swp\n").unwrap();
+		fs::write(pkg_dir.join("lua/plugins/.rinkleignore"), "!keep.swp\n")
+			.unwrap();
+
+		let cfg = Config {
+			global: Global {
+				source_dir: Some(src.display().to_string()),
+				ignore: vec![
+					".git/".into(),
+					"README.md".into(),
+					"/nvim/local.lua".into(),
+				],
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let pkg = Package {
+			ignore: vec!["*.log".into(), "!keep.log".into()],
+			..Default::default()
+		};
+		let rules = IgnoreRules::new(&cfg, &pkg, &pkg_dir);
+
+		// directory-only patterns
+		assert!(rules.is_ignored(&pkg_dir.join(".git"), true));
+		assert!(!rules.is_ignored(&pkg_dir.join(".git"), false));
+		// unanchored patterns match at any depth, anchored ones only at root
+		assert!(rules.is_ignored(&pkg_dir.join("lua/README.md"), false));
+		assert!(rules.is_ignored(&pkg_dir.join("local.lua"), false));
+		assert!(!rules.is_ignored(&pkg_dir.join("lua/local.lua"), false));
+		// negation within the package list
+		assert!(rules.is_ignored(&pkg_dir.join("debug.log"), false));
+		assert!(!rules.is_ignored(&pkg_dir.join("keep.log"), false));
+		// nested .rinkleignore files override their parents
+		assert!(rules.is_ignored(&pkg_dir.join("lua/init.swp"), false));
+		assert!(
+			!rules.is_ignored(&pkg_dir.join("lua/plugins/keep.swp"), false)
+		);
+		assert!(rules.is_ignored(&pkg_dir.join(IGNORE_FILE), false));
+
+		assert!(rules.ignores_within(&pkg_dir));
+	}
+}
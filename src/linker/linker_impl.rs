@@ -1,294 +1,1045 @@
-// // linker_impl.rs
-// // Actual implementation moved here for clarity and to allow for public
+// linker_impl.rs
+// Actual implementation moved here for clarity and to allow for public
 // re-exports.
 
-// use std::path::{Path, PathBuf};
-// #[cfg(unix)]
-// use std::os::unix::fs::symlink as unix_symlink;
-// #[cfg(windows)]
-// use std::os::windows::fs::{symlink_file, symlink_dir};
-// /// 安全验证：防止符号链接指向系统关键目录
-// fn validate_symlink_safety(_source: &Path, target: &Path) -> Result<(),
-// Error> {     // If target does not yet exist (normal case before creating a
-// symlink), skip strict check.     let canonical_target = match
-// target.canonicalize() {         Ok(p) => p,
-//         Err(e) => {
-//             if e.kind() == std::io::ErrorKind::NotFound { return Ok(()); }
-//             return Err(Error::InvalidConfig(format!("Invalid target path:
-// {}", e)));         }
-//     };
-//     let forbidden_paths = ["/etc", "/bin", "/sbin", "/usr/bin", "/usr/sbin"];
-//     for forbidden in &forbidden_paths {
-//         if canonical_target.starts_with(forbidden) {
-//             return Err(Error::InvalidConfig(
-//                 format!("Cannot link to system directory: {}",
-// canonical_target.display())             ));
-//         }
-//     }
-//     Ok(())
-// }
-
-// /// 跨平台符号链接创建
-// fn create_symlink(src: &Path, dst: &Path) -> Result<(), Error> {
-//     #[cfg(unix)]
-//     {
-//         unix_symlink(src, dst)?;
-//     }
-//     #[cfg(windows)]
-//     {
-//         if src.is_dir() {
-//             symlink_dir(src, dst)?;
-//         } else {
-//             symlink_file(src, dst)?;
-//         }
-//     }
-//     Ok(())
-// }
-
-// use crate::error::Error;
-
-// #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
-// pub enum LinkStatusKind {
-//     Ok,
-//     BrokenSymlink,
-//     NotSymlink,
-//     Missing,
-// }
-
-// #[derive(Debug, Clone, serde::Serialize)]
-// pub struct LinkStatus {
-//     pub package: String,
-//     pub target: PathBuf,
-//     pub kind: LinkStatusKind,
-// }
-
-// pub fn status_package(
-//     pkg_name: &str,
-//     pkg: &crate::config::entity::Package,
-//     cfg: &crate::config::entity::Config,
-//     state: &crate::state::State,
-// ) -> Result<LinkStatus, Error> {
-//     let source_root = cfg.global.source_dir.as_deref().unwrap_or(".");
-//     let target_root = cfg.global.target_dir.as_deref().unwrap_or(".");
-//     let (source_dir, target_dir) = resolve_paths(pkg_name, pkg, source_root,
-// target_root, cfg, state)?;
-
-//     // Determine kind
-//     let kind = if !target_dir.exists() {
-//         LinkStatusKind::Missing
-//     } else if target_dir.is_symlink() {
-//         match target_dir.read_link() {
-//             Ok(dest) => {
-//                 if dest == source_dir { LinkStatusKind::Ok } else {
-// LinkStatusKind::BrokenSymlink }             }
-//             Err(_) => LinkStatusKind::BrokenSymlink,
-//         }
-//     } else {
-//         LinkStatusKind::NotSymlink
-//     };
-
-//     Ok(LinkStatus { package: pkg_name.to_string(), target: target_dir, kind
-// }) }
-
-// pub fn link_package(
-//     name: &str,
-//     pkg: &crate::config::entity::Package,
-//     cfg: &crate::config::entity::Config,
-//     state: &crate::state::State,
-//     _ver: Option<&str>,
-//     dry_run: bool,
-// ) -> Result<(), Error> {
-//     let source_root = cfg.global.source_dir.as_deref().unwrap_or(".");
-//     let target_root = cfg.global.target_dir.as_deref().unwrap_or(".");
-//     let (source_dir, target_dir) = resolve_paths(name, pkg, source_root,
-// target_root, cfg, state)?;
-
-//     if !source_dir.exists() {
-//         return Err(Error::InvalidConfig(format!("source missing: {}",
-// source_dir.display())));     }
-
-//     std::fs::create_dir_all(target_dir.parent().unwrap())?;
-
-//     // 路径安全验证
-//     validate_symlink_safety(&source_dir, &target_dir)?;
-
-//     if target_dir.exists() {
-//         if target_dir.is_symlink() {
-//             // Already a symlink
-//             if target_dir.read_link()? == source_dir { return Ok(()); }
-//             // points elsewhere -> remove
-//             if !dry_run { std::fs::remove_file(&target_dir)?; }
-//         } else {
-//             // conflict
-//             handle_conflict(&target_dir, cfg.global.conflict_strategy,
-// dry_run)?;         }
-//     }
-
-//     if !dry_run {
-//         create_symlink(&source_dir, &target_dir)?;
-//     }
-//     Ok(())
-// }
-
-// pub fn remove_package(
-//     name: &str,
-//     pkg: &crate::config::entity::Package,
-//     cfg: &crate::config::entity::Config,
-//     state: &crate::state::State,
-//     _ver: Option<&str>,
-//     dry_run: bool,
-// ) -> Result<(), Error> {
-//     let source_root = cfg.global.source_dir.as_deref().unwrap_or(".");
-//     let target_root = cfg.global.target_dir.as_deref().unwrap_or(".");
-//     let (_source_dir, target_dir) = resolve_paths(name, pkg, source_root,
-// target_root, cfg, state)?;     if target_dir.exists() &&
-// target_dir.is_symlink() {         if !dry_run {
-// std::fs::remove_file(target_dir)?; }     }
-//     Ok(())
-// }
-
-// /// Expand a path that may contain `~` or environment variables.
-// ///
-// /// Currently supports:
-// /// - `~` / `~/sub/path` -> user's home directory
-// /// - `$VAR` environment variable prefixes
-// pub fn expand_path<P: AsRef<str>>(p: P) -> PathBuf {
-//     let raw = p.as_ref();
-//     // Use shellexpand for simplicity (already a dependency via Cargo.toml)
-//     let expanded = shellexpand::full(raw).unwrap_or_else(|_|
-// std::borrow::Cow::Borrowed(raw));     Path::new(expanded.as_ref()).
-// to_path_buf() }
-
-// fn resolve_paths(
-//     name: &str,
-//     pkg: &crate::config::entity::Package,
-//     source_root: &str,
-//     target_root: &str,
-//     cfg: &crate::config::entity::Config,
-//     state: &crate::state::State,
-// ) -> Result<(PathBuf, PathBuf), Error> {
-//     let version = pick_version(name, pkg, cfg, state).ok();
-//     let base_source = pkg.source.as_deref().unwrap_or(name);
-//     let mut source_dir = expand_path(format!("{}/{}", source_root,
-// base_source));     if let Some(ver) = version {
-//         // versioned directory convention: name@version
-//         let ver_path = expand_path(format!("{}/{}@{}", source_root, name,
-// ver));         if ver_path.exists() { source_dir = ver_path; }
-//     }
-//     let target_name = pkg.target.as_deref().unwrap_or_else(|| name);
-//     let target_dir = expand_path(format!("{}/{}", target_root, target_name));
-//     Ok((source_dir, target_dir))
-// }
-
-// fn pick_version(
-//     name: &str,
-//     pkg: &crate::config::entity::Package,
-//     cfg: &crate::config::entity::Config,
-//     state: &crate::state::State,
-// ) -> Result<String, Error> {
-//     if let Some(pin) = state.pinned_versions.get(name) { return
-// Ok(pin.clone()); }     if let Some(p) = pkg.default_version.clone() { return
-// Ok(p); }     if let Some(p) = cfg.vsc.default_version.clone() { return Ok(p);
-// }     Err(Error::Version(format!("no version for {name}")))
-// }
-
-// fn handle_conflict(path: &Path, strat:
-// crate::config::entity::ConflictStrategy, dry_run: bool) -> Result<(), Error>
-// {     use crate::config::entity::ConflictStrategy::*;
-//     match strat {
-//         Skip => { /* do nothing */ },
-//         Overwrite => { if !dry_run { if path.is_dir() {
-// std::fs::remove_dir_all(path)?; } else { std::fs::remove_file(path)?; } } },
-//         Backup => {
-//             if !dry_run {
-//                 let mut backup = path.to_path_buf();
-//                 backup.set_extension("bak");
-//                 std::fs::rename(path, backup)?;
-//             }
-//         },
-//         Prompt => {
-//             // simple prompt fallback
-//             if atty::is(atty::Stream::Stdin) {
-//                 eprint!("conflict at {} overwrite? [y/N] ", path.display());
-//                 use std::io::{Read, Write};
-//                 std::io::stdout().flush().ok();
-//                 let mut buf = [0u8; 1];
-//                 if std::io::stdin().read(&mut buf).ok().filter(|&n|
-// n>0).map(|_| buf[0] == b'y' || buf[0] == b'Y').unwrap_or(false) {
-// if !dry_run { if path.is_dir() { std::fs::remove_dir_all(path)?; } else {
-// std::fs::remove_file(path)?; } }                 } else {
-//                     return Err(Error::Conflict(format!("user skipped {}",
-// path.display())));                 }
-//             } else {
-//                 return Err(Error::Conflict("prompt strategy not usable in
-// non-interactive mode".into()));             }
-//         }
-//     }
-//     Ok(())
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use crate::{config::entity::{Config, Global, Package, ConflictStrategy,
-// Vsc}, state::State};     use tempfile::tempdir;
-
-//     fn base_cfg(source: &str, target: &str) -> Config {
-//         Config {
-//             global: Global {
-//                 source_dir: Some(source.to_string()),
-//                 target_dir: Some(target.to_string()),
-//                 conflict_strategy: ConflictStrategy::Backup,
-//                 ignore: vec![],
-//             },
-//             vsc: Vsc { template: None, default_version: None },
-//             profiles: Default::default(),
-//             packages: Default::default(),
-//         }
-//     }
-
-//     #[test]
-//     fn status_missing_then_ok() {
-//         let src = tempdir().unwrap();
-//         let tgt = tempdir().unwrap();
-//         std::fs::create_dir_all(src.path().join("nvim")).unwrap();
-//         let mut cfg = base_cfg(src.path().to_str().unwrap(),
-// tgt.path().to_str().unwrap());         cfg.packages.insert("nvim".into(),
-// Package { ..Default::default() });         let state = State::default();
-
-//         let st1 = status_package("nvim", cfg.packages.get("nvim").unwrap(),
-// &cfg, &state).unwrap();         assert!(matches!(st1.kind,
-// LinkStatusKind::Missing));
-
-//         link_package("nvim", cfg.packages.get("nvim").unwrap(), &cfg, &state,
-// None, false).unwrap();         let st2 = status_package("nvim",
-// cfg.packages.get("nvim").unwrap(), &cfg, &state).unwrap();         assert!
-// (matches!(st2.kind, LinkStatusKind::Ok));     }
-
-//     #[test]
-//     fn status_not_symlink_and_broken() {
-//         let src = tempdir().unwrap();
-//         let tgt = tempdir().unwrap();
-//         std::fs::create_dir_all(src.path().join("pkg")).unwrap();
-//         let mut cfg = base_cfg(src.path().to_str().unwrap(),
-// tgt.path().to_str().unwrap());         cfg.packages.insert("pkg".into(),
-// Package { ..Default::default() });         let state = State::default();
-
-//         // create regular file at target path
-//         let target_path = tgt.path().join("pkg");
-//         std::fs::write(&target_path, b"data").unwrap();
-//         let st_not = status_package("pkg", cfg.packages.get("pkg").unwrap(),
-// &cfg, &state).unwrap();         assert!(matches!(st_not.kind,
-// LinkStatusKind::NotSymlink));
-
-//         // Replace with symlink pointing elsewhere to simulate broken
-//         std::fs::remove_file(&target_path).unwrap();
-//         let other_dir = src.path().join("other");
-//         std::fs::create_dir_all(&other_dir).unwrap();
-//         std::os::unix::fs::symlink(&other_dir, &target_path).unwrap();
-//         // real source dir for pkg
-//         let _ = &src.path().join("pkg");
-//         let st_broken = status_package("pkg",
-// cfg.packages.get("pkg").unwrap(), &cfg, &state).unwrap();         assert!
-// (matches!(st_broken.kind, LinkStatusKind::BrokenSymlink));     }
-// }
+use std::{
+	collections::BTreeMap,
+	fmt,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use serde::Serialize;
+use tracing::{
+	debug,
+	info,
+	warn,
+};
+
+use super::{
+	IgnoreRules,
+	LinkError,
+	Transaction,
+	adopt,
+	deploy::{
+		deploy,
+		deploy_status,
+		undeploy,
+	},
+	paths::{
+		destination,
+		link_destination,
+		link_value,
+		resolved,
+		same_path,
+	},
+	prompt::{
+		self,
+		Resolution,
+	},
+	tree::{
+		TreeLinker,
+		tree_status,
+	},
+};
+use crate::{
+	config::{
+		Config,
+		ConflictStrategy,
+		Deploy,
+		LinkMode,
+		LinkStyle,
+		Package,
+	},
+	ownership::Ownership,
+	state::State,
+	utils::current_os,
+	vsc::{
+		self,
+		Versions,
+	},
+};
+
+/// The state of a link as observed on the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkStatusKind {
+	/// The target is a symlink resolving to the source.
+	Ok,
+	/// Nothing exists at the target path.
+	Missing,
+	/// The target is a symlink to a path that does not exist.
+	Dangling,
+	/// The target is a symlink to some other existing path.
+	WrongTarget,
+	/// The target exists but is a regular file or directory.
+	#[serde(rename = "not-a-symlink")]
+	NotSymlink,
+	/// The target is a symlink or file that rinkle did not create.
+	Unmanaged,
+	/// A copied or hard-linked target was changed since it was deployed.
+	ModifiedLocally,
+	/// The source of a copied or hard-linked target changed since it was
+	/// deployed.
+	SourceUpdated,
+	/// The resolved source of the package does not exist.
+	SourceMissing,
+	/// Some files of a tree package are linked and others are missing.
+	PartiallyLinked,
+	/// The package does not apply to this operating system.
+	OsFilteredOut,
+}
+
+impl LinkStatusKind {
+	/// Returns the short label used in text and JSON output.
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Ok => "ok",
+			Self::Missing => "missing",
+			Self::Dangling => "dangling",
+			Self::WrongTarget => "wrong-target",
+			Self::NotSymlink => "not-a-symlink",
+			Self::Unmanaged => "unmanaged",
+			Self::ModifiedLocally => "modified-locally",
+			Self::SourceUpdated => "source-updated",
+			Self::SourceMissing => "source-missing",
+			Self::PartiallyLinked => "partially-linked",
+			Self::OsFilteredOut => "os-filtered-out",
+		}
+	}
+
+	/// Whether the target matches the configuration.
+	pub const fn is_in_sync(self) -> bool {
+		matches!(self, Self::Ok | Self::OsFilteredOut)
+	}
+
+	/// Combines the statuses of the files of a package into one.
+	pub(super) fn aggregate(kinds: &[Self]) -> Self {
+		let all = |kind| kinds.iter().all(|k| *k == kind);
+		let any = |kind| kinds.contains(&kind);
+		if all(Self::Ok) {
+			Self::Ok
+		} else if all(Self::Missing) {
+			Self::Missing
+		} else if any(Self::NotSymlink) {
+			Self::NotSymlink
+		} else if any(Self::Unmanaged) {
+			Self::Unmanaged
+		} else if any(Self::ModifiedLocally) {
+			Self::ModifiedLocally
+		} else if any(Self::WrongTarget) {
+			Self::WrongTarget
+		} else if any(Self::SourceUpdated) {
+			Self::SourceUpdated
+		} else if any(Self::Dangling) {
+			Self::Dangling
+		} else {
+			Self::PartiallyLinked
+		}
+	}
+}
+
+/// The link status of a single file of a package.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStatus {
+	pub source: PathBuf,
+	pub target: PathBuf,
+	pub status: LinkStatusKind,
+}
+
+/// The link status of a package.
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkStatus {
+	pub package: String,
+	pub source:  PathBuf,
+	pub target:  PathBuf,
+	pub kind:    LinkStatusKind,
+	/// Every link of the package; a single entry unless it is mirrored.
+	pub files:   Vec<FileStatus>,
+	/// The version used, for versioned packages.
+	pub version: Option<VersionChoice>,
+}
+
+/// Inspects the target path of a package and reports its link status.
+///
+/// Links are only reported as fine if `owned` records them as created by
+/// rinkle.
+///
+/// For a package with `files` mappings, `target` is the deepest directory
+/// holding every mapped target.
+pub fn status_package(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	owned: &Ownership,
+) -> LinkStatus {
+	let (source, _) = resolve_paths(name, pkg, cfg, versions, state, None);
+	let mappings = resolve_mappings(name, pkg, cfg, versions, state, None);
+	let version = choose_version(name, pkg, cfg, versions, state, None);
+	let status = |source: &Path, kind, files| LinkStatus {
+		package: name.to_string(),
+		source: source.to_path_buf(),
+		target: common_ancestor(mappings.iter().map(|(_, t)| t.as_path())),
+		kind,
+		files,
+		version: version.clone(),
+	};
+
+	if !pkg.os.is_empty() && !pkg.os.iter().any(|os| os == current_os()) {
+		return status(&source, LinkStatusKind::OsFilteredOut, Vec::new());
+	}
+	if let Some((missing, _)) = mappings
+		.iter()
+		.find(|(s, _)| fs::symlink_metadata(s).is_err())
+	{
+		return status(missing, LinkStatusKind::SourceMissing, Vec::new());
+	}
+
+	let mut kinds = Vec::new();
+	let mut files = Vec::new();
+	for (source, target) in &mappings {
+		let (kind, mapping_files) =
+			mapping_status(pkg, cfg, source, target, owned);
+		kinds.push(kind);
+		files.extend(mapping_files);
+	}
+	let kind = match kinds[..] {
+		[kind] => kind,
+		_ => LinkStatusKind::aggregate(&kinds),
+	};
+	status(&source, kind, files)
+}
+
+/// The status of one `source -> target` mapping of a package.
+fn mapping_status(
+	pkg: &Package,
+	cfg: &Config,
+	source: &Path,
+	target: &Path,
+	owned: &Ownership,
+) -> (LinkStatusKind, Vec<FileStatus>) {
+	let rules = IgnoreRules::new(cfg, pkg, source);
+	let files = if pkg.deploy != Deploy::Symlink {
+		Some(deploy_status(source, target, &rules, owned))
+	} else if uses_tree(pkg, source, &rules) {
+		Some(tree_status(source, target, &rules, owned))
+	} else {
+		None
+	};
+	if let Some(files) = files {
+		let kind = if files.is_empty() {
+			// Nothing to link: fine as long as the directory exists.
+			if fs::symlink_metadata(target).is_ok() {
+				LinkStatusKind::Ok
+			} else {
+				LinkStatusKind::Missing
+			}
+		} else {
+			let kinds: Vec<_> = files.iter().map(|f| f.status).collect();
+			LinkStatusKind::aggregate(&kinds)
+		};
+		return (kind, files);
+	}
+
+	let kind = file_status(source, target, owned);
+	(kind, vec![FileStatus {
+		source: source.to_path_buf(),
+		target: target.to_path_buf(),
+		status: kind,
+	}])
+}
+
+/// The deepest directory containing all `paths`; the path itself if there
+/// is only one.
+fn common_ancestor<'a>(mut paths: impl Iterator<Item = &'a Path>) -> PathBuf {
+	let Some(first) = paths.next() else {
+		return PathBuf::new();
+	};
+	paths.fold(first.to_path_buf(), |common, path| {
+		common
+			.ancestors()
+			.find(|dir| path.starts_with(dir))
+			.map_or_else(PathBuf::new, Path::to_path_buf)
+	})
+}
+
+/// Reports whether `target` links to `source`.
+///
+/// Absolute and relative links are both fine as long as they resolve to the
+/// source; so is a target reached through a folded parent directory. Links
+/// not recorded in `owned` are reported as unmanaged.
+pub(super) fn file_status(
+	source: &Path,
+	target: &Path,
+	owned: &Ownership,
+) -> LinkStatusKind {
+	let kind = link_kind(source, target);
+	match kind {
+		LinkStatusKind::Ok |
+		LinkStatusKind::WrongTarget |
+		LinkStatusKind::Dangling
+			if !owned.manages(target) =>
+		{
+			LinkStatusKind::Unmanaged
+		}
+		_ => kind,
+	}
+}
+
+fn link_kind(source: &Path, target: &Path) -> LinkStatusKind {
+	match fs::symlink_metadata(target) {
+		Err(_) => LinkStatusKind::Missing,
+		Ok(_) if same_path(target, source) => LinkStatusKind::Ok,
+		Ok(meta) if meta.file_type().is_symlink() => {
+			match link_destination(target) {
+				Ok(dest) if same_path(&dest, source) => LinkStatusKind::Ok,
+				_ if fs::metadata(target).is_ok() => {
+					LinkStatusKind::WrongTarget
+				}
+				_ => LinkStatusKind::Dangling,
+			}
+		}
+		Ok(_) => LinkStatusKind::NotSymlink,
+	}
+}
+
+/// Creates the symlinks for a package.
+///
+/// `version` and `strategy` override the pinned/default version and the
+/// configured conflict strategy for this invocation only; the version is
+/// looked up in `versions`.
+/// All filesystem changes are recorded in `tx`; in dry-run mode they are
+/// printed instead of performed.
+pub fn link_package(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	(version, strategy): (Option<&str>, Option<ConflictStrategy>),
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let mappings = resolve_mappings(name, pkg, cfg, versions, state, version);
+	if let Some((missing, _)) = mappings.iter().find(|(s, _)| !s.exists()) {
+		return Err(LinkError::SourceMissing(missing.clone()));
+	}
+	for (source, target) in &mappings {
+		link_mapping(name, pkg, cfg, source, target, strategy, tx)?;
+	}
+	Ok(())
+}
+
+/// Moves a linked package from the version `old` picks to the one `new`
+/// picks.
+///
+/// Links placed directly at a target are re-pointed atomically. Mirrored
+/// trees and copied files are removed and placed again, since their entries
+/// differ between versions.
+pub fn switch_version(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	(old, new): (&State, &State),
+	strategy: Option<ConflictStrategy>,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let before = resolve_mappings(name, pkg, cfg, versions, old, None);
+	let after = resolve_mappings(name, pkg, cfg, versions, new, None);
+	if let Some((missing, _)) = after.iter().find(|(s, _)| !s.exists()) {
+		return Err(LinkError::SourceMissing(missing.clone()));
+	}
+	for ((old_source, target), (source, _)) in before.iter().zip(&after) {
+		if old_source == source {
+			continue;
+		}
+		let direct = pkg.deploy == Deploy::Symlink &&
+			![old_source, source]
+				.into_iter()
+				.any(|s| uses_tree(pkg, s, &IgnoreRules::new(cfg, pkg, s)));
+		if !direct {
+			remove_mapping(name, pkg, cfg, old_source, target, tx)?;
+		}
+		link_mapping(name, pkg, cfg, source, target, strategy, tx)?;
+	}
+	Ok(())
+}
+
+/// Links one `source -> target` mapping of a package.
+fn link_mapping(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	source: &Path,
+	target: &Path,
+	requested: Option<ConflictStrategy>,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	let strategy = pick_conflict_strategy(pkg, cfg, requested);
+	let style = pick_link_style(pkg, cfg);
+	let rules = IgnoreRules::new(cfg, pkg, source);
+	if pkg.deploy != Deploy::Symlink {
+		deploy(source, target, &rules, pkg.deploy, requested, strategy, tx)?;
+		if !tx.dry_run() {
+			info!("{name}: deployed {}", target.display());
+		}
+		return Ok(());
+	}
+	if uses_tree(pkg, source, &rules) {
+		let whole =
+			link_destination(target).is_ok_and(|to| same_path(&to, source));
+		if pkg.mode == LinkMode::Direct && whole {
+			warn!(
+				"{name}: {} has ignored entries, so the link at {} is \
+				 replaced by a mirror of its files; set mode = \"tree\" to \
+				 make this explicit",
+				source.display(),
+				target.display()
+			);
+		}
+		let tree = TreeLinker::new(cfg, strategy, style, &rules);
+		tree.link(source, target, tx)?;
+		if !tx.dry_run() {
+			info!("{name}: linked tree {}", target.display());
+		}
+		return Ok(());
+	}
+
+	let value = link_value(source, target, style);
+	if let Ok(meta) = fs::symlink_metadata(target) {
+		if meta.file_type().is_symlink() && fs::read_link(target)? == value {
+			debug!("{name}: already linked at {}", target.display());
+			tx.claim(target, &value);
+			return Ok(());
+		}
+		if meta.file_type().is_symlink() && tx.owner(target) == Some(name) {
+			// Another version or the other link style: re-point it in place.
+			tx.replace_symlink(&value, target)?;
+		} else {
+			let cleared = handle_conflict(source, target, strategy, tx)?;
+			if !cleared {
+				return Ok(());
+			}
+			tx.symlink(&value, target)?;
+		}
+	} else {
+		tx.symlink(&value, target)?;
+	}
+
+	if !tx.dry_run() {
+		info!(
+			"{name}: linked {} -> {}",
+			target.display(),
+			source.display()
+		);
+	}
+	Ok(())
+}
+
+/// Removes the symlinks for a package, leaving non-symlink targets untouched.
+pub fn remove_package(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	for (source, target) in
+		resolve_mappings(name, pkg, cfg, versions, state, version)
+	{
+		remove_mapping(name, pkg, cfg, &source, &target, tx)?;
+	}
+	Ok(())
+}
+
+/// Removes one `source -> target` mapping of a package.
+fn remove_mapping(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	source: &Path,
+	target: &Path,
+	tx: &mut Transaction,
+) -> Result<(), LinkError> {
+	if pkg.deploy != Deploy::Symlink {
+		undeploy(name, target, tx)?;
+		if !tx.dry_run() {
+			info!("{name}: removed {}", target.display());
+		}
+		return Ok(());
+	}
+
+	let rules = IgnoreRules::new(cfg, pkg, source);
+	if uses_tree(pkg, source, &rules) {
+		let tree = TreeLinker::new(
+			cfg,
+			pick_conflict_strategy(pkg, cfg, None),
+			pick_link_style(pkg, cfg),
+			&rules,
+		);
+		tree.unlink(source, target, tx)?;
+		if !tx.dry_run() {
+			info!("{name}: removed tree {}", target.display());
+		}
+		return Ok(());
+	}
+
+	match fs::symlink_metadata(target) {
+		Ok(meta) if meta.file_type().is_symlink() => match tx.owner(target) {
+			Some(owner) if owner == name => {
+				tx.remove_symlink(target)?;
+				if !tx.dry_run() {
+					info!("{name}: removed {}", target.display());
+				}
+			}
+			Some(owner) => warn!(
+				"{name}: {} belongs to package '{owner}', leaving it in place",
+				target.display()
+			),
+			None => warn!(
+				"{name}: {} exists but is not managed by rinkle, leaving it \
+				 in place",
+				target.display()
+			),
+		},
+		Ok(_) => {
+			warn!(
+				"{name}: {} is not a symlink, leaving it in place",
+				target.display()
+			);
+		}
+		Err(_) => debug!("{name}: nothing to remove at {}", target.display()),
+	}
+	Ok(())
+}
+
+/// Whether a package is mirrored file by file rather than linked as a whole.
+///
+/// Besides explicit tree mode, a directory package containing ignored entries
+/// is mirrored so that those entries never show up in the target.
+pub(super) fn uses_tree(
+	pkg: &Package,
+	source: &Path,
+	rules: &IgnoreRules,
+) -> bool {
+	source.is_dir() &&
+		(pkg.mode == LinkMode::Tree || rules.ignores_within(source))
+}
+
+/// Expand a path that may contain `~` or environment variables.
+///
+/// Currently supports:
+/// - `~` / `~/sub/path` -> user's home directory
+/// - `$VAR` environment variable prefixes
+pub fn expand_path<P: AsRef<str>>(p: P) -> PathBuf {
+	let raw = p.as_ref();
+	let expanded =
+		shellexpand::full(raw).unwrap_or(std::borrow::Cow::Borrowed(raw));
+	PathBuf::from(expanded.as_ref())
+}
+
+/// Returns the expanded `global.source_dir`.
+pub fn source_root(cfg: &Config) -> PathBuf {
+	expand_path(cfg.global.source_dir.as_deref().unwrap_or("."))
+}
+
+/// Returns the expanded `global.target_dir`.
+pub fn target_root(cfg: &Config) -> PathBuf {
+	expand_path(cfg.global.target_dir.as_deref().unwrap_or("."))
+}
+
+/// Resolves the `(source, target)` pair of a package.
+///
+/// Relative `source`/`target` overrides are joined onto `global.source_dir`
+/// and `global.target_dir`; absolute ones (after expansion) are used as-is.
+/// If [`choose_version`] chooses a version, its directory takes precedence
+/// over the unversioned source.
+pub fn resolve_paths(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> (PathBuf, PathBuf) {
+	let source_root = source_root(cfg);
+	let target_root = target_root(cfg);
+
+	let mut source =
+		source_root.join(expand_path(pkg.source.as_deref().unwrap_or(name)));
+	if let Some(dir) = choose_version(name, pkg, cfg, versions, state, version)
+		.and_then(|choice| choice.dir)
+	{
+		source = dir;
+	}
+
+	let target =
+		target_root.join(expand_path(pkg.target.as_deref().unwrap_or(name)));
+	(source, target)
+}
+
+/// Resolves the `(source, target)` pairs a package places.
+///
+/// Without `files` this is the single pair of [`resolve_paths`]. Each entry of
+/// `files` maps a path relative to the package source to a path relative to
+/// `global.target_dir`; absolute paths (after expansion) are used as-is.
+pub fn resolve_mappings(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Vec<(PathBuf, PathBuf)> {
+	let (source, target) =
+		resolve_paths(name, pkg, cfg, versions, state, version);
+	if pkg.files.is_empty() {
+		return vec![(source, target)];
+	}
+	pkg.files
+		.iter()
+		.zip(resolve_targets(name, pkg, cfg))
+		.map(|(file, target)| (source.join(expand_path(&file.source)), target))
+		.collect()
+}
+
+/// The targets of [`resolve_mappings`], which do not depend on the version.
+pub(super) fn resolve_targets(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+) -> Vec<PathBuf> {
+	let target_root = target_root(cfg);
+	if pkg.files.is_empty() {
+		let target = pkg.target.as_deref().unwrap_or(name);
+		return vec![target_root.join(expand_path(target))];
+	}
+	pkg.files
+		.iter()
+		.map(|file| target_root.join(expand_path(&file.target)))
+		.collect()
+}
+
+/// Where the version spec of a package came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionOrigin {
+	/// Given on the command line as `pkg@version`.
+	Requested,
+	/// Pinned with `rk vsc`.
+	Pinned,
+	/// The package's `default_version`.
+	PackageDefault,
+	/// `vsc.default_version`.
+	GlobalDefault,
+}
+
+impl fmt::Display for VersionOrigin {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Requested => "requested",
+			Self::Pinned => "pinned",
+			Self::PackageDefault => "default",
+			Self::GlobalDefault => "vsc.default_version",
+		})
+	}
+}
+
+/// The version chosen for a package, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChoice {
+	/// The spec asked for.
+	pub spec:     String,
+	pub origin:   VersionOrigin,
+	/// The discovered version used, if any.
+	pub version:  Option<String>,
+	/// The entry of the fallback chain used because `spec` was not found.
+	pub fallback: Option<String>,
+	/// The directory of `version`.
+	#[serde(skip)]
+	pub dir:      Option<PathBuf>,
+}
+
+impl fmt::Display for VersionChoice {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let Self {
+			spec,
+			origin,
+			version,
+			fallback,
+			..
+		} = self;
+		match (version, fallback) {
+			(None, _) => write!(f, "unversioned ({origin} {spec} not found)"),
+			(Some(version), Some(_)) => {
+				write!(f, "{version} (fallback, {origin} {spec} not found)")
+			}
+			(Some(version), None) if version == spec => {
+				write!(f, "{version} ({origin})")
+			}
+			(Some(version), None) => {
+				write!(f, "{version} ({origin} as {spec})")
+			}
+		}
+	}
+}
+
+/// Picks the version spec of a package to use, and where it came from.
+///
+/// Precedence: explicit override > pinned version in state > package default
+/// > `vsc.default_version`.
+///
+/// The spec may be an alias, `latest` or a range; [`choose_version`] resolves
+/// it.
+pub fn pick_version(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	state: &State,
+	version: Option<&str>,
+) -> Option<(String, VersionOrigin)> {
+	let picked = |spec: Option<&String>, origin| Some((spec?.clone(), origin));
+	version
+		.map(|spec| (spec.to_string(), VersionOrigin::Requested))
+		.or_else(|| {
+			picked(state.pinned_versions.get(name), VersionOrigin::Pinned)
+		})
+		.or_else(|| {
+			picked(pkg.default_version.as_ref(), VersionOrigin::PackageDefault)
+		})
+		.or_else(|| {
+			picked(
+				cfg.vsc.default_version.as_ref(),
+				VersionOrigin::GlobalDefault,
+			)
+		})
+}
+
+/// Chooses the version of a package among `versions`: the one
+/// [`pick_version`] asks for, or else the first entry of the package's
+/// `fallback` (or `vsc.fallback`) that is found. A version given on the command
+/// line never falls back, and none does with `vsc.strict`.
+///
+/// Returns `None` for packages without a spec, and for those with no
+/// versions at all when the spec is only `vsc.default_version`.
+pub fn choose_version(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Option<VersionChoice> {
+	let (spec, origin) = pick_version(name, pkg, cfg, state, version)?;
+	if origin == VersionOrigin::GlobalDefault &&
+		versions.of(name, pkg).is_none()
+	{
+		return None;
+	}
+	let resolve = |spec: &str| {
+		versions
+			.resolve(name, pkg, spec, &cfg.vsc.aliases)
+			.map(|(version, dir)| (version.to_string(), dir.to_path_buf()))
+	};
+	let mut choice = VersionChoice {
+		spec,
+		origin,
+		version: None,
+		fallback: None,
+		dir: None,
+	};
+	let found = resolve(&choice.spec).or_else(|| {
+		if cfg.vsc.strict || origin == VersionOrigin::Requested {
+			return None;
+		}
+		let chain = pkg.fallback.as_ref().unwrap_or(&cfg.vsc.fallback);
+		chain.iter().find_map(|entry| {
+			let found = resolve(entry)?;
+			choice.fallback = Some(entry.clone());
+			Some(found)
+		})
+	});
+	if let Some((version, dir)) = found {
+		choice.version = Some(version);
+		choice.dir = Some(dir);
+	}
+	Some(choice)
+}
+
+/// The version [`choose_version`] chooses, or the spec asked for if it
+/// chooses none.
+pub fn selected_version(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Option<String> {
+	choose_version(name, pkg, cfg, versions, state, version)
+		.map(|choice| choice.version.unwrap_or(choice.spec))
+}
+
+/// Makes sure the version asked for a package exists, or that a fallback
+/// was found for it.
+///
+/// `vsc.default_version` applies to every package, so unless `vsc.strict` is
+/// set, packages without it use their unversioned source instead.
+pub fn check_version(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Result<(), LinkError> {
+	let Some(choice) = choose_version(name, pkg, cfg, versions, state, version)
+	else {
+		return Ok(());
+	};
+	match (&choice.version, &choice.fallback) {
+		(Some(_), None) => return Ok(()),
+		(Some(version), Some(_)) => {
+			warn!(
+				"{name}: {} version '{}' not found, falling back to \
+				 '{version}'",
+				choice.origin, choice.spec
+			);
+			return Ok(());
+		}
+		(None, _)
+			if choice.origin == VersionOrigin::GlobalDefault &&
+				!cfg.vsc.strict =>
+		{
+			return Ok(());
+		}
+		(None, _) => {}
+	}
+	Err(LinkError::UnknownVersion {
+		package:   name.to_string(),
+		version:   choice.spec,
+		available: versions
+			.of(name, pkg)
+			.map(|found| {
+				vsc::spec::ordered(found)
+					.into_iter()
+					.map(str::to_string)
+					.collect()
+			})
+			.unwrap_or_default(),
+	})
+}
+
+/// The version whose directory the recorded links of a package point into.
+pub fn linked_version<'a>(
+	name: &str,
+	versions: &'a BTreeMap<String, PathBuf>,
+	owned: &Ownership,
+) -> Option<&'a str> {
+	let destinations: Vec<PathBuf> = owned
+		.links()
+		.filter(|(package, ..)| *package == name)
+		.map(|(_, link, to)| destination(link, to))
+		.collect();
+	versions
+		.iter()
+		.find(|(_, dir)| {
+			let dir = resolved(dir);
+			destinations.iter().any(|dest| dest.starts_with(&dir))
+		})
+		.map(|(version, _)| version.as_str())
+}
+
+/// Picks the conflict strategy for a package.
+///
+/// Precedence: `--conflict-strategy` > package `conflict_strategy` >
+/// `global.conflict_strategy` > [`ConflictStrategy::Backup`].
+pub fn pick_conflict_strategy(
+	pkg: &Package,
+	cfg: &Config,
+	strategy: Option<ConflictStrategy>,
+) -> ConflictStrategy {
+	strategy
+		.or(pkg.conflict_strategy)
+		.or(cfg.global.conflict_strategy)
+		.unwrap_or_default()
+}
+
+/// Picks the link style for a package.
+///
+/// Precedence: package `link_style` > `global.link_style` >
+/// [`LinkStyle::Absolute`].
+pub fn pick_link_style(pkg: &Package, cfg: &Config) -> LinkStyle {
+	pkg.link_style.or(cfg.global.link_style).unwrap_or_default()
+}
+
+/// Resolves a conflict with an existing file at `target`, which is about to be
+/// replaced by a link to `source`.
+///
+/// Returns `true` when the path has been cleared and the link may be created,
+/// or `false` when the package should be skipped.
+pub fn handle_conflict(
+	source: &Path,
+	target: &Path,
+	strategy: ConflictStrategy,
+	tx: &mut Transaction,
+) -> Result<bool, LinkError> {
+	let resolution = match strategy {
+		ConflictStrategy::Skip => Resolution::Skip,
+		ConflictStrategy::Overwrite => Resolution::Overwrite,
+		ConflictStrategy::Backup => Resolution::Backup,
+		ConflictStrategy::Adopt => Resolution::Adopt,
+		ConflictStrategy::Prompt => prompt::ask(source, target, tx)?,
+	};
+	match resolution {
+		Resolution::Skip => {
+			warn!("conflict at {}, skipping", target.display());
+			return Ok(false);
+		}
+		Resolution::Overwrite => tx.discard(target)?,
+		Resolution::Backup => tx.backup(target)?,
+		Resolution::Adopt => adopt::adopt(source, target, tx)?,
+	}
+	Ok(true)
+}
+
+/// Creates a symlink in a platform-appropriate way.
+pub(crate) fn create_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+	#[cfg(unix)]
+	{
+		std::os::unix::fs::symlink(src, dst)
+	}
+	#[cfg(windows)]
+	{
+		if src.is_dir() {
+			std::os::windows::fs::symlink_dir(src, dst)
+		} else {
+			std::os::windows::fs::symlink_file(src, dst)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::config::Global;
+
+	fn base_cfg(source: &Path, target: &Path) -> Config {
+		Config {
+			global: Global {
+				source_dir: Some(source.display().to_string()),
+				target_dir: Some(target.display().to_string()),
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn status_missing_then_ok() {
+		let src = tempdir().unwrap();
+		let tgt = tempdir().unwrap();
+		fs::create_dir_all(src.path().join("nvim")).unwrap();
+		let mut cfg = base_cfg(src.path(), tgt.path());
+		cfg.packages.insert("nvim".into(), Package::default());
+		let pkg = &cfg.packages["nvim"];
+		let versions = Versions::default();
+		let state = State::default();
+
+		let st1 = status_package(
+			"nvim",
+			pkg,
+			&cfg,
+			&versions,
+			&state,
+			&Ownership::default(),
+		);
+		assert_eq!(st1.kind, LinkStatusKind::Missing);
+
+		let mut tx = Transaction::new(None, false);
+		tx.set_package(Some("nvim"));
+		link_package(
+			"nvim",
+			pkg,
+			&cfg,
+			&versions,
+			&state,
+			(None, None),
+			&mut tx,
+		)
+		.unwrap();
+		let owned = tx.ownership().clone();
+		tx.commit().unwrap();
+		let st2 = status_package("nvim", pkg, &cfg, &versions, &state, &owned);
+		assert_eq!(st2.kind, LinkStatusKind::Ok);
+		let st3 = status_package(
+			"nvim",
+			pkg,
+			&cfg,
+			&versions,
+			&state,
+			&Ownership::default(),
+		);
+		assert_eq!(st3.kind, LinkStatusKind::Unmanaged);
+	}
+
+	#[test]
+	fn status_not_symlink_wrong_target_and_dangling() {
+		let src = tempdir().unwrap();
+		let tgt = tempdir().unwrap();
+		fs::create_dir_all(src.path().join("pkg")).unwrap();
+		let mut cfg = base_cfg(src.path(), tgt.path());
+		cfg.packages.insert("pkg".into(), Package::default());
+		let pkg = &cfg.packages["pkg"];
+		let versions = Versions::default();
+		let state = State::default();
+		let target_path = tgt.path().join("pkg");
+		let mut owned = Ownership::default();
+		owned.add_link("pkg", &target_path, &src.path().join("pkg"));
+
+		// regular file at the target path
+		fs::write(&target_path, b"data").unwrap();
+		let st_not =
+			status_package("pkg", pkg, &cfg, &versions, &state, &owned);
+		assert_eq!(st_not.kind, LinkStatusKind::NotSymlink);
+
+		// symlink pointing elsewhere
+		fs::remove_file(&target_path).unwrap();
+		let other_dir = src.path().join("other");
+		fs::create_dir_all(&other_dir).unwrap();
+		create_symlink(&other_dir, &target_path).unwrap();
+		let st_wrong =
+			status_package("pkg", pkg, &cfg, &versions, &state, &owned);
+		assert_eq!(st_wrong.kind, LinkStatusKind::WrongTarget);
+
+		// symlink pointing nowhere
+		fs::remove_dir(&other_dir).unwrap();
+		let st_dangling =
+			status_package("pkg", pkg, &cfg, &versions, &state, &owned);
+		assert_eq!(st_dangling.kind, LinkStatusKind::Dangling);
+		let st_unmanaged = status_package(
+			"pkg",
+			pkg,
+			&cfg,
+			&versions,
+			&state,
+			&Ownership::default(),
+		);
+		assert_eq!(st_unmanaged.kind, LinkStatusKind::Unmanaged);
+
+		// source gone
+		fs::remove_dir(src.path().join("pkg")).unwrap();
+		let st_source =
+			status_package("pkg", pkg, &cfg, &versions, &state, &owned);
+		assert_eq!(st_source.kind, LinkStatusKind::SourceMissing);
+	}
+}
@@ -2,4 +2,35 @@
 // This module re-exports the actual linker implementation for use in the rest
 // of the codebase.
 
+mod adopt;
+mod deploy;
+mod error;
+mod ignore_rules;
 mod linker_impl;
+mod paths;
+mod plan;
+mod policy;
+mod prompt;
+mod prune;
+mod transaction;
+mod tree;
+
+pub use adopt::AdoptedFile;
+pub use deploy::{
+	remove_deployed,
+	restore_deployed,
+};
+pub use error::LinkError;
+use ignore_rules::IgnoreRules;
+pub use linker_impl::*;
+pub use plan::find_overlaps;
+pub use policy::{
+	check_policy,
+	check_target,
+};
+pub use prune::{
+	find_orphans,
+	stale_records,
+};
+pub use transaction::Transaction;
+pub use tree::is_real_dir;
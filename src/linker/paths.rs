@@ -0,0 +1,166 @@
+//! Path helpers shared by the linkers.
+
+use std::{
+	fs,
+	path::{
+		Component,
+		Path,
+		PathBuf,
+	},
+};
+
+use crate::config::LinkStyle;
+
+/// The value to store in a symlink at `link` so that it points to
+/// `points_to`.
+///
+/// Relative values keep working when the home directory and the repository
+/// are moved together. They are computed between the [`resolved`] forms of
+/// both paths, since the kernel follows a relative link from the physical
+/// parent directory, not from the path the link was reached through.
+pub(super) fn link_value(
+	points_to: &Path,
+	link: &Path,
+	style: LinkStyle,
+) -> PathBuf {
+	match (style, link.parent()) {
+		(LinkStyle::Relative, Some(parent)) => {
+			relative_path(&resolved(points_to), &resolved(parent))
+		}
+		_ => points_to.to_path_buf(),
+	}
+}
+
+/// Reads the link at `link` and returns where it points, see
+/// [`destination`].
+pub(super) fn link_destination(link: &Path) -> std::io::Result<PathBuf> {
+	Ok(destination(link, &fs::read_link(link)?))
+}
+
+/// Where a link at `link` holding `value` points: a relative value is
+/// resolved against the physical parent directory, as the kernel does.
+pub(super) fn destination(link: &Path, value: &Path) -> PathBuf {
+	match link.parent() {
+		Some(parent) if value.is_relative() => {
+			normalize(&resolved(parent).join(value))
+		}
+		_ => value.to_path_buf(),
+	}
+}
+
+pub(super) fn canonical(path: &Path) -> PathBuf {
+	path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+pub(super) fn same_path(a: &Path, b: &Path) -> bool {
+	canonical(a) == canonical(b)
+}
+
+/// `path` made absolute against the current directory and normalized.
+pub(super) fn absolute(path: &Path) -> PathBuf {
+	normalize(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+/// `path` made absolute with the symlinks in its longest existing prefix
+/// resolved, so a path that does not exist yet can still be compared with
+/// canonical directories.
+pub(super) fn resolved(path: &Path) -> PathBuf {
+	let path = absolute(path);
+	for prefix in path.ancestors() {
+		if let Ok(real) = prefix.canonicalize() {
+			let rest = path.strip_prefix(prefix).unwrap_or(Path::new(""));
+			return real.join(rest);
+		}
+	}
+	path
+}
+
+/// Resolves `.` and `..` components without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+	let mut out = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				if !out.pop() {
+					out.push(component);
+				}
+			}
+			_ => out.push(component),
+		}
+	}
+	out
+}
+
+/// The path leading from the directory `base` to `path`; both absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+	let path: Vec<_> = path.components().collect();
+	let base: Vec<_> = base.components().collect();
+	let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+	let mut out = PathBuf::new();
+	for _ in common..base.len() {
+		out.push(Component::ParentDir);
+	}
+	for component in &path[common..] {
+		out.push(component);
+	}
+	if out.as_os_str().is_empty() {
+		out.push(Component::CurDir);
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn relative_link_values() {
+		let value = |to: &str, link: &str| {
+			link_value(Path::new(to), Path::new(link), LinkStyle::Relative)
+		};
+		assert_eq!(
+			value("/home/u/dotfiles/nvim", "/home/u/.config/nvim"),
+			PathBuf::from("../dotfiles/nvim")
+		);
+		assert_eq!(
+			value("/home/u/dotfiles/zshrc", "/home/u/.zshrc"),
+			PathBuf::from("dotfiles/zshrc")
+		);
+		assert_eq!(
+			value("/home/u/dotfiles/./a/../b", "/srv/x/y"),
+			PathBuf::from("../../home/u/dotfiles/b")
+		);
+		assert_eq!(
+			link_value(
+				Path::new("/d/a"),
+				Path::new("/t/a"),
+				LinkStyle::Absolute
+			),
+			PathBuf::from("/d/a")
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn relative_links_through_a_symlinked_parent() {
+		let tmp = tempdir().unwrap();
+		let home = tmp.path().join("home");
+		let source = home.join("dotfiles/nvim");
+		fs::create_dir_all(&source).unwrap();
+		fs::create_dir_all(tmp.path().join("data/config")).unwrap();
+		// `~/.config` is itself a link to a directory elsewhere.
+		std::os::unix::fs::symlink("../data/config", home.join(".config"))
+			.unwrap();
+		let link = home.join(".config/nvim");
+
+		let value = link_value(&source, &link, LinkStyle::Relative);
+		assert_eq!(value, PathBuf::from("../../home/dotfiles/nvim"));
+		std::os::unix::fs::symlink(&value, &link).unwrap();
+		assert!(same_path(&link, &source));
+		assert_eq!(link_destination(&link).unwrap(), canonical(&source));
+	}
+}
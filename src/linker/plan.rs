@@ -0,0 +1,252 @@
+//! Up-front checks on the targets of a set of packages.
+//!
+//! Two packages linking the same path, or one linking a path inside a
+//! directory that another links as a whole, make the later link overwrite or
+//! write through the earlier one. Tree packages sharing directories are fine:
+//! the tree linker unfolds them as needed.
+
+use std::{
+	collections::{
+		BTreeSet,
+		HashMap,
+	},
+	fmt,
+	path::PathBuf,
+};
+
+use super::{
+	IgnoreRules,
+	deploy::deploy_claims,
+	linker_impl::{
+		resolve_mappings,
+		uses_tree,
+	},
+	tree::tree_claims,
+};
+use crate::{
+	config::{
+		Config,
+		Deploy,
+		Package,
+	},
+	state::State,
+	vsc::Versions,
+};
+
+/// How the targets of two packages overlap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlapKind {
+	/// Both packages link this path.
+	SameTarget(PathBuf),
+	/// The second package links `inner`, which lies inside `outer`, a path
+	/// the first package links.
+	Nested { outer: PathBuf, inner: PathBuf },
+}
+
+/// Two packages whose targets get in each other's way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetOverlap {
+	pub first:  String,
+	pub second: String,
+	pub kind:   OverlapKind,
+}
+
+impl fmt::Display for TargetOverlap {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let Self {
+			first,
+			second,
+			kind,
+		} = self;
+		match kind {
+			OverlapKind::SameTarget(path) => write!(
+				f,
+				"packages '{first}' and '{second}' both link {}",
+				path.display()
+			),
+			OverlapKind::Nested { outer, inner } => write!(
+				f,
+				"package '{second}' links {} inside {}, which package \
+				 '{first}' links",
+				inner.display(),
+				outer.display()
+			),
+		}
+	}
+}
+
+/// What a package places at a target path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Claim {
+	/// A link to the whole package source.
+	Whole,
+	/// A link to one file of a tree package.
+	File,
+	/// A directory of a tree package, which may be folded into a link.
+	Dir,
+}
+
+/// Finds overlapping targets among `packages`, given as `(name, package,
+/// version)`. At most one overlap is reported per pair of packages.
+pub fn find_overlaps(
+	packages: &[(&str, &Package, Option<&str>)],
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+) -> Vec<TargetOverlap> {
+	let mut packages = packages.to_vec();
+	packages.sort_unstable_by_key(|(name, ..)| *name);
+
+	let mut claims: HashMap<PathBuf, Vec<(&str, Claim)>> = HashMap::new();
+	let mappings = packages.iter().flat_map(|&(name, pkg, version)| {
+		resolve_mappings(name, pkg, cfg, versions, state, version)
+			.into_iter()
+			.map(move |(source, target)| (name, pkg, source, target))
+	});
+	for (name, pkg, source, target) in mappings {
+		let rules = IgnoreRules::new(cfg, pkg, &source);
+		let mirrored = if pkg.deploy != Deploy::Symlink {
+			Some(deploy_claims(&source, &target, &rules))
+		} else if uses_tree(pkg, &source, &rules) {
+			Some(tree_claims(&source, &target, &rules))
+		} else {
+			None
+		};
+		if let Some(mirrored) = mirrored {
+			for (path, is_dir) in mirrored {
+				let claim = if is_dir { Claim::Dir } else { Claim::File };
+				claims.entry(path).or_default().push((name, claim));
+			}
+		} else {
+			claims.entry(target).or_default().push((name, Claim::Whole));
+		}
+	}
+
+	let mut paths: Vec<_> = claims.keys().collect();
+	paths.sort_unstable();
+	let mut seen = BTreeSet::new();
+	let mut out = Vec::new();
+	let mut report = |first: &str, second: &str, kind| {
+		let pair = if first < second {
+			(first.to_string(), second.to_string())
+		} else {
+			(second.to_string(), first.to_string())
+		};
+		if seen.insert(pair) {
+			out.push(TargetOverlap {
+				first: first.to_string(),
+				second: second.to_string(),
+				kind,
+			});
+		}
+	};
+
+	for path in paths {
+		let here = &claims[path];
+		for (i, &(a, a_claim)) in here.iter().enumerate() {
+			for &(b, b_claim) in &here[i + 1..] {
+				if a != b && !(a_claim == Claim::Dir && b_claim == Claim::Dir) {
+					report(a, b, OverlapKind::SameTarget(path.clone()));
+				}
+			}
+		}
+		for outer in path.ancestors().skip(1) {
+			let Some(above) = claims.get(outer) else {
+				continue;
+			};
+			for &(a, a_claim) in above {
+				for &(b, b_claim) in here {
+					// Tree packages unfold each other's directories.
+					let writes_through =
+						a_claim != Claim::Dir || b_claim == Claim::Whole;
+					if a != b && writes_through {
+						report(a, b, OverlapKind::Nested {
+							outer: outer.to_path_buf(),
+							inner: path.clone(),
+						});
+					}
+				}
+			}
+		}
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::config::LinkMode;
+
+	#[test]
+	fn collisions_and_nesting() {
+		let src = tempdir().unwrap();
+		let dst = tempdir().unwrap();
+		for dir in ["nvim", "nvim-lua", "fish-a/conf.d", "fish-b/conf.d", "zsh"]
+		{
+			fs::create_dir_all(src.path().join(dir)).unwrap();
+		}
+		fs::write(src.path().join("fish-a/conf.d/a.fish"), "a").unwrap();
+		fs::write(src.path().join("fish-b/conf.d/b.fish"), "b").unwrap();
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(src.path().display().to_string());
+		cfg.global.target_dir = Some(dst.path().display().to_string());
+		let pkg = |target: &str, mode| Package {
+			target: Some(target.into()),
+			mode,
+			..Package::default()
+		};
+		cfg.packages = [
+			("nvim", pkg("nvim", LinkMode::Direct)),
+			("nvim-lua", pkg("nvim/lua", LinkMode::Direct)),
+			("fish-a", pkg("fish", LinkMode::Tree)),
+			("fish-b", pkg("fish", LinkMode::Tree)),
+			("zsh", pkg("fish/conf.d/zsh", LinkMode::Direct)),
+		]
+		.into_iter()
+		.map(|(name, pkg)| (name.to_string(), pkg))
+		.collect();
+
+		let packages: Vec<_> = cfg
+			.packages
+			.iter()
+			.map(|(name, pkg)| (name.as_str(), pkg, None))
+			.collect();
+		let overlaps = find_overlaps(
+			&packages,
+			&cfg,
+			&Versions::default(),
+			&State::default(),
+		);
+		let target = |p: &str| dst.path().join(p);
+		assert_eq!(overlaps, vec![
+			TargetOverlap {
+				first:  "fish-a".into(),
+				second: "zsh".into(),
+				kind:   OverlapKind::Nested {
+					outer: target("fish/conf.d"),
+					inner: target("fish/conf.d/zsh"),
+				},
+			},
+			TargetOverlap {
+				first:  "fish-b".into(),
+				second: "zsh".into(),
+				kind:   OverlapKind::Nested {
+					outer: target("fish/conf.d"),
+					inner: target("fish/conf.d/zsh"),
+				},
+			},
+			TargetOverlap {
+				first:  "nvim".into(),
+				second: "nvim-lua".into(),
+				kind:   OverlapKind::Nested {
+					outer: target("nvim"),
+					inner: target("nvim/lua"),
+				},
+			},
+		]);
+	}
+}
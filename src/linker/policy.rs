@@ -0,0 +1,204 @@
+//! Enforcement of the `[policy]` section.
+//!
+//! Targets are compared both lexically and with the symlinks of their
+//! existing prefix resolved, so neither a target that does not exist yet nor
+//! one reached through a symlinked directory slips past a root.
+
+use std::path::{
+	Path,
+	PathBuf,
+};
+
+use directories_next::BaseDirs;
+
+use super::{
+	LinkError,
+	linker_impl::{
+		expand_path,
+		resolve_mappings,
+		source_root,
+	},
+	paths::{
+		absolute,
+		resolved,
+	},
+};
+use crate::{
+	config::{
+		Config,
+		DEFAULT_FORBIDDEN_ROOTS,
+		Package,
+	},
+	state::State,
+	vsc::Versions,
+};
+
+/// Checks the sources and targets of a package against the policy.
+pub fn check_policy(
+	name: &str,
+	pkg: &Package,
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	version: Option<&str>,
+) -> Result<(), LinkError> {
+	let is_absolute = |raw: Option<&str>| {
+		raw.is_some_and(|raw| expand_path(raw).is_absolute())
+	};
+	let root = absolute(&source_root(cfg));
+	let mappings = resolve_mappings(name, pkg, cfg, versions, state, version);
+	for (i, (source, target)) in mappings.iter().enumerate() {
+		let raw = pkg.files.get(i).map(|file| file.source.as_str());
+		if !cfg.policy.allow_source_escape &&
+			!is_absolute(pkg.source.as_deref()) &&
+			!is_absolute(raw) &&
+			!absolute(source).starts_with(&root)
+		{
+			return Err(violation(
+				name,
+				source,
+				"leaves global.source_dir (set policy.allow_source_escape to \
+				 allow it)",
+			));
+		}
+		check_target(name, target, cfg)?;
+	}
+	Ok(())
+}
+
+/// Checks a path a package links at against the policy.
+pub fn check_target(
+	name: &str,
+	target: &Path,
+	cfg: &Config,
+) -> Result<(), LinkError> {
+	let policy = &cfg.policy;
+	let forms = [absolute(target), resolved(target)];
+	let inside = |root: &Path| {
+		let root_forms = [absolute(root), resolved(root)];
+		forms
+			.iter()
+			.any(|form| root_forms.iter().any(|root| form.starts_with(root)))
+	};
+
+	let forbidden: Vec<PathBuf> = match &policy.forbidden_roots {
+		Some(roots) => roots.iter().map(expand_path).collect(),
+		None => DEFAULT_FORBIDDEN_ROOTS.iter().map(PathBuf::from).collect(),
+	};
+	if let Some(root) = forbidden.iter().find(|root| inside(root)) {
+		return Err(violation(
+			name,
+			target,
+			&format!(
+				"is inside the forbidden root {} (policy.forbidden_roots)",
+				root.display()
+			),
+		));
+	}
+
+	let allowed: Vec<PathBuf> =
+		policy.allowed_roots.iter().map(expand_path).collect();
+	if !allowed.is_empty() && !allowed.iter().any(|root| inside(root)) {
+		return Err(violation(
+			name,
+			target,
+			"is outside every policy.allowed_roots entry",
+		));
+	}
+
+	let Some(home) = BaseDirs::new().map(|d| d.home_dir().to_path_buf()) else {
+		return Ok(());
+	};
+	// Linking at the home directory or above it would replace it.
+	if forms.iter().any(|form| {
+		[absolute(&home), resolved(&home)]
+			.iter()
+			.any(|home| home.starts_with(form))
+	}) {
+		return Err(violation(
+			name,
+			target,
+			"would replace the home directory",
+		));
+	}
+	if !policy.allow_outside_home.unwrap_or(true) && !inside(&home) {
+		return Err(violation(
+			name,
+			target,
+			"is outside the home directory (set policy.allow_outside_home to \
+			 allow it)",
+		));
+	}
+	Ok(())
+}
+
+fn violation(name: &str, path: &Path, reason: &str) -> LinkError {
+	LinkError::Policy {
+		package: name.to_string(),
+		path:    path.to_path_buf(),
+		reason:  reason.to_string(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::Policy;
+
+	#[test]
+	fn roots_and_escapes() {
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some("/srv/dotfiles".into());
+		cfg.global.target_dir = Some("/srv/home".into());
+		let reason =
+			|name: &str, pkg: &Package, cfg: &Config| match check_policy(
+				name,
+				pkg,
+				cfg,
+				&Versions::default(),
+				&State::default(),
+				None,
+			) {
+				Ok(()) => None,
+				Err(LinkError::Policy { reason, .. }) => Some(reason),
+				Err(e) => panic!("unexpected error: {e}"),
+			};
+		let pkg = |source: Option<&str>, target: &str| Package {
+			source: source.map(str::to_string),
+			target: Some(target.into()),
+			..Package::default()
+		};
+
+		assert_eq!(reason("zsh", &pkg(None, "zsh"), &cfg), None);
+		assert_eq!(
+			reason("hosts", &pkg(None, "/etc/hosts"), &cfg).as_deref(),
+			Some("is inside the forbidden root /etc (policy.forbidden_roots)")
+		);
+		assert!(
+			reason("up", &pkg(Some("../../etc"), "up"), &cfg)
+				.unwrap()
+				.starts_with("leaves global.source_dir")
+		);
+		assert_eq!(reason("abs", &pkg(Some("/opt/abs"), "abs"), &cfg), None);
+		assert_eq!(
+			reason("root", &pkg(None, "/"), &cfg).as_deref(),
+			Some("would replace the home directory")
+		);
+
+		cfg.policy = Policy {
+			allowed_roots: vec!["/srv/home/.config".into()],
+			forbidden_roots: Some(Vec::new()),
+			allow_source_escape: true,
+			..Policy::default()
+		};
+		assert_eq!(
+			reason("hosts", &pkg(None, "/etc/hosts"), &cfg).as_deref(),
+			Some("is outside every policy.allowed_roots entry")
+		);
+		assert_eq!(reason("up", &pkg(Some("../x"), ".config/up"), &cfg), None);
+		assert_eq!(
+			reason("root", &pkg(None, "/"), &cfg).as_deref(),
+			Some("is outside every policy.allowed_roots entry")
+		);
+	}
+}
@@ -0,0 +1,201 @@
+//! Interactive conflict resolution for `conflict_strategy = "prompt"`.
+//!
+//! The user is shown what currently occupies the target and, for text files,
+//! a unified diff against the package source before choosing what to do.
+
+use std::{
+	fmt::Write,
+	fs,
+	path::Path,
+};
+
+use colored::Colorize;
+use dialoguer::{
+	Select,
+	theme::ColorfulTheme,
+};
+use similar::TextDiff;
+
+use super::{
+	LinkError,
+	Transaction,
+};
+
+/// Files larger than this are not diffed.
+const MAX_DIFF_BYTES: u64 = 256 * 1024;
+
+/// How the user chose to resolve a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+	/// Replace the existing file with the link.
+	Overwrite,
+	/// Keep the existing file as a backup, then link.
+	Backup,
+	/// Leave the existing file alone and do not link.
+	Skip,
+	/// Move the existing file into the package source, then link it back.
+	Adopt,
+}
+
+impl Resolution {
+	const ALL: [Self; 4] =
+		[Self::Overwrite, Self::Backup, Self::Skip, Self::Adopt];
+
+	const fn label(self) -> &'static str {
+		match self {
+			Self::Overwrite => "overwrite - replace the existing file",
+			Self::Backup => "backup    - keep the existing file as a backup",
+			Self::Skip => "skip      - leave it alone and do not link",
+			Self::Adopt => "adopt     - move it into the repo and link it back",
+		}
+	}
+}
+
+/// Asks how to resolve the conflict at `target`.
+///
+/// A choice made with "apply to all" is remembered in `tx` and returned for
+/// every later conflict of the same run without asking again.
+pub(super) fn ask(
+	source: &Path,
+	target: &Path,
+	tx: &mut Transaction,
+) -> Result<Resolution, LinkError> {
+	if let Some(resolution) = tx.remembered_resolution() {
+		return Ok(resolution);
+	}
+	if !atty::is(atty::Stream::Stdin) {
+		return Err(LinkError::Conflict(format!(
+			"{} already exists and stdin is not a terminal, so rinkle cannot \
+			 ask what to do; pass --conflict-strategy or set \
+			 conflict_strategy to skip, overwrite or backup",
+			target.display()
+		)));
+	}
+
+	eprintln!(
+		"{} {} already exists: {}",
+		"conflict:".yellow().bold(),
+		target.display(),
+		describe(target)
+	);
+	eprint!("{}", preview(source, target));
+
+	let theme = ColorfulTheme::default();
+	let mut items: Vec<_> = Resolution::ALL.iter().map(|r| r.label()).collect();
+	items.push("apply to all - choose an action for every remaining conflict");
+	items.push("abort        - roll back everything and stop");
+	let picked = select(&theme, "What should rinkle do?", &items)?;
+
+	match Resolution::ALL.get(picked) {
+		Some(resolution) => Ok(*resolution),
+		None if picked == Resolution::ALL.len() => {
+			let labels: Vec<_> =
+				Resolution::ALL.iter().map(|r| r.label()).collect();
+			let picked =
+				select(&theme, "Apply to all remaining conflicts", &labels)?;
+			let resolution = Resolution::ALL[picked];
+			tx.remember_resolution(resolution);
+			Ok(resolution)
+		}
+		None => Err(LinkError::Aborted),
+	}
+}
+
+fn select(
+	theme: &ColorfulTheme,
+	prompt: &str,
+	items: &[&str],
+) -> Result<usize, LinkError> {
+	Select::with_theme(theme)
+		.with_prompt(prompt)
+		.items(items)
+		.default(0)
+		.interact()
+		.map_err(|e| LinkError::Conflict(e.to_string()))
+}
+
+/// A one-line description of what occupies `path`.
+fn describe(path: &Path) -> String {
+	let Ok(meta) = fs::symlink_metadata(path) else {
+		return "unreadable".into();
+	};
+	if meta.file_type().is_symlink() {
+		return fs::read_link(path).map_or_else(
+			|_| "symlink".into(),
+			|p| format!("symlink to {}", p.display()),
+		);
+	}
+	if meta.is_dir() {
+		let count = fs::read_dir(path).map_or(0, Iterator::count);
+		return format!("directory with {count} entries");
+	}
+	format!("file, {} bytes", meta.len())
+}
+
+/// A unified diff of `target` against `source`, or a short note when the two
+/// cannot be compared as text.
+fn preview(source: &Path, target: &Path) -> String {
+	let is_file = |p: &Path| {
+		fs::symlink_metadata(p)
+			.is_ok_and(|m| m.is_file() && m.len() <= MAX_DIFF_BYTES)
+	};
+	if !is_file(source) || !is_file(target) {
+		return String::new();
+	}
+	let (Ok(old), Ok(new)) =
+		(fs::read_to_string(target), fs::read_to_string(source))
+	else {
+		return "  (binary files differ)\n".into();
+	};
+	if old == new {
+		return "  (contents are identical to the source)\n".into();
+	}
+
+	let diff = TextDiff::from_lines(&old, &new);
+	let text = diff
+		.unified_diff()
+		.header(&target.display().to_string(), &source.display().to_string())
+		.to_string();
+	let mut out = String::new();
+	for line in text.lines() {
+		let colored = if line.starts_with("+++") || line.starts_with("---") {
+			line.bold()
+		} else if line.starts_with('+') {
+			line.green()
+		} else if line.starts_with('-') {
+			line.red()
+		} else if line.starts_with("@@") {
+			line.cyan()
+		} else {
+			line.normal()
+		};
+		let _ = writeln!(out, "{colored}");
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn preview_shows_unified_diff() {
+		colored::control::set_override(false);
+		let tmp = tempdir().unwrap();
+		let source = tmp.path().join("source");
+		let target = tmp.path().join("target");
+		fs::write(&source, "a\nb\n").unwrap();
+		fs::write(&target, "a\nc\n").unwrap();
+
+		let diff = preview(&source, &target);
+		assert!(diff.contains("@@"));
+		assert!(diff.contains("-c\n"));
+		assert!(diff.contains("+b\n"));
+		assert_eq!(describe(&target), "file, 4 bytes");
+
+		fs::write(&target, "a\nb\n").unwrap();
+		assert!(preview(&source, &target).contains("identical"));
+	}
+}
@@ -0,0 +1,233 @@
+//! Finding links into the repository that no package accounts for.
+//!
+//! Renaming a package or dropping it from `rinkle.toml` leaves its links
+//! behind. Links recorded in the ownership database are orphaned when their
+//! package is gone or no longer targets them, or when they dangle; only those
+//! are ever removed.
+//!
+//! To point out links into the repository that rinkle did not create, the
+//! directories rinkle links into are scanned as well: the target root, every
+//! directory on the way to a configured target, the real directories of tree
+//! packages and the directories of recorded links. Only the symlinks directly
+//! inside those directories are inspected, so `$HOME` is never walked as a
+//! whole.
+
+use std::{
+	collections::BTreeSet,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use super::{
+	linker_impl::{
+		resolve_mappings,
+		source_root,
+		target_root,
+	},
+	paths::{
+		absolute,
+		canonical,
+		link_destination,
+	},
+	tree::is_real_dir,
+};
+use crate::{
+	config::Config,
+	ownership::Ownership,
+	state::State,
+	vsc::Versions,
+};
+
+/// A symlink into the source directory that should go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrphanLink {
+	pub link:      PathBuf,
+	/// Where the link points, resolved against its directory.
+	pub points_to: PathBuf,
+	/// Whether `points_to` no longer exists.
+	pub dangling:  bool,
+	/// The package that created the link; `None` if rinkle did not, in which
+	/// case it is only reported.
+	pub package:   Option<String>,
+}
+
+/// Finds the recorded links that no configured package accounts for or that
+/// dangle, and the links into `global.source_dir` that rinkle did not create.
+pub fn find_orphans(
+	cfg: &Config,
+	versions: &Versions,
+	state: &State,
+	owned: &Ownership,
+) -> Vec<OrphanLink> {
+	let targets: Vec<_> = cfg
+		.packages
+		.iter()
+		.flat_map(|(name, pkg)| {
+			resolve_mappings(name, pkg, cfg, versions, state, None)
+				.into_iter()
+				.map(|(source, target)| (name.as_str(), source, target))
+		})
+		.collect();
+
+	let mut orphans = Vec::new();
+	for (package, link, _) in owned.links() {
+		if !is_symlink(link) {
+			continue;
+		}
+		let Ok(points_to) = link_destination(link) else {
+			continue;
+		};
+		let dangling = fs::metadata(link).is_err();
+		let still_wanted = targets.iter().any(|(name, _, target)| {
+			*name == package && absolute(link).starts_with(absolute(target))
+		});
+		if dangling || !still_wanted {
+			orphans.push(OrphanLink {
+				link: link.to_path_buf(),
+				points_to,
+				dangling,
+				package: Some(package.to_string()),
+			});
+		}
+	}
+
+	let roots = [absolute(&source_root(cfg)), canonical(&source_root(cfg))];
+	let mut dirs = BTreeSet::new();
+	let top = absolute(&target_root(cfg));
+	dirs.insert(top.clone());
+	for (_, source, target) in &targets {
+		let target = absolute(target);
+		for dir in target.ancestors().skip(1) {
+			dirs.insert(dir.to_path_buf());
+			if dir == top || !dir.starts_with(&top) {
+				break;
+			}
+		}
+		mirrored_dirs(source, &target, &mut dirs);
+	}
+	dirs.extend(
+		owned
+			.links()
+			.filter_map(|(_, link, _)| link.parent().map(absolute)),
+	);
+
+	for dir in dirs {
+		let Ok(entries) = fs::read_dir(&dir) else {
+			continue;
+		};
+		let links = entries
+			.filter_map(Result::ok)
+			.filter(|e| e.file_type().is_ok_and(|t| t.is_symlink()))
+			.map(|e| e.path());
+		for link in links {
+			let Ok(points_to) = link_destination(&link) else {
+				continue;
+			};
+			let recorded = owned
+				.links()
+				.any(|(_, owned_link, _)| absolute(owned_link) == link);
+			if recorded || !roots.iter().any(|r| points_to.starts_with(r)) {
+				continue;
+			}
+			orphans.push(OrphanLink {
+				dangling: fs::metadata(&link).is_err(),
+				link,
+				points_to,
+				package: None,
+			});
+		}
+	}
+	orphans.sort_by(|a, b| a.link.cmp(&b.link));
+	orphans
+}
+
+/// Recorded links that are gone or were replaced by something else.
+pub fn stale_records(owned: &Ownership) -> Vec<PathBuf> {
+	owned
+		.links()
+		.filter(|(_, link, _)| !is_symlink(link))
+		.map(|(_, link, _)| link.to_path_buf())
+		.collect()
+}
+
+fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Adds the real directories of `target` that mirror directories of
+/// `source`, where a tree package places its links.
+fn mirrored_dirs(source: &Path, target: &Path, dirs: &mut BTreeSet<PathBuf>) {
+	if !is_real_dir(source) || !is_real_dir(target) {
+		return;
+	}
+	dirs.insert(target.to_path_buf());
+	let Ok(entries) = fs::read_dir(source) else {
+		return;
+	};
+	for entry in entries.filter_map(Result::ok) {
+		mirrored_dirs(&entry.path(), &target.join(entry.file_name()), dirs);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::{
+		config::Package,
+		linker::create_symlink,
+	};
+
+	#[test]
+	fn finds_orphaned_dangling_and_unmanaged_links() {
+		let src = tempdir().unwrap();
+		let dst = tempdir().unwrap();
+		let (src, dst) = (src.path(), dst.path());
+		for dir in ["zsh", "nvim@0.9", "old", ".config"] {
+			fs::create_dir_all(src.join(dir)).unwrap();
+		}
+		fs::create_dir_all(dst.join(".config")).unwrap();
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(src.display().to_string());
+		cfg.global.target_dir = Some(dst.display().to_string());
+		cfg.packages.insert("zsh".into(), Package::default());
+		cfg.packages.insert("nvim".into(), Package {
+			target: Some(".config/nvim".into()),
+			..Package::default()
+		});
+
+		let mut owned = Ownership::default();
+		let mut link = |package: &str, to: &Path, link: &Path| {
+			create_symlink(to, link).unwrap();
+			owned.add_link(package, link, to);
+		};
+		link("zsh", &src.join("zsh"), &dst.join("zsh"));
+		link("nvim", &src.join("nvim@0.9"), &dst.join(".config/nvim"));
+		link("old", &src.join("old"), &dst.join(".config/old"));
+		link("zsh", &src.join("gone"), &dst.join("gone"));
+		create_symlink(&src.join("zsh"), &dst.join("by-hand")).unwrap();
+		create_symlink(dst, &dst.join("elsewhere")).unwrap();
+
+		let orphans =
+			find_orphans(&cfg, &Versions::default(), &State::default(), &owned);
+		let found: Vec<_> = orphans
+			.iter()
+			.map(|o| {
+				(
+					o.link.strip_prefix(dst).unwrap(),
+					o.dangling,
+					o.package.as_deref(),
+				)
+			})
+			.collect();
+		assert_eq!(found, vec![
+			(Path::new(".config/old"), false, Some("old")),
+			(Path::new("by-hand"), false, None),
+			(Path::new("gone"), true, Some("zsh")),
+		]);
+	}
+}
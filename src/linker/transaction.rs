@@ -0,0 +1,821 @@
+//! Journaled filesystem transactions.
+//!
+//! Every mutation performed by a link or remove run goes through a
+//! [`Transaction`]. Each action is appended to an on-disk journal *before* it
+//! is performed, so that on error, on Ctrl-C, or after a crash all completed
+//! actions can be reversed and the targets left exactly as they were before
+//! the command. Files that would be deleted (the `overwrite` strategy) are
+//! stashed next to the original and only removed when the transaction
+//! commits.
+//!
+//! A transaction also keeps the ownership database up to date: links, copied
+//! files and directories created for a package are recorded under it, and the
+//! database is saved only when the transaction commits.
+//!
+//! A run holds an exclusive lock on `journal.lock` for the whole transaction.
+//! A journal is therefore only rolled back once the run that wrote it is gone,
+//! and a second run started meanwhile fails instead.
+
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+	sync::{
+		Once,
+		atomic::{
+			AtomicBool,
+			Ordering,
+		},
+	},
+};
+
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use tracing::{
+	debug,
+	error,
+	info,
+	warn,
+};
+
+use super::{
+	AdoptedFile,
+	LinkError,
+	create_symlink,
+	deploy::hash_file,
+	prompt::Resolution,
+};
+use crate::{
+	backup::{
+		self,
+		BackupEntry,
+	},
+	config::Deploy,
+	ownership::{
+		self,
+		DeployedFile,
+		Ownership,
+	},
+	state,
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+/// A single reversible filesystem action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum Action {
+	CreateSymlink {
+		link:      PathBuf,
+		points_to: PathBuf,
+	},
+	RemoveSymlink {
+		link:      PathBuf,
+		points_to: PathBuf,
+	},
+	ReplaceSymlink {
+		link: PathBuf,
+		from: PathBuf,
+		to:   PathBuf,
+	},
+	Rename {
+		from: PathBuf,
+		to:   PathBuf,
+	},
+	Stash {
+		path:  PathBuf,
+		stash: PathBuf,
+	},
+	Backup {
+		path:  PathBuf,
+		entry: PathBuf,
+		data:  PathBuf,
+	},
+	CreateFile {
+		path: PathBuf,
+	},
+	CreateDir {
+		path: PathBuf,
+	},
+	RemoveDir {
+		path: PathBuf,
+	},
+}
+
+impl Action {
+	/// Reverses the action. Tolerates actions that were journaled but never
+	/// performed because the process died in between.
+	fn undo(&self) -> std::io::Result<()> {
+		match self {
+			Self::CreateSymlink { link, points_to } => {
+				if fs::read_link(link).is_ok_and(|p| &p == points_to) {
+					fs::remove_file(link)?;
+				}
+			}
+			Self::RemoveSymlink { link, points_to } => {
+				if fs::symlink_metadata(link).is_err() {
+					create_symlink(points_to, link)?;
+				}
+			}
+			Self::ReplaceSymlink { link, from, to } => {
+				match fs::read_link(link) {
+					Ok(p) if &p == to => swap_symlink(from, link)?,
+					Err(_) if fs::symlink_metadata(link).is_err() => {
+						create_symlink(from, link)?;
+					}
+					_ => {}
+				}
+			}
+			Self::Rename { from, to } |
+			Self::Stash {
+				path: from,
+				stash: to,
+			} => {
+				if fs::symlink_metadata(from).is_err() &&
+					fs::symlink_metadata(to).is_ok()
+				{
+					backup::move_path(to, from)?;
+				}
+			}
+			Self::Backup { path, entry, data } => {
+				if fs::symlink_metadata(path).is_err() &&
+					fs::symlink_metadata(data).is_ok()
+				{
+					backup::move_path(data, path)?;
+				}
+				if entry.exists() {
+					fs::remove_dir_all(entry)?;
+				}
+			}
+			Self::CreateFile { path } => {
+				if fs::symlink_metadata(path).is_ok_and(|m| m.is_file()) {
+					fs::remove_file(path)?;
+				}
+			}
+			Self::CreateDir { path } => {
+				if path.is_dir() {
+					fs::remove_dir(path)?;
+				}
+			}
+			Self::RemoveDir { path } => {
+				if fs::symlink_metadata(path).is_err() {
+					fs::create_dir(path)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A journaled set of filesystem changes that is either committed as a whole
+/// or rolled back.
+///
+/// A transaction that is dropped without being committed is rolled back.
+pub struct Transaction {
+	journal:      Vec<Action>,
+	journal_path: Option<PathBuf>,
+	dry_run:      bool,
+	finished:     bool,
+	/// Conflict resolution the user chose to apply to all remaining conflicts.
+	resolution:   Option<Resolution>,
+	/// Source files changed by adopting existing targets.
+	adopted:      Vec<AdoptedFile>,
+	/// The package currently being processed, recorded with backups and as
+	/// the owner of created links and directories.
+	package:      Option<String>,
+	/// The ownership database as changed by this transaction.
+	ownership:    Ownership,
+	/// Where to save `ownership` on commit; `None` keeps it in memory.
+	owned_path:   Option<PathBuf>,
+	/// The lock file, held until the transaction is dropped.
+	lock:         Option<fs::File>,
+}
+
+impl Transaction {
+	/// Creates a transaction journaled to `journal_path`, or kept in memory
+	/// only when `None`. It starts with an empty ownership database that is
+	/// never saved.
+	pub const fn new(journal_path: Option<PathBuf>, dry_run: bool) -> Self {
+		Self {
+			journal: Vec::new(),
+			journal_path,
+			dry_run,
+			finished: false,
+			resolution: None,
+			adopted: Vec::new(),
+			package: None,
+			ownership: Ownership {
+				packages: BTreeMap::new(),
+			},
+			owned_path: None,
+			lock: None,
+		}
+	}
+
+	/// Starts a transaction journaled next to the state file.
+	///
+	/// The lock is taken first, failing if another run holds it; a journal
+	/// left behind by an interrupted run is then rolled back. A Ctrl-C
+	/// handler is installed so that interrupts abort the transaction between
+	/// two actions instead of killing the process mid-way. Dry runs take no
+	/// lock.
+	pub fn begin(dry_run: bool) -> Result<Self, LinkError> {
+		install_interrupt_handler();
+		let lock = if dry_run {
+			None
+		} else {
+			Some(take_lock(&lock_path())?)
+		};
+		let path = journal_path();
+		if path.exists() {
+			if dry_run {
+				warn!(
+					"found a journal at {}; unless another run is in \
+					 progress, it will be rolled back by the next real run",
+					path.display()
+				);
+			} else {
+				recover(&path)?;
+			}
+		}
+		let mut tx = Self::new((!dry_run).then_some(path), dry_run);
+		let owned_path = ownership::db_path();
+		tx.ownership = ownership::load_from(&owned_path)?;
+		tx.owned_path = (!dry_run).then_some(owned_path);
+		tx.lock = lock;
+		Ok(tx)
+	}
+
+	/// Whether actions are only printed instead of performed.
+	pub const fn dry_run(&self) -> bool {
+		self.dry_run
+	}
+
+	/// The conflict resolution chosen with "apply to all", if any.
+	pub(super) const fn remembered_resolution(&self) -> Option<Resolution> {
+		self.resolution
+	}
+
+	/// Applies `resolution` to every later conflict of this transaction.
+	pub(super) const fn remember_resolution(&mut self, resolution: Resolution) {
+		self.resolution = Some(resolution);
+	}
+
+	/// Sets the package that following actions are performed for.
+	pub fn set_package(&mut self, package: Option<&str>) {
+		self.package = package.map(str::to_string);
+	}
+
+	/// The package that following actions are performed for.
+	pub fn package(&self) -> Option<&str> {
+		self.package.as_deref()
+	}
+
+	/// The ownership database including the changes made so far.
+	pub const fn ownership(&self) -> &Ownership {
+		&self.ownership
+	}
+
+	/// The package that created the link at `link`, if any.
+	pub fn owner(&self, link: &Path) -> Option<&str> {
+		self.ownership.owner(link)
+	}
+
+	/// Records the existing link at `link` as created for the current
+	/// package, without touching the filesystem, unless it already has an
+	/// owner.
+	pub fn claim(&mut self, link: &Path, points_to: &Path) {
+		if self.dry_run || self.ownership.owner(link).is_some() {
+			return;
+		}
+		if let Some(package) = &self.package {
+			self.ownership.add_link(package, link, points_to);
+		}
+	}
+
+	/// Drops the ownership record of `path`, e.g. when the link is gone.
+	pub fn forget(&mut self, path: &Path) {
+		if !self.dry_run {
+			self.ownership.forget(path);
+		}
+	}
+
+	/// Moves `path` into the backup store.
+	pub fn backup(&mut self, path: &Path) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] back up {}", path.display());
+			return Ok(());
+		}
+		let entry = BackupEntry::new(path, self.package.clone());
+		self.record(Action::Backup {
+			path:  path.to_path_buf(),
+			entry: entry.dir(),
+			data:  entry.data(),
+		})?;
+		backup::store(&entry, path)?;
+		self.ownership.forget(path);
+		info!("backed up {} as {}", path.display(), entry.id);
+		Ok(())
+	}
+
+	/// Notes a source file changed by adopting a target.
+	pub(super) fn record_adopted(&mut self, file: AdoptedFile) {
+		self.adopted.push(file);
+	}
+
+	/// The source files changed by adopting targets so far.
+	pub fn adopted(&self) -> &[AdoptedFile] {
+		&self.adopted
+	}
+
+	/// Creates a symlink at `link` pointing to `points_to`, creating missing
+	/// parent directories.
+	pub fn symlink(
+		&mut self,
+		points_to: &Path,
+		link: &Path,
+	) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!(
+				"[dry-run] link {} -> {}",
+				link.display(),
+				points_to.display()
+			);
+			return Ok(());
+		}
+		if let Some(parent) = link.parent() {
+			self.create_dir_all(parent)?;
+		}
+		self.record(Action::CreateSymlink {
+			link:      link.to_path_buf(),
+			points_to: points_to.to_path_buf(),
+		})?;
+		create_symlink(points_to, link)?;
+		if let Some(package) = &self.package {
+			self.ownership.add_link(package, link, points_to);
+		}
+		debug!("linked {} -> {}", link.display(), points_to.display());
+		Ok(())
+	}
+
+	/// Copies the file `source` to `target`, creating missing parent
+	/// directories.
+	pub fn copy_file(
+		&mut self,
+		source: &Path,
+		target: &Path,
+	) -> Result<(), LinkError> {
+		self.create_file(source, target, Deploy::Copy)
+	}
+
+	/// Hard links the file `source` at `target`, creating missing parent
+	/// directories.
+	pub fn hard_link(
+		&mut self,
+		source: &Path,
+		target: &Path,
+	) -> Result<(), LinkError> {
+		self.create_file(source, target, Deploy::Hardlink)
+	}
+
+	fn create_file(
+		&mut self,
+		source: &Path,
+		target: &Path,
+		deploy: Deploy,
+	) -> Result<(), LinkError> {
+		let verb = if deploy == Deploy::Hardlink {
+			"hard link"
+		} else {
+			"copy"
+		};
+		if self.dry_run {
+			println!(
+				"[dry-run] {verb} {} -> {}",
+				source.display(),
+				target.display()
+			);
+			return Ok(());
+		}
+		if let Some(parent) = target.parent() {
+			self.create_dir_all(parent)?;
+		}
+		self.record(Action::CreateFile {
+			path: target.to_path_buf(),
+		})?;
+		if deploy == Deploy::Hardlink {
+			fs::hard_link(source, target)?;
+		} else {
+			fs::copy(source, target)?;
+		}
+		self.claim_file(source, target, deploy)?;
+		debug!("{verb} {} -> {}", source.display(), target.display());
+		Ok(())
+	}
+
+	/// Records the file at `target` as deployed from `source` for the current
+	/// package, with the hash of its current content.
+	pub fn claim_file(
+		&mut self,
+		source: &Path,
+		target: &Path,
+		deploy: Deploy,
+	) -> Result<(), LinkError> {
+		if self.dry_run {
+			return Ok(());
+		}
+		if let Some(package) = &self.package {
+			let file = DeployedFile {
+				source: source.to_path_buf(),
+				hash: hash_file(target)?,
+				deploy,
+			};
+			self.ownership.add_file(package, target, file);
+		}
+		Ok(())
+	}
+
+	/// Removes the symlink at `link`.
+	pub fn remove_symlink(&mut self, link: &Path) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] remove {}", link.display());
+			return Ok(());
+		}
+		let points_to = fs::read_link(link)?;
+		self.record(Action::RemoveSymlink {
+			link: link.to_path_buf(),
+			points_to,
+		})?;
+		fs::remove_file(link)?;
+		self.ownership.forget(link);
+		debug!("removed {}", link.display());
+		Ok(())
+	}
+
+	/// Points the existing symlink at `link` to `points_to` instead.
+	///
+	/// The new link is created next to the old one and renamed over it, so
+	/// `link` never goes missing, not even briefly.
+	pub fn replace_symlink(
+		&mut self,
+		points_to: &Path,
+		link: &Path,
+	) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!(
+				"[dry-run] re-point {} -> {}",
+				link.display(),
+				points_to.display()
+			);
+			return Ok(());
+		}
+		let from = fs::read_link(link)?;
+		self.record(Action::ReplaceSymlink {
+			link: link.to_path_buf(),
+			from: from.clone(),
+			to:   points_to.to_path_buf(),
+		})?;
+		swap_symlink(points_to, link)?;
+		if let Some(package) = &self.package {
+			self.ownership.add_link(package, link, points_to);
+		}
+		debug!(
+			"re-pointed {} from {} to {}",
+			link.display(),
+			from.display(),
+			points_to.display()
+		);
+		Ok(())
+	}
+
+	/// Renames `from` to `to`.
+	pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] move {} -> {}", from.display(), to.display());
+			return Ok(());
+		}
+		self.record(Action::Rename {
+			from: from.to_path_buf(),
+			to:   to.to_path_buf(),
+		})?;
+		backup::move_path(from, to)?;
+		self.ownership.forget(from);
+		debug!("moved {} -> {}", from.display(), to.display());
+		Ok(())
+	}
+
+	/// Deletes `path` once the transaction commits. Until then it is moved
+	/// aside so that a rollback can put it back.
+	pub fn discard(&mut self, path: &Path) -> Result<(), LinkError> {
+		let dir = path.parent().unwrap_or(Path::new("."));
+		self.discard_into(path, dir)
+	}
+
+	/// Like [`Transaction::discard`], but keeps `path` in `dir` until the
+	/// transaction commits, so that its own directory can be removed.
+	pub fn discard_into(
+		&mut self,
+		path: &Path,
+		dir: &Path,
+	) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] delete {}", path.display());
+			return Ok(());
+		}
+		let stash = stash_path(path, dir, self.journal.len());
+		self.record(Action::Stash {
+			path:  path.to_path_buf(),
+			stash: stash.clone(),
+		})?;
+		fs::rename(path, &stash)?;
+		self.ownership.forget(path);
+		debug!("stashed {} at {}", path.display(), stash.display());
+		Ok(())
+	}
+
+	/// Creates `dir` and any missing ancestors.
+	pub fn create_dir_all(&mut self, dir: &Path) -> Result<(), LinkError> {
+		let missing: Vec<&Path> = dir
+			.ancestors()
+			.take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+			.collect();
+		for path in missing.into_iter().rev() {
+			self.create_dir(path)?;
+		}
+		Ok(())
+	}
+
+	/// Creates the directory `dir`.
+	pub fn create_dir(&mut self, dir: &Path) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] create directory {}", dir.display());
+			return Ok(());
+		}
+		self.record(Action::CreateDir {
+			path: dir.to_path_buf(),
+		})?;
+		fs::create_dir(dir)?;
+		if let Some(package) = &self.package {
+			self.ownership.add_dir(package, dir);
+		}
+		Ok(())
+	}
+
+	/// Removes the empty directory `dir`.
+	pub fn remove_dir(&mut self, dir: &Path) -> Result<(), LinkError> {
+		if self.dry_run {
+			println!("[dry-run] remove directory {}", dir.display());
+			return Ok(());
+		}
+		self.record(Action::RemoveDir {
+			path: dir.to_path_buf(),
+		})?;
+		fs::remove_dir(dir)?;
+		self.ownership.forget(dir);
+		Ok(())
+	}
+
+	/// Fails with [`LinkError::Interrupted`] if Ctrl-C was pressed.
+	pub fn check_interrupted() -> Result<(), LinkError> {
+		if INTERRUPTED.load(Ordering::SeqCst) {
+			return Err(LinkError::Interrupted);
+		}
+		Ok(())
+	}
+
+	/// Makes the changes permanent: stashed files are deleted and the journal
+	/// is removed.
+	pub fn commit(mut self) -> Result<(), LinkError> {
+		// Saved first: if this fails, dropping `self` rolls everything back.
+		if let Some(path) = &self.owned_path {
+			ownership::save_to(path, &self.ownership)?;
+		}
+		self.finished = true;
+		// Once the journal is gone the run can no longer be rolled back, so
+		// drop it before deleting anything that a rollback would need.
+		self.clear_journal()?;
+		for action in &self.journal {
+			if let Action::Stash { stash, .. } = action {
+				let removed = match fs::symlink_metadata(stash) {
+					Ok(meta) if meta.is_dir() => fs::remove_dir_all(stash),
+					Ok(_) => fs::remove_file(stash),
+					Err(e) => Err(e),
+				};
+				if let Err(e) = removed {
+					warn!("failed to delete {}: {e}", stash.display());
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Reverses every completed action, most recent first.
+	pub fn rollback(mut self) {
+		self.finished = true;
+		self.undo_all();
+	}
+
+	fn undo_all(&mut self) {
+		if self.journal.is_empty() {
+			return;
+		}
+		warn!("rolling back {} action(s)", self.journal.len());
+		undo_actions(&self.journal);
+		self.journal.clear();
+		if let Err(e) = self.clear_journal() {
+			error!("failed to remove journal: {e}");
+		}
+	}
+
+	fn record(&mut self, action: Action) -> Result<(), LinkError> {
+		Self::check_interrupted()?;
+		self.journal.push(action);
+		if let Some(path) = &self.journal_path {
+			fs::write(path, serde_json::to_vec_pretty(&self.journal)?)?;
+		}
+		Ok(())
+	}
+
+	fn clear_journal(&self) -> std::io::Result<()> {
+		match &self.journal_path {
+			Some(path) if path.exists() => fs::remove_file(path),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Drop for Transaction {
+	fn drop(&mut self) {
+		if !self.finished {
+			self.undo_all();
+		}
+	}
+}
+
+/// Returns the path of the transaction journal.
+pub fn journal_path() -> PathBuf {
+	state::state_file("journal.json")
+}
+
+/// Returns the path of the lock held by a running transaction.
+pub fn lock_path() -> PathBuf {
+	state::state_file("journal.lock")
+}
+
+/// Takes the exclusive lock on `path`, which is created if missing and never
+/// removed, so that every run locks the same file.
+fn take_lock(path: &Path) -> Result<fs::File, LinkError> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let file = fs::OpenOptions::new()
+		.create(true)
+		.truncate(false)
+		.write(true)
+		.open(path)?;
+	match file.try_lock() {
+		Ok(()) => Ok(file),
+		Err(fs::TryLockError::WouldBlock) => {
+			Err(LinkError::Locked(path.to_path_buf()))
+		}
+		Err(fs::TryLockError::Error(e)) => Err(e.into()),
+	}
+}
+
+/// Rolls back the journal left behind by an interrupted run.
+fn recover(path: &Path) -> Result<(), LinkError> {
+	let journal: Vec<Action> = serde_json::from_slice(&fs::read(path)?)?;
+	warn!(
+		"rolling back {} action(s) from an interrupted run",
+		journal.len()
+	);
+	undo_actions(&journal);
+	fs::remove_file(path)?;
+	Ok(())
+}
+
+fn undo_actions(journal: &[Action]) {
+	for action in journal.iter().rev() {
+		if let Err(e) = action.undo() {
+			error!("failed to undo {action:?}: {e}");
+		}
+	}
+}
+
+fn install_interrupt_handler() {
+	HANDLER.call_once(|| {
+		if let Err(e) = ctrlc::set_handler(|| {
+			eprintln!("interrupted, rolling back...");
+			INTERRUPTED.store(true, Ordering::SeqCst);
+		}) {
+			warn!("failed to install Ctrl-C handler: {e}");
+		}
+	});
+}
+
+/// Atomically replaces the symlink at `link` with one pointing to
+/// `points_to`, through a temporary link renamed over it.
+fn swap_symlink(points_to: &Path, link: &Path) -> std::io::Result<()> {
+	let name = link
+		.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	let tmp = link
+		.with_file_name(format!(".{name}.rinkle-{}-new", std::process::id()));
+	let _ = fs::remove_file(&tmp);
+	create_symlink(points_to, &tmp)?;
+	fs::rename(&tmp, link).inspect_err(|_| {
+		let _ = fs::remove_file(&tmp);
+	})
+}
+
+/// A path in `dir` used to keep a discarded file until commit; `n` keeps
+/// files of the same name apart.
+fn stash_path(path: &Path, dir: &Path, n: usize) -> PathBuf {
+	let name = path
+		.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	dir.join(format!(".{name}.rinkle-{}-{n}", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn rollback_restores_previous_state() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src");
+		fs::write(&src, "source").unwrap();
+		let existing = tmp.path().join("existing");
+		fs::write(&existing, "keep me").unwrap();
+		let link = tmp.path().join("nested/dir/link");
+
+		let mut tx = Transaction::new(None, false);
+		tx.discard(&existing).unwrap();
+		tx.symlink(&src, &existing).unwrap();
+		tx.symlink(&src, &link).unwrap();
+		assert!(link.exists());
+		tx.rollback();
+
+		assert_eq!(fs::read_to_string(&existing).unwrap(), "keep me");
+		assert!(!tmp.path().join("nested").exists());
+		assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+	}
+
+	#[test]
+	fn recover_undoes_journal_on_disk() {
+		let tmp = tempdir().unwrap();
+		let journal = tmp.path().join("journal.json");
+		let original = tmp.path().join("file");
+		let backup = tmp.path().join("file.bak");
+		fs::write(&original, "data").unwrap();
+
+		let mut tx = Transaction::new(Some(journal.clone()), false);
+		tx.rename(&original, &backup).unwrap();
+		// simulate a crash: the journal survives, nothing is rolled back
+		std::mem::forget(tx);
+		assert!(journal.exists());
+
+		recover(&journal).unwrap();
+		assert_eq!(fs::read_to_string(&original).unwrap(), "data");
+		assert!(!backup.exists());
+		assert!(!journal.exists());
+	}
+
+	#[test]
+	fn lock_is_exclusive_until_released() {
+		let tmp = tempdir().unwrap();
+		let path = tmp.path().join("state/journal.lock");
+
+		let held = take_lock(&path).unwrap();
+		assert!(matches!(take_lock(&path), Err(LinkError::Locked(_))));
+		drop(held);
+		take_lock(&path).unwrap();
+	}
+
+	#[test]
+	fn replace_symlink_is_undone() {
+		let tmp = tempdir().unwrap();
+		let (old, new) = (tmp.path().join("old"), tmp.path().join("new"));
+		let link = tmp.path().join("link");
+		create_symlink(&old, &link).unwrap();
+
+		let mut tx = Transaction::new(None, false);
+		tx.replace_symlink(&new, &link).unwrap();
+		assert_eq!(fs::read_link(&link).unwrap(), new);
+		tx.rollback();
+
+		assert_eq!(fs::read_link(&link).unwrap(), old);
+		assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+	}
+}
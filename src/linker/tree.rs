@@ -0,0 +1,627 @@
+//! Stow-style tree linking.
+//!
+//! In tree mode a package's file tree is mirrored into its target. A directory
+//! that only one package contributes to is *folded* into a single symlink.
+//! When a second package needs to place entries inside a folded directory,
+//! the symlink is *unfolded* into a real directory holding one link per entry
+//! of the first package, after which both packages can share it. Removing a
+//! package reverses this: emptied directories are deleted and directories left
+//! with links into a single package are folded again.
+//!
+//! Ignored entries are never linked, so a directory containing any of them is
+//! always mirrored instead of folded. Entries placed when unfolding follow the
+//! ignore rules of the package that owns the folded link.
+
+use std::{
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use tracing::{
+	debug,
+	info,
+	warn,
+};
+
+use super::{
+	FileStatus,
+	IgnoreRules,
+	LinkError,
+	Transaction,
+	file_status,
+	handle_conflict,
+	linker_impl::{
+		resolve_targets,
+		source_root,
+		target_root,
+	},
+	paths::{
+		canonical,
+		link_destination,
+		link_value,
+		same_path,
+	},
+};
+use crate::{
+	config::{
+		Config,
+		ConflictStrategy,
+		LinkStyle,
+		Package,
+	},
+	ownership::Ownership,
+};
+
+/// Links, unlinks and inspects packages in tree mode.
+pub(super) struct TreeLinker<'a> {
+	cfg:         &'a Config,
+	/// Canonical `global.source_dir`; links resolving below it are owned by
+	/// rinkle and may be unfolded.
+	source_root: PathBuf,
+	/// Directory that is never removed when it becomes empty.
+	target_root: PathBuf,
+	strategy:    ConflictStrategy,
+	style:       LinkStyle,
+	rules:       &'a IgnoreRules,
+}
+
+impl<'a> TreeLinker<'a> {
+	pub(super) fn new(
+		cfg: &'a Config,
+		strategy: ConflictStrategy,
+		style: LinkStyle,
+		rules: &'a IgnoreRules,
+	) -> Self {
+		Self {
+			cfg,
+			source_root: canonical(&source_root(cfg)),
+			target_root: target_root(cfg),
+			strategy,
+			style,
+			rules,
+		}
+	}
+
+	/// Mirrors the directory `src` into `dst`.
+	pub(super) fn link(
+		&self,
+		src: &Path,
+		dst: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		match fs::symlink_metadata(dst) {
+			Err(_) if !self.rules.ignores_within(src) => {
+				return self.symlink(src, dst, tx);
+			}
+			Err(_) => tx.create_dir_all(dst)?,
+			Ok(meta) if meta.file_type().is_symlink() => {
+				let points_to = link_destination(dst)?;
+				let folded_here = same_path(&points_to, src);
+				if folded_here && !self.rules.ignores_within(src) {
+					debug!("{} already folded", dst.display());
+					tx.claim(dst, &fs::read_link(dst)?);
+					return Ok(());
+				}
+				if folded_here || (self.owns(&points_to) && points_to.is_dir())
+				{
+					self.unfold(dst, &points_to, tx)?;
+				} else {
+					return self.resolve_conflict(src, dst, tx);
+				}
+			}
+			Ok(meta) if meta.is_dir() => {}
+			Ok(_) => return self.resolve_conflict(src, dst, tx),
+		}
+
+		for (child_src, name) in self.entries(src)? {
+			let child_dst = dst.join(&name);
+			if is_real_dir(&child_src) {
+				self.link(&child_src, &child_dst, tx)?;
+			} else {
+				self.link_file(&child_src, &child_dst, tx)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Removes the links pointing into `src` from `dst`, folding or deleting
+	/// directories that are left behind.
+	pub(super) fn unlink(
+		&self,
+		src: &Path,
+		dst: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		match fs::symlink_metadata(dst) {
+			Ok(meta) if meta.file_type().is_symlink() => {
+				if same_path(&link_destination(dst)?, src) {
+					remove_managed(dst, tx)?;
+				}
+				return Ok(());
+			}
+			Ok(meta) if meta.is_dir() => {}
+			_ => return Ok(()),
+		}
+
+		for (child_src, name) in self.entries(src)? {
+			let child_dst = dst.join(&name);
+			if is_real_dir(&child_src) {
+				self.unlink(&child_src, &child_dst, tx)?;
+			} else {
+				let is_link = fs::symlink_metadata(&child_dst)
+					.is_ok_and(|m| m.file_type().is_symlink());
+				if is_link &&
+					same_path(&link_destination(&child_dst)?, &child_src)
+				{
+					remove_managed(&child_dst, tx)?;
+				}
+			}
+		}
+
+		if !tx.dry_run() {
+			self.cleanup(dst, tx)?;
+		}
+		Ok(())
+	}
+
+	fn link_file(
+		&self,
+		src: &Path,
+		dst: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		match fs::symlink_metadata(dst) {
+			Err(_) => self.symlink(src, dst, tx),
+			Ok(meta) if meta.file_type().is_symlink() => {
+				let points_to = link_destination(dst)?;
+				if same_path(&points_to, src) {
+					tx.claim(dst, &fs::read_link(dst)?);
+					Ok(())
+				} else if self.owns(&points_to) {
+					Err(LinkError::Conflict(format!(
+						"{} is already linked to {}",
+						dst.display(),
+						points_to.display()
+					)))
+				} else {
+					self.resolve_conflict(src, dst, tx)
+				}
+			}
+			Ok(_) => self.resolve_conflict(src, dst, tx),
+		}
+	}
+
+	fn resolve_conflict(
+		&self,
+		src: &Path,
+		dst: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		if handle_conflict(src, dst, self.strategy, tx)? {
+			self.symlink(src, dst, tx)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Replaces the folded link at `dst` with a real directory containing one
+	/// link per entry of `points_to` that its owner does not ignore.
+	fn unfold(
+		&self,
+		dst: &Path,
+		points_to: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		if tx.dry_run() {
+			println!("[dry-run] unfold {}", dst.display());
+			return Ok(());
+		}
+		// The unfolded directory and links belong to the folded link's owner.
+		let current = tx.package().map(str::to_string);
+		let owner = tx.owner(dst).map(str::to_string);
+		let rules = self.owner_rules(owner.as_deref(), dst, points_to);
+		tx.remove_symlink(dst)?;
+		tx.set_package(owner.as_deref());
+		tx.create_dir(dst)?;
+		for (entry, name) in entries(points_to, &rules)? {
+			self.symlink(&entry, &dst.join(name), tx)?;
+		}
+		tx.set_package(current.as_deref());
+		info!("unfolded {}", dst.display());
+		Ok(())
+	}
+
+	/// Folds `dir` back into a single link if every entry links into the same
+	/// owned directory, or removes it if it is empty. Only directories rinkle
+	/// created are touched.
+	fn cleanup(
+		&self,
+		dir: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		if dir == self.target_root || tx.ownership().dir_owner(dir).is_none() {
+			return Ok(());
+		}
+		let entries = sorted_entries(dir)?;
+		if entries.is_empty() {
+			tx.remove_dir(dir)?;
+			info!("removed empty directory {}", dir.display());
+			return Ok(());
+		}
+
+		let mut parent: Option<PathBuf> = None;
+		let mut owner: Option<String> = None;
+		for (entry, name) in &entries {
+			if !fs::symlink_metadata(entry)
+				.is_ok_and(|m| m.file_type().is_symlink())
+			{
+				return Ok(());
+			}
+			match (tx.owner(entry), &owner) {
+				(None, _) => return Ok(()),
+				(Some(o), Some(prev)) if o != prev => return Ok(()),
+				(Some(o), _) => owner = Some(o.to_string()),
+			}
+			let points_to = link_destination(entry)?;
+			if points_to.file_name() != Some(name.as_os_str()) {
+				return Ok(());
+			}
+			let Some(points_to_parent) = points_to.parent() else {
+				return Ok(());
+			};
+			match &parent {
+				Some(p) if p != points_to_parent => return Ok(()),
+				Some(_) => {}
+				None => parent = Some(points_to_parent.to_path_buf()),
+			}
+		}
+		let Some(parent) = parent else {
+			return Ok(());
+		};
+		if !self.owns(&parent) ||
+			sorted_entries(&parent)?.len() != entries.len()
+		{
+			return Ok(());
+		}
+
+		for (entry, _) in &entries {
+			tx.remove_symlink(entry)?;
+		}
+		tx.remove_dir(dir)?;
+		let current = tx.package().map(str::to_string);
+		tx.set_package(owner.as_deref());
+		self.symlink(&parent, dir, tx)?;
+		tx.set_package(current.as_deref());
+		info!("folded {} -> {}", dir.display(), parent.display());
+		Ok(())
+	}
+
+	fn symlink(
+		&self,
+		points_to: &Path,
+		link: &Path,
+		tx: &mut Transaction,
+	) -> Result<(), LinkError> {
+		tx.symlink(&link_value(points_to, link, self.style), link)
+	}
+
+	fn owns(&self, path: &Path) -> bool {
+		canonical(path).starts_with(&self.source_root)
+	}
+
+	/// The ignore rules of `owner`, whose folded link at `dst` points to
+	/// `points_to`. A package that is no longer configured only has the
+	/// global ones.
+	fn owner_rules(
+		&self,
+		owner: Option<&str>,
+		dst: &Path,
+		points_to: &Path,
+	) -> IgnoreRules {
+		let default = Package::default();
+		let Some((name, pkg)) =
+			owner.and_then(|name| Some((name, self.cfg.packages.get(name)?)))
+		else {
+			return IgnoreRules::new(self.cfg, &default, points_to);
+		};
+		// The source of the mapping targeting an ancestor of `dst` is the
+		// same number of levels above `points_to`.
+		let mut source = points_to.to_path_buf();
+		if let Some(rel) = resolve_targets(name, pkg, self.cfg)
+			.iter()
+			.find_map(|target| dst.strip_prefix(target).ok())
+		{
+			for _ in rel.components() {
+				source.pop();
+			}
+		}
+		IgnoreRules::new(self.cfg, pkg, &source)
+	}
+
+	/// The entries of the source directory `dir` that are not ignored.
+	fn entries(
+		&self,
+		dir: &Path,
+	) -> std::io::Result<Vec<(PathBuf, std::ffi::OsString)>> {
+		entries(dir, self.rules)
+	}
+}
+
+/// The entries of the source directory `dir` that `rules` do not ignore.
+fn entries(
+	dir: &Path,
+	rules: &IgnoreRules,
+) -> std::io::Result<Vec<(PathBuf, std::ffi::OsString)>> {
+	Ok(sorted_entries(dir)?
+		.into_iter()
+		.filter(|(path, _)| !rules.is_ignored(path, is_real_dir(path)))
+		.collect())
+}
+
+/// Removes the link at `link` if rinkle created it.
+fn remove_managed(link: &Path, tx: &mut Transaction) -> Result<(), LinkError> {
+	if tx.owner(link).is_some() {
+		tx.remove_symlink(link)
+	} else {
+		warn!(
+			"{} exists but is not managed by rinkle, leaving it in place",
+			link.display()
+		);
+		Ok(())
+	}
+}
+
+/// Reports the status of every file in `src` as seen through `dst`.
+pub(super) fn tree_status(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	owned: &Ownership,
+) -> Vec<FileStatus> {
+	let mut files = Vec::new();
+	collect_status(src, dst, rules, owned, &mut files);
+	files
+}
+
+fn collect_status(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	owned: &Ownership,
+	files: &mut Vec<FileStatus>,
+) {
+	let Ok(entries) = sorted_entries(src) else {
+		return;
+	};
+	for (child_src, name) in entries {
+		let child_dst = dst.join(&name);
+		let is_dir = is_real_dir(&child_src);
+		if rules.is_ignored(&child_src, is_dir) {
+			continue;
+		}
+		if is_dir {
+			collect_status(&child_src, &child_dst, rules, owned, files);
+			continue;
+		}
+		files.push(FileStatus {
+			status: file_status(&child_src, &child_dst, owned),
+			source: child_src,
+			target: child_dst,
+		});
+	}
+}
+
+/// The target paths a tree package may occupy, as `(path, is_dir)` pairs.
+///
+/// Every file is included, as is every directory that is not already a real
+/// directory in the target and may therefore be folded into a link.
+pub(super) fn tree_claims(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+) -> Vec<(PathBuf, bool)> {
+	let mut claims = Vec::new();
+	collect_claims(src, dst, rules, &mut claims);
+	claims
+}
+
+fn collect_claims(
+	src: &Path,
+	dst: &Path,
+	rules: &IgnoreRules,
+	claims: &mut Vec<(PathBuf, bool)>,
+) {
+	if !is_real_dir(dst) {
+		claims.push((dst.to_path_buf(), true));
+	}
+	let Ok(entries) = sorted_entries(src) else {
+		return;
+	};
+	for (child_src, name) in entries {
+		let child_dst = dst.join(&name);
+		let is_dir = is_real_dir(&child_src);
+		if rules.is_ignored(&child_src, is_dir) {
+			continue;
+		}
+		if is_dir {
+			collect_claims(&child_src, &child_dst, rules, claims);
+		} else {
+			claims.push((child_dst, false));
+		}
+	}
+}
+
+/// Returns the entries of `dir` as `(path, file name)` pairs in name order.
+pub(super) fn sorted_entries(
+	dir: &Path,
+) -> std::io::Result<Vec<(PathBuf, std::ffi::OsString)>> {
+	let mut entries = fs::read_dir(dir)?
+		.map(|e| e.map(|e| (e.path(), e.file_name())))
+		.collect::<Result<Vec<_>, _>>()?;
+	entries.sort_by(|a, b| a.1.cmp(&b.1));
+	Ok(entries)
+}
+
+/// A directory that is not itself a symlink. Symlinks inside a package are
+/// linked as leaves rather than descended into.
+pub fn is_real_dir(path: &Path) -> bool {
+	fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+	use crate::{
+		config::Global,
+		linker::LinkStatusKind,
+	};
+
+	fn write(path: &Path) {
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(path, path.display().to_string()).unwrap();
+	}
+
+	fn base_cfg(source: &Path, target: &Path) -> Config {
+		Config {
+			global: Global {
+				source_dir: Some(source.display().to_string()),
+				target_dir: Some(target.display().to_string()),
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn fold_unfold_and_refold() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src");
+		let dst = tmp.path().join("dst");
+		fs::create_dir_all(&dst).unwrap();
+		write(&src.join("a/conf.d/a.fish"));
+		write(&src.join("b/conf.d/b.fish"));
+		let cfg = base_cfg(&src, &dst);
+		let rules = IgnoreRules::new(&cfg, &Package::default(), &src);
+		let linker = TreeLinker::new(
+			&cfg,
+			ConflictStrategy::Skip,
+			LinkStyle::Relative,
+			&rules,
+		);
+		let mut tx = Transaction::new(None, false);
+		let fish = dst.join("fish");
+		let status_of = |src: &Path, dst: &Path, tx: &Transaction| {
+			let kinds: Vec<_> = tree_status(src, dst, &rules, tx.ownership())
+				.iter()
+				.map(|f| f.status)
+				.collect();
+			LinkStatusKind::aggregate(&kinds)
+		};
+
+		// first package owns the directory: folded into one symlink
+		tx.set_package(Some("a"));
+		linker.link(&src.join("a"), &fish, &mut tx).unwrap();
+		assert!(
+			fs::symlink_metadata(&fish)
+				.unwrap()
+				.file_type()
+				.is_symlink()
+		);
+
+		// second package forces an unfold down to conf.d
+		tx.set_package(Some("b"));
+		linker.link(&src.join("b"), &fish, &mut tx).unwrap();
+		let conf_d = fish.join("conf.d");
+		assert!(fs::symlink_metadata(&fish).unwrap().is_dir());
+		assert!(fs::symlink_metadata(&conf_d).unwrap().is_dir());
+		assert!(same_path(
+			&conf_d.join("a.fish"),
+			&src.join("a/conf.d/a.fish")
+		));
+		assert!(same_path(
+			&conf_d.join("b.fish"),
+			&src.join("b/conf.d/b.fish")
+		));
+		assert_eq!(status_of(&src.join("a"), &fish, &tx), LinkStatusKind::Ok);
+		assert_eq!(status_of(&src.join("b"), &fish, &tx), LinkStatusKind::Ok);
+
+		// removing the second package folds everything back onto the first
+		assert_eq!(tx.owner(&conf_d.join("a.fish")), Some("a"));
+		assert_eq!(tx.owner(&conf_d.join("b.fish")), Some("b"));
+		linker.unlink(&src.join("b"), &fish, &mut tx).unwrap();
+		assert!(
+			fs::symlink_metadata(&fish)
+				.unwrap()
+				.file_type()
+				.is_symlink()
+		);
+		assert!(same_path(&fish, &src.join("a")));
+		assert_eq!(
+			status_of(&src.join("b"), &fish, &tx),
+			LinkStatusKind::Missing
+		);
+
+		assert_eq!(tx.owner(&fish), Some("a"));
+		tx.set_package(Some("a"));
+		linker.unlink(&src.join("a"), &fish, &mut tx).unwrap();
+		assert!(fs::symlink_metadata(&fish).is_err());
+		tx.commit().unwrap();
+	}
+
+	#[test]
+	fn unfolding_follows_the_owners_ignore_rules() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src");
+		let dst = tmp.path().join("dst");
+		fs::create_dir_all(&dst).unwrap();
+		write(&src.join("a/conf.d/a.fish"));
+		write(&src.join("b/conf.d/b.fish"));
+		let mut cfg = base_cfg(&src, &dst);
+		for (name, ignore) in [("a", vec!["*.tmp".to_string()]), ("b", vec![])]
+		{
+			cfg.packages.insert(name.into(), Package {
+				target: Some("fish".into()),
+				ignore,
+				..Package::default()
+			});
+		}
+		let rules_of = |name: &str| {
+			IgnoreRules::new(&cfg, &cfg.packages[name], &src.join(name))
+		};
+		let (rules_a, rules_b) = (rules_of("a"), rules_of("b"));
+		let linker_of = |rules| {
+			TreeLinker::new(
+				&cfg,
+				ConflictStrategy::Skip,
+				LinkStyle::Relative,
+				rules,
+			)
+		};
+		let mut tx = Transaction::new(None, false);
+		let fish = dst.join("fish");
+
+		tx.set_package(Some("a"));
+		linker_of(&rules_a)
+			.link(&src.join("a"), &fish, &mut tx)
+			.unwrap();
+		// Shows up through the folded link, but `a` ignores it.
+		write(&src.join("a/conf.d/cache.tmp"));
+
+		tx.set_package(Some("b"));
+		linker_of(&rules_b)
+			.link(&src.join("b"), &fish, &mut tx)
+			.unwrap();
+		let conf_d = fish.join("conf.d");
+		assert!(same_path(
+			&conf_d.join("a.fish"),
+			&src.join("a/conf.d/a.fish")
+		));
+		assert!(fs::symlink_metadata(conf_d.join("cache.tmp")).is_err());
+		tx.commit().unwrap();
+	}
+}
@@ -1,8 +1,16 @@
+use std::process::ExitCode;
+
 use rinkle::App;
 
-fn main() {
-	tracing_subscriber::fmt().init();
-	if App::new().run().is_err() {
-		std::process::exit(1);
+fn main() -> ExitCode {
+	tracing_subscriber::fmt()
+		.with_writer(std::io::stderr)
+		.init();
+	match App::new().run() {
+		Ok(code) => code,
+		Err(e) => {
+			tracing::error!("{e:#}");
+			ExitCode::FAILURE
+		}
 	}
 }
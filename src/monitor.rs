@@ -1,8 +1,5 @@
 use std::{
-	path::{
-		Path,
-		PathBuf,
-	},
+	path::Path,
 	sync::{
 		Arc,
 		atomic::{
@@ -33,9 +30,15 @@ use tracing::{
 };
 
 use crate::{
+	config::{
+		self,
+		Config,
+	},
 	daemon,
 	ipc,
+	linker,
 	utils::socket_path,
+	vsc,
 };
 
 // --- Constants ---
@@ -72,16 +75,14 @@ pub fn start() -> Result<()> {
 /// Stops the running monitor daemon.
 pub fn stop() -> Result<()> {
 	info!("Requesting to stop monitor daemon...");
-	let pid = if let Some(pid) = daemon::get_running_pid()? {
-		pid
-	} else {
+	let Some(pid) = daemon::get_running_pid()? else {
 		info!("Monitor is not running.");
 		return Ok(());
 	};
 
 	// First, try a graceful shutdown via IPC.
 	info!("Attempting graceful shutdown via IPC...");
-	match ipc::send_request(&socket_path(), ipc::Request::Stop) {
+	match ipc::send_request(&socket_path(), &ipc::Request::Stop) {
 		Ok(ipc::Response::Ok) => {
 			info!("Daemon acknowledged stop request.");
 		}
@@ -122,7 +123,7 @@ pub fn status() -> bool {
 	}
 	// Process is running, now check for service responsiveness via IPC ping.
 	if matches!(
-		ipc::send_request(&socket_path(), ipc::Request::Ping),
+		ipc::send_request(&socket_path(), &ipc::Request::Ping),
 		Ok(ipc::Response::Pong)
 	) {
 		true
@@ -146,7 +147,7 @@ pub fn run_service_loop() -> Result<()> {
 	}
 
 	let shutdown_signal = Arc::new(AtomicBool::new(false));
-	let (watcher_shutdown_tx, _watcher_shutdown_rx) = mpsc::channel::<()>();
+	let (watcher_shutdown_tx, watcher_shutdown_rx) = mpsc::channel::<()>();
 
 	// Graceful shutdown for Unix via SIGTERM.
 	#[cfg(unix)]
@@ -163,13 +164,13 @@ pub fn run_service_loop() -> Result<()> {
 		});
 	}
 
-	let watcher_handle: Option<thread::JoinHandle<()>> = None;
-	// if let Some(src_dir) = resolve_source_dir() {
-	//     let thread_config_path = config_path.to_path_buf();
-	//     watcher_handle = Some(spawn_watcher_thread(src_dir,
-	// watcher_shutdown_rx, thread_config_path)); } else {
-	//     warn!("No 'global.source_dir' configured – file watcher is
-	// disabled."); }
+	let watcher_handle = if let Some(cfg) = config::CFG.get() {
+		refresh_versions(cfg);
+		Some(spawn_watcher_thread(cfg, watcher_shutdown_rx))
+	} else {
+		warn!("No config loaded – file watcher is disabled.");
+		None
+	};
 
 	// The IPC handler closure.
 	let shutdown_signal_clone = shutdown_signal.clone();
@@ -201,11 +202,23 @@ pub fn run_service_loop() -> Result<()> {
 	Ok(())
 }
 
-fn _spawn_watcher_thread(
-	path: PathBuf,
+/// Rebuilds the version cache if the source directory changed, so commands
+/// find it fresh.
+fn refresh_versions(cfg: &Config) {
+	match vsc::discover(cfg, false) {
+		Ok(versions) => debug!(
+			packages = versions.packages.len(),
+			"Version cache is up to date."
+		),
+		Err(e) => warn!(error = %e, "Failed to discover versions"),
+	}
+}
+
+fn spawn_watcher_thread(
+	cfg: &'static Config,
 	shutdown_rx: Receiver<()>,
-	_config_path: PathBuf,
 ) -> thread::JoinHandle<()> {
+	let path = linker::source_root(cfg);
 	info!(watch_path = %path.display(), "Spawning filesystem watcher thread.");
 	thread::spawn(move || {
 		if !path.exists() {
@@ -255,6 +268,7 @@ fn _spawn_watcher_thread(
 				}
 				Err(mpsc::RecvTimeoutError::Timeout) => {
 					if needs_relink {
+						refresh_versions(cfg);
 						// TODO: Implement the relink action.
 						debug!(
 							"Debounce timeout reached. File system event was \
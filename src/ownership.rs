@@ -0,0 +1,239 @@
+//! The database of links and directories created by rinkle.
+//!
+//! Every link, copied or hard-linked file and directory a transaction creates
+//! while processing a package is recorded under that package in `owned.toml`
+//! next to `state.toml`, and forgotten again when rinkle removes it. Copies
+//! carry the hash of the content deployed, so later changes to either side
+//! can be detected. `remove`, `prune` and `status` only ever act on recorded
+//! entries, so links made by hand are left alone even when they point into
+//! the repository.
+
+use std::{
+	collections::{
+		BTreeMap,
+		BTreeSet,
+	},
+	fs,
+	io,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use thiserror::Error;
+
+use crate::{
+	config::Deploy,
+	state,
+};
+
+/// Errors that can occur while reading or writing the ownership database.
+#[derive(Debug, Error)]
+pub enum OwnershipError {
+	/// An I/O error occurred while reading or writing the database.
+	#[error("io error: {0}")]
+	Io(#[from] io::Error),
+	/// The database could not be parsed.
+	#[error("toml parse error: {0}")]
+	TomlDe(#[from] toml::de::Error),
+	/// The database could not be serialized.
+	#[error("toml serialize error: {0}")]
+	TomlSer(#[from] toml::ser::Error),
+}
+
+/// What rinkle created for one package.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owned {
+	/// Link path to the value stored in the link.
+	#[serde(default)]
+	pub links: BTreeMap<PathBuf, PathBuf>,
+	/// Directories created to hold the links.
+	#[serde(default)]
+	pub dirs:  BTreeSet<PathBuf>,
+	/// Copied or hard-linked file path to what was deployed there.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub files: BTreeMap<PathBuf, DeployedFile>,
+}
+
+/// A file rinkle copied or hard linked into place.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeployedFile {
+	pub source: PathBuf,
+	/// SHA-256 of the content deployed, in hex.
+	pub hash:   String,
+	pub deploy: Deploy,
+}
+
+/// The links and directories created by rinkle, keyed by package.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ownership {
+	#[serde(default)]
+	pub packages: BTreeMap<String, Owned>,
+}
+
+impl Ownership {
+	/// The package that created the link at `link`, if any.
+	pub fn owner(&self, link: &Path) -> Option<&str> {
+		self.packages
+			.iter()
+			.find(|(_, owned)| owned.links.contains_key(link))
+			.map(|(name, _)| name.as_str())
+	}
+
+	/// The package that created the directory `dir`, if any.
+	pub fn dir_owner(&self, dir: &Path) -> Option<&str> {
+		self.packages
+			.iter()
+			.find(|(_, owned)| owned.dirs.contains(dir))
+			.map(|(name, _)| name.as_str())
+	}
+
+	/// The package that deployed the file at `path`, and what it deployed.
+	pub fn deployed(&self, path: &Path) -> Option<(&str, &DeployedFile)> {
+		self.packages.iter().find_map(|(name, owned)| {
+			owned.files.get(path).map(|file| (name.as_str(), file))
+		})
+	}
+
+	/// Whether `path` is a recorded link or lies inside one, as the files of
+	/// a folded tree package do.
+	pub fn manages(&self, path: &Path) -> bool {
+		path.ancestors().any(|p| self.owner(p).is_some())
+	}
+
+	/// Every recorded link with its value and package.
+	pub fn links(&self) -> impl Iterator<Item = (&str, &Path, &Path)> {
+		self.packages.iter().flat_map(|(name, owned)| {
+			owned.links.iter().map(move |(link, to)| {
+				(name.as_str(), link.as_path(), to.as_path())
+			})
+		})
+	}
+
+	/// Every recorded copied or hard-linked file with its record and package.
+	pub fn files(&self) -> impl Iterator<Item = (&str, &Path, &DeployedFile)> {
+		self.packages.iter().flat_map(|(name, owned)| {
+			owned
+				.files
+				.iter()
+				.map(move |(path, file)| (name.as_str(), path.as_path(), file))
+		})
+	}
+
+	pub fn add_link(&mut self, package: &str, link: &Path, points_to: &Path) {
+		self.forget(link);
+		self.packages
+			.entry(package.to_string())
+			.or_default()
+			.links
+			.insert(link.to_path_buf(), points_to.to_path_buf());
+	}
+
+	pub fn add_file(&mut self, package: &str, path: &Path, file: DeployedFile) {
+		self.forget(path);
+		self.packages
+			.entry(package.to_string())
+			.or_default()
+			.files
+			.insert(path.to_path_buf(), file);
+	}
+
+	pub fn add_dir(&mut self, package: &str, dir: &Path) {
+		self.packages
+			.entry(package.to_string())
+			.or_default()
+			.dirs
+			.insert(dir.to_path_buf());
+	}
+
+	/// Drops every record of `path`, as a link, a file or a directory.
+	pub fn forget(&mut self, path: &Path) {
+		for owned in self.packages.values_mut() {
+			owned.links.remove(path);
+			owned.files.remove(path);
+			owned.dirs.remove(path);
+		}
+		self.packages.retain(|_, owned| {
+			!owned.links.is_empty() ||
+				!owned.files.is_empty() ||
+				!owned.dirs.is_empty()
+		});
+	}
+}
+
+/// Returns the path of the ownership database.
+pub fn db_path() -> PathBuf {
+	state::state_file("owned.toml")
+}
+
+/// Loads the ownership database, empty if it does not exist yet.
+pub fn load() -> Result<Ownership, OwnershipError> {
+	load_from(&db_path())
+}
+
+pub fn load_from(path: &Path) -> Result<Ownership, OwnershipError> {
+	match fs::read_to_string(path) {
+		Ok(content) => Ok(toml::from_str(&content)?),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {
+			Ok(Ownership::default())
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+pub fn save_to(
+	path: &Path,
+	ownership: &Ownership,
+) -> Result<(), OwnershipError> {
+	fs::write(path, toml::to_string_pretty(ownership)?)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn records_round_trip() {
+		let tmp = tempdir().unwrap();
+		let path = tmp.path().join("owned.toml");
+		let mut owned = Ownership::default();
+		owned.add_link("fish", Path::new("/t/fish"), Path::new("/s/fish"));
+		owned.add_dir("nvim", Path::new("/t/nvim"));
+		owned.add_link(
+			"nvim",
+			Path::new("/t/nvim/init.lua"),
+			Path::new("/s/i"),
+		);
+		owned.add_file("git", Path::new("/t/.gitconfig"), DeployedFile {
+			source: PathBuf::from("/s/git/gitconfig"),
+			hash:   "00ff".into(),
+			deploy: Deploy::Copy,
+		});
+		save_to(&path, &owned).unwrap();
+
+		let loaded = load_from(&path).unwrap();
+		assert_eq!(loaded, owned);
+		assert_eq!(loaded.owner(Path::new("/t/fish")), Some("fish"));
+		assert_eq!(loaded.dir_owner(Path::new("/t/nvim")), Some("nvim"));
+		assert!(loaded.manages(Path::new("/t/fish/conf.d/a.fish")));
+		assert!(!loaded.manages(Path::new("/t/nvim/lua")));
+		assert_eq!(
+			loaded
+				.deployed(Path::new("/t/.gitconfig"))
+				.map(|(p, f)| (p, f.deploy)),
+			Some(("git", Deploy::Copy))
+		);
+
+		owned.forget(Path::new("/t/fish"));
+		assert_eq!(owned.owner(Path::new("/t/fish")), None);
+		assert!(!owned.packages.contains_key("fish"));
+	}
+}
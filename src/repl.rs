@@ -39,7 +39,7 @@ pub fn run() -> std::io::Result<()> {
 
 		// Execute command by spawning self
 		if let Err(e) = execute_command(&parts) {
-			eprintln!("command failed: {}", e);
+			eprintln!("command failed: {e}");
 		}
 	}
 	Ok(())
@@ -50,10 +50,7 @@ fn execute_command(args: &[&str]) -> std::io::Result<()> {
 	let status = std::process::Command::new(exe).args(args).status()?;
 
 	if !status.success() {
-		return Err(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			format!("command {:?} failed", args),
-		));
+		return Err(std::io::Error::other(format!("command {args:?} failed")));
 	}
 	Ok(())
 }
@@ -28,16 +28,16 @@ pub fn init(
 	std::fs::create_dir_all(&cfg_dir)?;
 	let cfg_path = cfg_dir.join("rinkle.toml");
 
-	if !cfg_path.exists() {
-		if Confirm::with_theme(&ColorfulTheme::default())
+	if cfg_path.exists() {
+		println!("rinkle.toml already exists.");
+	} else {
+		let create = Confirm::with_theme(&ColorfulTheme::default())
 			.with_prompt("rinkle.toml not found. Create a new one?")
 			.interact()
-			.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-		{
+			.map_err(std::io::Error::other)?;
+		if create {
 			interactive_config(&cfg_path)?;
 		}
-	} else {
-		println!("rinkle.toml already exists.");
 	}
 
 	println!("initialized rinkle at {}", root.display());
@@ -50,10 +50,7 @@ fn git_clone(repo: &str, dest: &Path) -> std::io::Result<()> {
 		.args(["clone", repo, &dest.to_string_lossy()])
 		.status()?;
 	if !status.success() {
-		return Err(std::io::Error::new(
-			std::io::ErrorKind::Other,
-			"git clone failed",
-		));
+		return Err(std::io::Error::other("git clone failed"));
 	}
 	Ok(())
 }
@@ -64,13 +61,13 @@ fn interactive_config(path: &Path) -> std::io::Result<()> {
 		.with_prompt("Source directory for your dotfiles?")
 		.default("~/dotfiles".into())
 		.interact_text()
-		.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+		.map_err(std::io::Error::other)?;
 
 	let target_dir: String = Input::with_theme(&theme)
 		.with_prompt("Target directory for symlinks?")
 		.default("~/.config".into())
 		.interact_text()
-		.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+		.map_err(std::io::Error::other)?;
 
 	let strategies = &["skip", "overwrite", "backup"];
 	let strategy_idx = Select::with_theme(&theme)
@@ -78,7 +75,7 @@ fn interactive_config(path: &Path) -> std::io::Result<()> {
 		.items(strategies)
 		.default(2) // backup
 		.interact()
-		.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+		.map_err(std::io::Error::other)?;
 
 	let content = format!(
 		r#"[global]
@@ -25,9 +25,6 @@ use thiserror::Error;
 /// Errors that can occur during state loading or saving.
 #[derive(Debug, Error)]
 pub enum StateError {
-	/// The user's configuration directory could not be determined.
-	#[error("could not find user's config directory")]
-	NoConfigDir,
 	/// An I/O error occurred while reading or writing the state file.
 	#[error("io error: {0}")]
 	Io(#[from] std::io::Error),
@@ -72,11 +69,17 @@ pub fn default_state_path() -> PathBuf {
 	path
 }
 
+/// Returns the path of a rinkle-managed file kept next to `state.toml`.
+pub fn state_file(name: &str) -> PathBuf {
+	default_state_path().with_file_name(name)
+}
+
 fn open_state_file(path: &Path) -> Result<File, StateError> {
 	OpenOptions::new()
 		.read(true)
 		.write(true)
 		.create(true)
+		.truncate(false)
 		.open(path)
 		.map_err(StateError::Io)
 }
@@ -1,5 +1,8 @@
 use std::path::PathBuf;
 
+/// The values of a package's `os` list that can match [`current_os`].
+pub const KNOWN_OS: &[&str] = &["linux", "macos"];
+
 pub const fn current_os() -> &'static str {
 	if cfg!(target_os = "linux") {
 		"linux"
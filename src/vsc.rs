@@ -0,0 +1,291 @@
+//! Discovery of versioned package directories.
+//!
+//! Every directory directly inside `global.source_dir` whose name matches
+//! `vsc.template` is a version of a package. The template's `version` group
+//! names the version; its `package` group, if any, names the package.
+//! Without one, the package is the part of the name before the version, less
+//! a trailing `@`, `-` or `_`. Unset, the template is the `name@version`
+//! convention. Results are cached on disk, see [`cache`]. Which version a
+//! spec such as `latest` or `^1.2` picks is decided by [`spec`].
+
+pub mod cache;
+pub mod spec;
+
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
+};
+
+use regex::Regex;
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+	config::{
+		Config,
+		Package,
+	},
+	linker,
+};
+
+/// The template used when `vsc.template` is unset.
+pub const DEFAULT_TEMPLATE: &str = "^(?P<package>.+)@(?P<version>[^@]+)$";
+
+/// Errors that can occur while discovering versions.
+#[derive(Debug, Error)]
+pub enum VscError {
+	/// `vsc.template` is not a valid regex.
+	#[error("vsc.template '{template}' is not a valid regex: {source}")]
+	Template {
+		template: String,
+		source:   regex::Error,
+	},
+	/// `vsc.template` has no `version` group.
+	#[error("vsc.template '{0}' has no 'version' capture group")]
+	NoVersionGroup(String),
+	/// The source directory could not be read.
+	#[error("io error: {0}")]
+	Io(#[from] std::io::Error),
+}
+
+/// The versions found in the source directory, by package.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versions {
+	/// Package to version to directory.
+	#[serde(default)]
+	pub packages: BTreeMap<String, BTreeMap<String, PathBuf>>,
+}
+
+impl Versions {
+	/// The versions of a package, found under its name or the name of its
+	/// `source`.
+	pub fn of(
+		&self,
+		name: &str,
+		pkg: &Package,
+	) -> Option<&BTreeMap<String, PathBuf>> {
+		self.packages
+			.get(name)
+			.or_else(|| self.packages.get(pkg.source.as_deref()?))
+	}
+
+	/// The version of a package `spec` picks, and its directory.
+	pub fn resolve(
+		&self,
+		name: &str,
+		pkg: &Package,
+		spec: &str,
+		aliases: &BTreeMap<String, String>,
+	) -> Option<(&str, &Path)> {
+		let found = self.of(name, pkg)?;
+		let version = spec::resolve(spec, found, aliases)?;
+		Some((version, found[version].as_path()))
+	}
+}
+
+/// `vsc.template`, or [`DEFAULT_TEMPLATE`].
+pub fn template_str(cfg: &Config) -> &str {
+	cfg.vsc.template.as_deref().unwrap_or(DEFAULT_TEMPLATE)
+}
+
+/// Compiles `vsc.template`, or [`DEFAULT_TEMPLATE`].
+pub fn template(cfg: &Config) -> Result<Regex, VscError> {
+	let template = template_str(cfg);
+	let re = Regex::new(template).map_err(|source| VscError::Template {
+		template: template.to_string(),
+		source,
+	})?;
+	if !re.capture_names().flatten().any(|n| n == "version") {
+		return Err(VscError::NoVersionGroup(template.to_string()));
+	}
+	Ok(re)
+}
+
+/// Finds the versions of packages in the source directory, from the cache
+/// if it is still fresh.
+///
+/// A rescan is written back to the cache, except in dry-run mode.
+pub fn discover(cfg: &Config, dry_run: bool) -> Result<Versions, VscError> {
+	discover_with(cfg, &cache::cache_path(), dry_run)
+}
+
+fn discover_with(
+	cfg: &Config,
+	cache_path: &Path,
+	dry_run: bool,
+) -> Result<Versions, VscError> {
+	let source_dir = source_dir(cfg);
+	let template = template_str(cfg);
+	let mut cache = cache::load_from(cache_path);
+	if let Some(entry) = cache.get(&source_dir, template) {
+		if entry.is_fresh() {
+			return Ok(entry.versions.clone());
+		}
+	}
+	let versions = scan(cfg)?;
+	cache.insert(cache::CacheEntry::new(
+		&source_dir,
+		template,
+		versions.clone(),
+	));
+	if dry_run {
+		debug!("dry run, not writing {}", cache_path.display());
+		return Ok(versions);
+	}
+	if let Err(e) = cache::save_to(cache_path, &cache) {
+		debug!("cannot write {}: {e}", cache_path.display());
+	}
+	Ok(versions)
+}
+
+/// The source directory as the cache knows it.
+pub fn source_dir(cfg: &Config) -> PathBuf {
+	let root = linker::source_root(cfg);
+	std::path::absolute(&root).unwrap_or(root)
+}
+
+/// Scans the source directory for versions of packages, bypassing the
+/// cache.
+pub fn scan(cfg: &Config) -> Result<Versions, VscError> {
+	let re = template(cfg)?;
+	let mut versions = Versions::default();
+	let entries = match fs::read_dir(source_dir(cfg)) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			return Ok(versions);
+		}
+		Err(e) => return Err(e.into()),
+	};
+	for entry in entries {
+		let entry = entry?;
+		let file_name = entry.file_name();
+		let Some(dir_name) = file_name.to_str() else {
+			continue;
+		};
+		let Some(caps) = re.captures(dir_name) else {
+			continue;
+		};
+		let Some(version) = caps.name("version") else {
+			continue;
+		};
+		let package = caps.name("package").map_or_else(
+			|| dir_name[..version.start()].trim_end_matches(['@', '-', '_']),
+			|m| m.as_str(),
+		);
+		if package.is_empty() || !entry.path().is_dir() {
+			continue;
+		}
+		versions
+			.packages
+			.entry(package.to_string())
+			.or_default()
+			.insert(version.as_str().to_string(), entry.path());
+	}
+	Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn discovers_versions_with_and_without_a_package_group() {
+		let tmp = tempdir().unwrap();
+		for dir in ["nvim@stable", "nvim@nightly", "alacritty-v0.12.0", "zsh"] {
+			fs::create_dir(tmp.path().join(dir)).unwrap();
+		}
+		fs::write(tmp.path().join("tmux@3.4"), "").unwrap();
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(tmp.path().display().to_string());
+		let listed = |cfg: &Config| -> Vec<(String, Vec<String>)> {
+			scan(cfg)
+				.unwrap()
+				.packages
+				.into_iter()
+				.map(|(pkg, versions)| (pkg, versions.into_keys().collect()))
+				.collect()
+		};
+
+		assert_eq!(listed(&cfg), [("nvim".to_string(), vec![
+			"nightly".to_string(),
+			"stable".to_string()
+		])]);
+
+		cfg.vsc.template = Some(r"-(?P<version>v\d+\.\d+\.\d+)$".into());
+		assert_eq!(listed(&cfg), [("alacritty".to_string(), vec![
+			"v0.12.0".to_string()
+		])]);
+		let pkg = Package {
+			source: Some("alacritty".into()),
+			..Package::default()
+		};
+		let aliases = BTreeMap::new();
+		assert_eq!(
+			scan(&cfg)
+				.unwrap()
+				.resolve("term", &pkg, "latest", &aliases),
+			Some(("v0.12.0", tmp.path().join("alacritty-v0.12.0").as_path()))
+		);
+
+		cfg.vsc.template = Some("^(?P<package>.+)$".into());
+		assert!(matches!(scan(&cfg), Err(VscError::NoVersionGroup(_))));
+	}
+
+	#[test]
+	fn cache_is_reused_until_the_source_changes() {
+		let tmp = tempdir().unwrap();
+		let src = tmp.path().join("src");
+		let cache_path = tmp.path().join("cache/vsc.toml");
+		fs::create_dir_all(src.join("nvim@stable")).unwrap();
+		let mut cfg = Config::default();
+		cfg.global.source_dir = Some(src.display().to_string());
+		let versions_of = |cfg: &Config| -> Vec<String> {
+			discover_with(cfg, &cache_path, false).unwrap().packages["nvim"]
+				.keys()
+				.cloned()
+				.collect()
+		};
+
+		// A dry run scans but leaves the cache alone.
+		let scanned = discover_with(&cfg, &cache_path, true).unwrap();
+		assert!(scanned.packages["nvim"].contains_key("stable"));
+		assert!(!cache_path.exists());
+
+		assert_eq!(versions_of(&cfg), ["stable"]);
+		// A fresh entry is used as is, without scanning.
+		let mut cache = cache::load_from(&cache_path);
+		let entry = &mut cache.entries[0];
+		assert!(entry.is_fresh());
+		entry
+			.versions
+			.packages
+			.get_mut("nvim")
+			.unwrap()
+			.insert("cached".into(), src.join("nvim@cached"));
+		cache::save_to(&cache_path, &cache).unwrap();
+		assert_eq!(versions_of(&cfg), ["cached", "stable"]);
+
+		// Adding a version directory invalidates it.
+		std::thread::sleep(std::time::Duration::from_millis(20));
+		fs::create_dir(src.join("nvim@nightly")).unwrap();
+		assert_eq!(versions_of(&cfg), ["nightly", "stable"]);
+
+		// So does another template, kept as a separate entry.
+		cfg.vsc.template = Some("^(?P<package>nvim)@(?P<version>s.*)$".into());
+		assert_eq!(versions_of(&cfg), ["stable"]);
+		assert_eq!(cache::load_from(&cache_path).entries.len(), 2);
+
+		assert!(cache::clear(&cache_path).unwrap());
+		assert!(!cache::clear(&cache_path).unwrap());
+	}
+}
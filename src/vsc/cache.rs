@@ -0,0 +1,155 @@
+//! The on-disk cache of version discovery.
+//!
+//! Scanning a large source directory on every command adds up, so the result
+//! of [`scan`](super::scan) is kept in `$XDG_CACHE_HOME/rinkle/vsc.toml`
+//! (override the directory with `RINKLE_CACHE_DIR`), one entry per source
+//! directory and template. An entry records the modification time of the
+//! source directory and of every version directory found, and is thrown away
+//! as soon as one of them changes: adding, removing or renaming a directory
+//! changes the mtime of the source directory.
+
+use std::{
+	collections::BTreeMap,
+	fs,
+	io,
+	path::{
+		Path,
+		PathBuf,
+	},
+	time::SystemTime,
+};
+
+use serde::{
+	Deserialize,
+	Serialize,
+};
+use tracing::debug;
+
+use super::Versions;
+
+/// One discovery result and what it was computed from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+	pub source_dir: PathBuf,
+	pub template:   String,
+	/// The directories the result depends on, with their modification time.
+	pub mtimes:     BTreeMap<PathBuf, SystemTime>,
+	pub versions:   Versions,
+}
+
+impl CacheEntry {
+	/// Records `versions` as found in `source_dir` with `template`.
+	pub fn new(source_dir: &Path, template: &str, versions: Versions) -> Self {
+		let mtimes = std::iter::once(source_dir)
+			.chain(
+				versions
+					.packages
+					.values()
+					.flat_map(|found| found.values().map(PathBuf::as_path)),
+			)
+			.filter_map(|dir| Some((dir.to_path_buf(), mtime(dir)?)))
+			.collect();
+		Self {
+			source_dir: source_dir.to_path_buf(),
+			template: template.to_string(),
+			mtimes,
+			versions,
+		}
+	}
+
+	/// Whether none of the directories changed since the entry was made.
+	pub fn is_fresh(&self) -> bool {
+		self.mtimes.contains_key(&self.source_dir) &&
+			self.mtimes
+				.iter()
+				.all(|(dir, time)| mtime(dir).as_ref() == Some(time))
+	}
+}
+
+/// The cache file: every entry recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cache {
+	#[serde(default)]
+	pub entries: Vec<CacheEntry>,
+}
+
+impl Cache {
+	/// The entry for `source_dir` and `template`, fresh or not.
+	pub fn get(
+		&self,
+		source_dir: &Path,
+		template: &str,
+	) -> Option<&CacheEntry> {
+		self.entries
+			.iter()
+			.find(|e| e.source_dir == source_dir && e.template == template)
+	}
+
+	/// Replaces the entry for the same source directory and template, and
+	/// drops those of source directories that are gone.
+	pub fn insert(&mut self, entry: CacheEntry) {
+		self.entries.retain(|e| {
+			!(e.source_dir == entry.source_dir && e.template == entry.template) &&
+				e.source_dir.is_dir()
+		});
+		self.entries.push(entry);
+	}
+}
+
+/// Returns rinkle's cache directory.
+pub fn cache_dir() -> PathBuf {
+	// Allow tests / callers to override the location for isolation.
+	if let Ok(custom) = std::env::var("RINKLE_CACHE_DIR") {
+		return PathBuf::from(custom);
+	}
+	dirs::cache_dir()
+		.unwrap_or_else(std::env::temp_dir)
+		.join("rinkle")
+}
+
+/// Returns the path of the version cache.
+pub fn cache_path() -> PathBuf {
+	cache_dir().join("vsc.toml")
+}
+
+/// Loads the cache at `path`. A missing or unreadable cache is empty.
+pub fn load_from(path: &Path) -> Cache {
+	let content = match fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(e) => {
+			if e.kind() != io::ErrorKind::NotFound {
+				debug!("cannot read {}: {e}", path.display());
+			}
+			return Cache::default();
+		}
+	};
+	toml::from_str(&content).unwrap_or_else(|e| {
+		debug!("ignoring unreadable {}: {e}", path.display());
+		Cache::default()
+	})
+}
+
+/// Writes the cache to `path`, replacing it atomically so concurrent runs
+/// never read half a file.
+pub fn save_to(path: &Path, cache: &Cache) -> io::Result<()> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let content = toml::to_string_pretty(cache).map_err(io::Error::other)?;
+	let tmp = path.with_extension(format!("toml.{}", std::process::id()));
+	fs::write(&tmp, content)?;
+	fs::rename(&tmp, path)
+}
+
+/// Deletes the cache at `path`. Returns whether there was one.
+pub fn clear(path: &Path) -> io::Result<bool> {
+	match fs::remove_file(path) {
+		Ok(()) => Ok(true),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
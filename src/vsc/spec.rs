@@ -0,0 +1,229 @@
+//! Version specs: how a requested, pinned or default version picks one of
+//! the discovered versions of a package.
+//!
+//! A spec is, by precedence, the exact name of a discovered version, an alias
+//! from `[vsc.aliases]`, `latest`, or a range over the versions that look like
+//! `1.2.3` (a `v` prefix, a `-pre` suffix and fewer or more components are
+//! fine): `^1.2` (`>=1.2.0, <2.0.0`), `~1.2` (`>=1.2.0, <1.3.0`), `v1.*` or
+//! `1.2.x`, or a partial version such as `v0.9`, which stands for `v0.9.*`.
+//! `latest` and ranges pick the highest version they match and never pick a
+//! pre-release.
+
+use std::{
+	cmp::Ordering,
+	collections::BTreeMap,
+	path::PathBuf,
+};
+
+/// The spec that picks the highest version.
+pub const LATEST: &str = "latest";
+
+/// Resolves `spec` to one of the versions in `found`.
+///
+/// An alias may stand for a version, `latest` or a range, but not for
+/// another alias.
+pub fn resolve<'a>(
+	spec: &str,
+	found: &'a BTreeMap<String, PathBuf>,
+	aliases: &BTreeMap<String, String>,
+) -> Option<&'a str> {
+	if let Some((version, _)) = found.get_key_value(spec) {
+		return Some(version);
+	}
+	resolve_unaliased(aliases.get(spec).map_or(spec, String::as_str), found)
+}
+
+fn resolve_unaliased<'a>(
+	spec: &str,
+	found: &'a BTreeMap<String, PathBuf>,
+) -> Option<&'a str> {
+	if let Some((version, _)) = found.get_key_value(spec) {
+		return Some(version);
+	}
+	let range = if spec == LATEST {
+		Range::ANY
+	} else {
+		Range::parse(spec)?
+	};
+	found
+		.keys()
+		.filter_map(|version| Some((version, Numbered::parse(version)?)))
+		.filter(|(_, numbered)| range.matches(numbered))
+		.max_by(|(a, x), (b, y)| x.cmp(y).then_with(|| a.cmp(b)))
+		.map(|(version, _)| version.as_str())
+}
+
+/// Orders version names: names that are not numbered (`nightly`, `stable`)
+/// alphabetically, then numbered versions from the lowest to the highest.
+pub fn compare(a: &str, b: &str) -> Ordering {
+	match (Numbered::parse(a), Numbered::parse(b)) {
+		(Some(x), Some(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+		(Some(_), None) => Ordering::Greater,
+		(None, Some(_)) => Ordering::Less,
+		(None, None) => a.cmp(b),
+	}
+}
+
+/// The versions in `found`, ordered by [`compare`].
+pub fn ordered(found: &BTreeMap<String, PathBuf>) -> Vec<&str> {
+	let mut versions: Vec<&str> = found.keys().map(String::as_str).collect();
+	versions.sort_by(|a, b| compare(a, b));
+	versions
+}
+
+/// A version such as `v1.2.3` or `1.2.0-rc1`.
+#[derive(Debug, PartialEq, Eq)]
+struct Numbered {
+	release: Vec<u64>,
+	pre:     Option<String>,
+}
+
+impl Numbered {
+	fn parse(version: &str) -> Option<Self> {
+		let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+		let (release, pre) = match version.split_once('-') {
+			Some((release, pre)) => (release, Some(pre.to_string())),
+			None => (version, None),
+		};
+		Some(Self {
+			release: numbers(release)?,
+			pre,
+		})
+	}
+}
+
+impl Ord for Numbered {
+	fn cmp(&self, other: &Self) -> Ordering {
+		compare_release(&self.release, &other.release).then_with(|| {
+			match (&self.pre, &other.pre) {
+				(None, None) => Ordering::Equal,
+				(None, Some(_)) => Ordering::Greater,
+				(Some(_), None) => Ordering::Less,
+				(Some(a), Some(b)) => a.cmp(b),
+			}
+		})
+	}
+}
+
+impl PartialOrd for Numbered {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+/// The released versions at or above `min` whose first `fixed` components
+/// are those of `min`.
+#[derive(Debug, PartialEq, Eq)]
+struct Range {
+	min:   Vec<u64>,
+	fixed: usize,
+}
+
+impl Range {
+	const ANY: Self = Self {
+		min:   Vec::new(),
+		fixed: 0,
+	};
+
+	fn parse(spec: &str) -> Option<Self> {
+		if let Some(min) = spec.strip_prefix('^') {
+			let min = numbers(min.strip_prefix(['v', 'V']).unwrap_or(min))?;
+			// Everything up to the first non-zero component is fixed.
+			let fixed = min
+				.iter()
+				.position(|&n| n != 0)
+				.map_or(min.len(), |i| i + 1);
+			return Some(Self { min, fixed });
+		}
+		if let Some(min) = spec.strip_prefix('~') {
+			let min = numbers(min.strip_prefix(['v', 'V']).unwrap_or(min))?;
+			let fixed = min.len().min(2);
+			return Some(Self { min, fixed });
+		}
+		let spec = spec.strip_prefix(['v', 'V']).unwrap_or(spec);
+		let parts: Vec<&str> = spec.split('.').collect();
+		let is_wildcard = |part: &&str| matches!(*part, "*" | "x" | "X");
+		let wildcard =
+			parts.iter().position(is_wildcard).unwrap_or(parts.len());
+		if !parts[wildcard..].iter().all(is_wildcard) {
+			return None;
+		}
+		let min = parts[..wildcard]
+			.iter()
+			.map(|part| part.parse().ok())
+			.collect::<Option<Vec<u64>>>()?;
+		Some(Self {
+			fixed: min.len(),
+			min,
+		})
+	}
+
+	fn matches(&self, version: &Numbered) -> bool {
+		let component = |i: usize| version.release.get(i).copied().unwrap_or(0);
+		version.pre.is_none() &&
+			(0..self.fixed).all(|i| component(i) == self.min[i]) &&
+			compare_release(&version.release, &self.min) != Ordering::Less
+	}
+}
+
+/// Parses `1.2.3` into its components.
+fn numbers(release: &str) -> Option<Vec<u64>> {
+	release.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Compares release components, missing ones counting as zero.
+fn compare_release(a: &[u64], b: &[u64]) -> Ordering {
+	(0..a.len().max(b.len()))
+		.map(|i| {
+			let (x, y) = (a.get(i).unwrap_or(&0), b.get(i).unwrap_or(&0));
+			x.cmp(y)
+		})
+		.find(|ordering| ordering.is_ne())
+		.unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn orders_and_resolves_specs() {
+		let found: BTreeMap<String, PathBuf> = [
+			"nightly",
+			"stable",
+			"v0.9.0",
+			"v0.9.5",
+			"v0.10.1",
+			"v1.2.0",
+			"v1.10.0-rc1",
+			"v2.0.0",
+		]
+		.into_iter()
+		.map(|v| (v.to_string(), PathBuf::from(v)))
+		.collect();
+		assert_eq!(ordered(&found), [
+			"nightly",
+			"stable",
+			"v0.9.0",
+			"v0.9.5",
+			"v0.10.1",
+			"v1.2.0",
+			"v1.10.0-rc1",
+			"v2.0.0"
+		]);
+
+		let aliases = BTreeMap::from([("lts".to_string(), "v0.9".to_string())]);
+		let resolve = |spec: &str| resolve(spec, &found, &aliases);
+		assert_eq!(resolve("stable"), Some("stable"));
+		assert_eq!(resolve("latest"), Some("v2.0.0"));
+		assert_eq!(resolve("lts"), Some("v0.9.5"));
+		assert_eq!(resolve("^0.9"), Some("v0.9.5"));
+		assert_eq!(resolve("^1"), Some("v1.2.0"));
+		assert_eq!(resolve("~0.9.3"), Some("v0.9.5"));
+		assert_eq!(resolve("v1.*"), Some("v1.2.0"));
+		assert_eq!(resolve("0.x"), Some("v0.10.1"));
+		assert_eq!(resolve("^3"), None);
+		assert_eq!(resolve("beta"), None);
+		assert_eq!(resolve("1.*.2"), None);
+	}
+}
@@ -1,5 +1,6 @@
 use std::{
 	fs,
+	path::Path,
 	process::Command,
 };
 
@@ -7,14 +8,21 @@ use assert_cmd::prelude::*;
 use predicates::prelude::*;
 use tempfile::tempdir;
 
-fn bin() -> Command {
-	Command::cargo_bin("rinkle").expect("failed to find binary")
+/// The binary with its state, data and cache directories inside `dir`, so
+/// tests never touch the real ones.
+fn bin(dir: &Path) -> Command {
+	let mut cmd = Command::cargo_bin("rinkle").expect("failed to find binary");
+	cmd.env("RINKLE_STATE_PATH", dir.join("state.toml"))
+		.env("RINKLE_DATA_DIR", dir.join("data"))
+		.env("RINKLE_CACHE_DIR", dir.join("cache"));
+	cmd
 }
 
 #[test]
 fn list_and_status_json() {
+	let tmp = tempdir().unwrap();
 	// Use repo's example config
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.arg("list")
 		.arg("--config")
 		.arg("examples/config/rinkle.toml");
@@ -22,25 +30,27 @@ fn list_and_status_json() {
 		.success()
 		.stdout(predicate::str::contains("zsh"));
 
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.args(["status", "--json"])
 		.arg("--config")
 		.arg("examples/config/rinkle.toml");
+	// 2 means some package is out of sync, which depends on this machine.
 	cmd.assert()
-		.success()
+		.code(predicate::in_iter([0, 2]))
 		.stdout(predicate::str::contains("\"name\": \"zsh\""));
 }
 
 #[test]
 fn dry_run_link_remove_ok() {
+	let tmp = tempdir().unwrap();
 	// Should not fail even if directories don't exist
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.args(["link", "--dry-run"])
 		.arg("--config")
 		.arg("config/rinkle.toml");
 	cmd.assert().success();
 
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.args(["remove", "zsh"])
 		.arg("--dry-run")
 		.arg("--config")
@@ -50,10 +60,10 @@ fn dry_run_link_remove_ok() {
 
 #[test]
 fn link_and_remove_real() {
-	let dir = tempdir().unwrap();
-	let src_root = dir.path().join("src");
-	let dst_root = dir.path().join("dst");
-	let cfg_root = dir.path().join("cfg");
+	let tmp = tempdir().unwrap();
+	let src_root = tmp.path().join("src");
+	let dst_root = tmp.path().join("dst");
+	let cfg_root = tmp.path().join("cfg");
 	fs::create_dir_all(&src_root).unwrap();
 	fs::create_dir_all(&dst_root).unwrap();
 	fs::create_dir_all(&cfg_root).unwrap();
@@ -76,8 +86,8 @@ target_dir = "{}"
 	fs::write(&config_path, config_content).unwrap();
 
 	// Run link command
-	let mut cmd = bin();
-	cmd.current_dir(dir.path())
+	let mut cmd = bin(tmp.path());
+	cmd.current_dir(tmp.path())
 		.args(["link", "mypkg", "--config"])
 		.arg(&config_path);
 	cmd.assert().success();
@@ -94,8 +104,8 @@ target_dir = "{}"
 	assert_eq!(fs::read_link(&dst_path).unwrap(), pkg_src);
 
 	// Run remove command
-	let mut cmd = bin();
-	cmd.current_dir(dir.path())
+	let mut cmd = bin(tmp.path());
+	cmd.current_dir(tmp.path())
 		.args(["remove", "mypkg", "--config"])
 		.arg(&config_path);
 	cmd.assert().success();
@@ -1,5 +1,9 @@
 use std::{
 	fs,
+	path::{
+		Path,
+		PathBuf,
+	},
 	process::Command,
 };
 
@@ -7,8 +11,20 @@ use assert_cmd::prelude::*;
 use predicates::prelude::*;
 use tempfile::tempdir;
 
-fn bin() -> Command {
-	Command::cargo_bin("rinkle").unwrap()
+fn backup_entries(data_dir: &Path) -> Vec<PathBuf> {
+	fs::read_dir(data_dir.join("backups"))
+		.map(|dirs| dirs.map(|d| d.unwrap().path()).collect())
+		.unwrap_or_default()
+}
+
+/// The binary with its state, data and cache directories inside `dir`, so
+/// tests never touch the real ones.
+fn bin(dir: &Path) -> Command {
+	let mut cmd = Command::cargo_bin("rinkle").unwrap();
+	cmd.env("RINKLE_STATE_PATH", dir.join("state.toml"))
+		.env("RINKLE_DATA_DIR", dir.join("data"))
+		.env("RINKLE_CACHE_DIR", dir.join("cache"));
+	cmd
 }
 
 // Helper to write a config file
@@ -45,15 +61,16 @@ tags = ["p"]
 	);
 	let cfg_path = write_cfg(tmp.path(), &cfg);
 
-	// status default -> none because no default profile tags
-	let mut cmd = bin();
+	// status before linking: whichever profile applies, its packages are
+	// missing
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["status", "--config"])
 		.arg(&cfg_path);
-	cmd.assert().success();
+	cmd.assert().code(2);
 
 	// set profile work
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["use-profile", "work", "--config"])
 		.arg(&cfg_path);
@@ -61,7 +78,7 @@ tags = ["p"]
 		.stdout(predicate::str::contains("active profile set to work"));
 
 	// link (should link only pkg1)
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["link", "--config"])
 		.arg(&cfg_path);
@@ -75,9 +92,6 @@ fn vsc_version_selection_priority() {
 	let tmp = tempdir().unwrap();
 	let src = tmp.path().join("src");
 	let dst = tmp.path().join("dst");
-	// isolated state file
-	let state_path = tmp.path().join("state.toml");
-	std::env::set_var("RINKLE_STATE_PATH", &state_path);
 	fs::create_dir_all(&src).unwrap();
 	fs::create_dir_all(&dst).unwrap();
 	fs::create_dir_all(src.join("tool@1")).unwrap();
@@ -98,7 +112,7 @@ default_version = "2"
 	let cfg_path = write_cfg(tmp.path(), &cfg);
 
 	// Without pin, expect directory tool@2 picked
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["link", "tool", "--config"])
 		.arg(&cfg_path);
@@ -108,7 +122,7 @@ default_version = "2"
 	assert_eq!(fs::read_link(&link).unwrap(), src.join("tool@2"));
 
 	// Pin to version 1
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["vsc", "tool", "1", "--config"])
 		.arg(&cfg_path);
@@ -117,7 +131,7 @@ default_version = "2"
 
 	// Remove old link then re-link
 	let _ = fs::remove_file(&link);
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["link", "tool", "--config"])
 		.arg(&cfg_path);
@@ -150,16 +164,24 @@ conflict_strategy = "backup"
 	let target_file = dst.join("pkg");
 	fs::write(&target_file, b"data").unwrap();
 
-	let mut cmd = bin();
+	let data_dir = tmp.path().join("data");
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
+		.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.env("RINKLE_DATA_DIR", &data_dir)
 		.args(["link", "pkg", "--config"])
 		.arg(&cfg_path);
 	cmd.assert().success();
 
-	// original renamed to .bak and link created
-	let mut bak = target_file.clone();
-	bak.set_extension("bak");
-	assert!(bak.exists());
+	// original moved into the backup store and link created
+	assert!(!dst.join("pkg.bak").exists());
+	let entries = backup_entries(&data_dir);
+	assert_eq!(entries.len(), 1);
+	assert_eq!(fs::read(entries[0].join("data")).unwrap(), b"data");
+	let manifest =
+		fs::read_to_string(entries[0].join("manifest.toml")).unwrap();
+	assert!(manifest.contains(&target_file.display().to_string()));
+	assert!(manifest.contains("package = \"pkg\""));
 	assert!(
 		fs::symlink_metadata(&target_file)
 			.unwrap()
@@ -168,6 +190,229 @@ conflict_strategy = "backup"
 	);
 }
 
+#[test]
+fn backups_restore_and_prune() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("a")).unwrap();
+	fs::create_dir_all(src.join("b")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(dst.join("a"), b"old a").unwrap();
+	fs::write(dst.join("b"), b"old b").unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.a]
+[packages.b]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let data_dir = tmp.path().join("data");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+			.env("RINKLE_DATA_DIR", &data_dir)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["link", "a", "b"]).success();
+	let entries = backup_entries(&data_dir);
+	assert_eq!(entries.len(), 2);
+	run(&["backups", "list"])
+		.success()
+		.stdout(predicate::str::contains(
+			dst.join("a").display().to_string(),
+		));
+
+	let id_of = |pkg: &str| {
+		entries
+			.iter()
+			.find(|e| {
+				fs::read_to_string(e.join("manifest.toml"))
+					.unwrap()
+					.contains(&format!("package = \"{pkg}\""))
+			})
+			.unwrap()
+			.file_name()
+			.unwrap()
+			.to_string_lossy()
+			.into_owned()
+	};
+	let id_a = id_of("a");
+	run(&["backups", "restore", &id_a]).success();
+	assert_eq!(fs::read(dst.join("a")).unwrap(), b"old a");
+	assert_eq!(backup_entries(&data_dir).len(), 1);
+
+	run(&["backups", "prune", "--older-than", "1d"]).success();
+	assert_eq!(backup_entries(&data_dir).len(), 1);
+	for age in ["9999999999999w", "999999999w"] {
+		run(&["backups", "prune", "--older-than", age])
+			.code(predicate::ne(101))
+			.stderr(predicate::str::contains("out of range"));
+	}
+	assert_eq!(backup_entries(&data_dir).len(), 1);
+
+	// An unreadable manifest does not hide the other backups.
+	let broken = data_dir.join("backups/broken");
+	fs::create_dir_all(&broken).unwrap();
+	fs::write(broken.join("manifest.toml"), "not = [toml").unwrap();
+	run(&["backups", "list"])
+		.success()
+		.stdout(predicate::str::contains(
+			dst.join("b").display().to_string(),
+		))
+		.stderr(predicate::str::contains("skipping backup"));
+	fs::remove_dir_all(&broken).unwrap();
+	run(&["backups", "prune", "--older-than", "0m"]).success();
+	assert!(backup_entries(&data_dir).is_empty());
+	run(&["backups", "restore", &id_a])
+		.failure()
+		.stderr(predicate::str::contains("not found"));
+}
+
+#[test]
+fn conflict_strategy_precedence() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("gitconfig")).unwrap();
+	fs::create_dir_all(src.join("zsh")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+conflict_strategy = "backup"
+[packages.gitconfig]
+conflict_strategy = "overwrite"
+[packages.zsh]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	fs::write(dst.join("gitconfig"), b"old").unwrap();
+	fs::write(dst.join("zsh"), b"old").unwrap();
+
+	let link = |extra: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+			.env("RINKLE_DATA_DIR", tmp.path().join("data"))
+			.args(["link", "gitconfig", "zsh", "--config"])
+			.arg(&cfg_path)
+			.args(extra);
+		cmd.assert().success();
+	};
+
+	// the CLI flag beats both the package and the global strategy
+	link(&["--conflict-strategy", "skip"]);
+	assert_eq!(fs::read(dst.join("gitconfig")).unwrap(), b"old");
+	assert_eq!(fs::read(dst.join("zsh")).unwrap(), b"old");
+
+	// package overwrite beats global backup
+	link(&[]);
+	let is_link = |p: &str| {
+		fs::symlink_metadata(dst.join(p))
+			.unwrap()
+			.file_type()
+			.is_symlink()
+	};
+	assert!(is_link("gitconfig"));
+	assert!(is_link("zsh"));
+	// only zsh was backed up
+	let entries = backup_entries(&tmp.path().join("data"));
+	assert_eq!(entries.len(), 1);
+	assert!(
+		fs::read_to_string(entries[0].join("manifest.toml"))
+			.unwrap()
+			.contains("zsh")
+	);
+}
+
+#[test]
+fn prompt_strategy_requires_a_terminal() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("pkg")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+conflict_strategy = "prompt"
+[packages.pkg]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	fs::write(dst.join("pkg"), b"mine").unwrap();
+
+	bin(tmp.path())
+		.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["link", "pkg", "--config"])
+		.arg(&cfg_path)
+		.assert()
+		.failure()
+		.stderr(predicate::str::contains("stdin is not a terminal"));
+	assert_eq!(fs::read(dst.join("pkg")).unwrap(), b"mine");
+}
+
+#[test]
+fn adopt_moves_existing_files_into_repo() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("kitty")).unwrap();
+	fs::create_dir_all(dst.join("kitty")).unwrap();
+	fs::write(src.join("kitty/kitty.conf"), "repo").unwrap();
+	fs::write(src.join("kitty/theme.conf"), "theme").unwrap();
+	fs::write(dst.join("kitty/kitty.conf"), "hand-edited").unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.kitty]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+
+	bin(tmp.path())
+		.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["adopt", "kitty", "--config"])
+		.arg(&cfg_path)
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("overwritten"))
+		.stdout(predicate::str::contains("kitty.conf"));
+
+	assert!(
+		fs::symlink_metadata(dst.join("kitty"))
+			.unwrap()
+			.file_type()
+			.is_symlink()
+	);
+	assert_eq!(
+		fs::read_to_string(src.join("kitty/kitty.conf")).unwrap(),
+		"hand-edited"
+	);
+	assert_eq!(
+		fs::read_to_string(src.join("kitty/theme.conf")).unwrap(),
+		"theme"
+	);
+}
+
 #[test]
 fn dry_run_no_side_effects() {
 	let tmp = tempdir().unwrap();
@@ -188,7 +433,7 @@ target_dir = "{}"
 	);
 	let cfg_path = write_cfg(tmp.path(), &cfg);
 
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["link", "pkg", "--dry-run", "--config"])
 		.arg(&cfg_path);
@@ -217,12 +462,1105 @@ target_dir = "{}"
 	);
 	let cfg_path = write_cfg(tmp.path(), &cfg);
 
-	let mut cmd = bin();
+	let mut cmd = bin(tmp.path());
 	cmd.current_dir(tmp.path())
 		.args(["status", "--json", "--config"])
 		.arg(&cfg_path);
-	cmd.assert().success().stdout(
+	cmd.assert().code(2).stdout(
 		predicate::str::contains("\"name\": \"a\"")
-			.or(predicate::str::contains("\"status\"")),
+			.and(predicate::str::contains("\"status\": \"missing\"")),
+	);
+}
+
+#[test]
+fn status_reports_every_kind_and_exit_code() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	for pkg in [
+		"ok", "dangling", "wrong", "plain", "other", "tree/a", "tree/b",
+	] {
+		fs::create_dir_all(src.join(pkg)).unwrap();
+	}
+	fs::write(src.join("tree/a/one"), "1").unwrap();
+	fs::write(src.join("tree/b/two"), "2").unwrap();
+	fs::create_dir_all(dst.join("plain")).unwrap();
+	fs::create_dir_all(dst.join("tree")).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.ok]
+[packages.missing]
+[packages.dangling]
+[packages.wrong]
+[packages.plain]
+[packages.tree]
+mode = "tree"
+[packages.foreign]
+os = ["plan9"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let state = tmp.path().join("state.toml");
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state)
+		.args(["link", "ok", "dangling", "wrong", "tree", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+	fs::remove_file(dst.join("dangling")).unwrap();
+	std::os::unix::fs::symlink(tmp.path().join("gone"), dst.join("dangling"))
+		.unwrap();
+	fs::remove_file(dst.join("wrong")).unwrap();
+	std::os::unix::fs::symlink(src.join("other"), dst.join("wrong")).unwrap();
+	fs::remove_file(dst.join("tree/b")).unwrap();
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state)
+		.args(["status", "--json", "--config"])
+		.arg(&cfg_path);
+	let out = cmd.assert().code(2).get_output().stdout.clone();
+	let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+	let status = |name: &str| {
+		v.as_array()
+			.unwrap()
+			.iter()
+			.find(|item| item["name"] == name)
+			.map(|item| item["status"].as_str().unwrap().to_string())
+			.unwrap()
+	};
+	assert_eq!(status("ok"), "ok");
+	assert_eq!(status("missing"), "source-missing");
+	assert_eq!(status("dangling"), "dangling");
+	assert_eq!(status("wrong"), "wrong-target");
+	assert_eq!(status("plain"), "not-a-symlink");
+	assert_eq!(status("tree"), "partially-linked");
+	assert_eq!(status("foreign"), "os-filtered-out");
+
+	// only in-sync packages selected: exit 0
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.ok]
+[packages.foreign]
+os = ["plan9"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state)
+		.args(["status", "--config"])
+		.arg(&cfg_path);
+	cmd.assert()
+		.success()
+		.stdout(predicate::str::contains("os-filtered-out"));
+}
+
+#[test]
+fn profile_flag_overrides_active_profile() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	fs::create_dir_all(src.join("pkg1")).unwrap();
+	fs::create_dir_all(src.join("pkg2")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[profiles]
+work = ["w"]
+play = ["p"]
+[packages.pkg1]
+tags = ["w"]
+[packages.pkg2]
+tags = ["p"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state_path)
+		.args(["use-profile", "work", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+
+	// --profile wins over the saved active profile for a single command
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state_path)
+		.args(["link", "--profile", "play", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+	assert!(dst.join("pkg2").exists());
+	assert!(!dst.join("pkg1").exists());
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state_path)
+		.args(["remove", "--profile", "play", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+	assert!(fs::symlink_metadata(dst.join("pkg2")).is_err());
+}
+
+#[test]
+fn failed_link_rolls_back_whole_run() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	fs::create_dir_all(src.join("a")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	// pre-existing file that the backup strategy moves out of the way
+	fs::write(dst.join("a"), b"original").unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.a]
+[packages.missing]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state_path)
+		.env("RINKLE_DATA_DIR", tmp.path().join("data"))
+		.args(["link", "a", "missing", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().failure();
+
+	assert_eq!(fs::read(dst.join("a")).unwrap(), b"original");
+	assert!(backup_entries(&tmp.path().join("data")).is_empty());
+	assert!(!tmp.path().join("journal.json").exists());
+}
+
+#[test]
+fn relative_links_survive_moving_the_tree() {
+	let tmp = tempdir().unwrap();
+	let home = tmp.path().join("home");
+	let src = home.join("dotfiles");
+	let dst = home.join(".config");
+	fs::create_dir_all(src.join("nvim")).unwrap();
+	fs::create_dir_all(src.join("kitty")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(src.join("nvim/init.lua"), "init").unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+link_style = "relative"
+[packages.nvim]
+[packages.kitty]
+link_style = "absolute"
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["link", "nvim", "kitty", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		PathBuf::from("../dotfiles/nvim")
+	);
+	assert_eq!(fs::read_link(dst.join("kitty")).unwrap(), src.join("kitty"));
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["status", "--json", "--config"])
+		.arg(&cfg_path);
+	let out = cmd.assert().success().get_output().stdout.clone();
+	let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+	assert!(
+		v.as_array()
+			.unwrap()
+			.iter()
+			.all(|item| item["status"] == "ok")
+	);
+
+	let moved = tmp.path().join("moved");
+	fs::rename(&home, &moved).unwrap();
+	assert_eq!(
+		fs::read_to_string(moved.join(".config/nvim/init.lua")).unwrap(),
+		"init"
+	);
+}
+
+#[test]
+fn doctor_reports_config_problems() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("zsh")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[profiles]
+default = ["shell"]
+[packages.zsh]
+tags = ["shell"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["doctor", "--config"])
+		.arg(&cfg_path);
+	cmd.assert()
+		.success()
+		.stdout(predicate::str::contains("error:").not());
+
+	let cfg = format!(
+		"{cfg}[packages.nvim]\ntags = [\"editor\"]\nos = \
+		 [\"linx\"]\n[vsc]\ntemplate = \"(\"\n"
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["doctor", "--config"])
+		.arg(&cfg_path);
+	cmd.assert()
+		.code(1)
+		.stdout(predicate::str::contains("source"))
+		.stdout(predicate::str::contains("does not exist"))
+		.stdout(predicate::str::contains("not a valid regex"))
+		.stdout(predicate::str::contains("unknown os 'linx'"))
+		.stdout(predicate::str::contains(
+			"package 'nvim' is not selected by any profile",
+		));
+}
+
+#[test]
+fn overlapping_targets_are_refused_unless_forced() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("nvim")).unwrap();
+	fs::create_dir_all(src.join("nvim-lua")).unwrap();
+	fs::create_dir_all(src.join("vim")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.nvim]
+[packages.nvim-lua]
+target = "nvim/lua"
+[packages.vim]
+target = "nvim"
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let state = tmp.path().join("state.toml");
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state)
+		.args(["link", "nvim", "--config"])
+		.arg(&cfg_path);
+	cmd.assert()
+		.failure()
+		.stderr(predicate::str::contains(
+			"packages 'nvim' and 'vim' both link",
+		))
+		.stderr(predicate::str::contains("package 'nvim-lua' links"))
+		.stderr(predicate::str::contains(
+			"3 overlapping package target(s), nothing was changed; fix the \
+			 targets or pass --force",
+		));
+	assert!(fs::symlink_metadata(dst.join("nvim")).is_err());
+
+	let mut cmd = bin(tmp.path());
+	cmd.env("RINKLE_STATE_PATH", &state)
+		.args(["link", "nvim", "--force", "--config"])
+		.arg(&cfg_path);
+	cmd.assert().success();
+	assert!(
+		fs::symlink_metadata(dst.join("nvim"))
+			.unwrap()
+			.file_type()
+			.is_symlink()
+	);
+}
+
+#[test]
+fn prune_removes_links_of_dropped_packages() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("zsh")).unwrap();
+	fs::create_dir_all(src.join("bash")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = |packages: &str| {
+		format!(
+			"[global]\nsource_dir = \"{}\"\ntarget_dir = \"{}\"\n{packages}",
+			src.display(),
+			dst.display()
+		)
+	};
+	let cfg_path =
+		write_cfg(tmp.path(), &cfg("[packages.zsh]\n[packages.bash]\n"));
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["link"]).success();
+	write_cfg(tmp.path(), &cfg("[packages.zsh]\n"));
+
+	run(&["prune"])
+		.failure()
+		.stdout(predicate::str::contains("orphan:"))
+		.stderr(predicate::str::contains("pass --yes"));
+	run(&["prune", "--dry-run"])
+		.success()
+		.stdout(predicate::str::contains("[dry-run] remove"));
+	assert!(fs::symlink_metadata(dst.join("bash")).is_ok());
+
+	run(&["prune", "--yes"]).success();
+	assert!(fs::symlink_metadata(dst.join("bash")).is_err());
+	assert!(fs::symlink_metadata(dst.join("zsh")).is_ok());
+	run(&["prune"])
+		.success()
+		.stdout(predicate::str::contains("No orphaned links"));
+}
+
+#[test]
+fn links_made_by_hand_are_not_managed() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("zsh")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	std::os::unix::fs::symlink(src.join("zsh"), dst.join("zsh")).unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			"[global]\nsource_dir = \"{}\"\ntarget_dir = \
+			 \"{}\"\n[packages.zsh]\n",
+			src.display(),
+			dst.display()
+		),
+	);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["status"])
+		.code(2)
+		.stdout(predicate::str::contains("unmanaged"));
+	run(&["remove", "zsh"]).success();
+	assert!(fs::symlink_metadata(dst.join("zsh")).is_ok());
+
+	// linking claims the link that is already correct
+	run(&["link", "zsh"]).success();
+	let owned = fs::read_to_string(tmp.path().join("owned.toml")).unwrap();
+	assert!(owned.contains("[packages.zsh"));
+	run(&["status"]).success();
+	run(&["remove", "zsh"]).success();
+	assert!(fs::symlink_metadata(dst.join("zsh")).is_err());
+}
+
+#[test]
+fn policy_violations_stop_the_run_before_any_change() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("zsh")).unwrap();
+	fs::create_dir_all(src.join("hosts")).unwrap();
+	fs::create_dir_all(tmp.path().join("outside")).unwrap();
+	let state = tmp.path().join("state.toml");
+	let link = |extra: &str| {
+		let cfg_path = write_cfg(
+			tmp.path(),
+			&format!(
+				"[global]\nsource_dir = \"{}\"\ntarget_dir = \
+				 \"{}\"\n[packages.zsh]\n{extra}",
+				src.display(),
+				dst.display()
+			),
+		);
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(["link", "--config"])
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	link("[packages.hosts]\ntarget = \"/etc/rinkle-hosts\"\n")
+		.failure()
+		.stderr(predicate::str::contains(
+			"package 'hosts': /etc/rinkle-hosts is inside the forbidden root \
+			 /etc",
+		));
+	link("[packages.up]\nsource = \"../outside\"\n")
+		.failure()
+		.stderr(predicate::str::contains("package 'up':"))
+		.stderr(predicate::str::contains("leaves global.source_dir"));
+	assert!(fs::symlink_metadata(dst.join("zsh")).is_err());
+
+	link(&format!(
+		"[policy]\nallowed_roots = [\"{}\"]\n",
+		tmp.path().join("elsewhere").display()
+	))
+	.failure()
+	.stderr(predicate::str::contains(
+		"outside every policy.allowed_roots",
+	));
+	link(
+		"[policy]\nallow_source_escape = true\n[packages.up]\nsource = \
+		 \"../outside\"\n",
+	)
+	.success();
+	assert!(fs::symlink_metadata(dst.join("up")).is_ok());
+}
+
+#[test]
+fn copy_and_hardlink_deployments_detect_drift() {
+	use std::os::unix::fs::MetadataExt;
+
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("git")).unwrap();
+	fs::create_dir_all(src.join("app")).unwrap();
+	fs::write(src.join("git/config"), "[user]").unwrap();
+	fs::write(src.join("app/settings.json"), "{}").unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			"[global]\nsource_dir = \"{}\"\ntarget_dir = \
+			 \"{}\"\n[packages.git]\ndeploy = \
+			 \"copy\"\n[packages.app]\ndeploy = \"hardlink\"\n",
+			src.display(),
+			dst.display()
+		),
+	);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.env("RINKLE_DATA_DIR", tmp.path().join("data"))
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+	let status = |name: &str| {
+		let out = run(&["status", "--json"]).get_output().stdout.clone();
+		let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+		v.as_array()
+			.unwrap()
+			.iter()
+			.find(|item| item["name"] == name)
+			.map(|item| item["status"].as_str().unwrap().to_string())
+			.unwrap()
+	};
+
+	run(&["link"]).success();
+	let copy = dst.join("git/config");
+	assert!(
+		!fs::symlink_metadata(&copy)
+			.unwrap()
+			.file_type()
+			.is_symlink()
+	);
+	let settings = dst.join("app/settings.json");
+	assert_eq!(
+		fs::metadata(&settings).unwrap().ino(),
+		fs::metadata(src.join("app/settings.json")).unwrap().ino()
+	);
+	run(&["status"]).success();
+
+	fs::write(&copy, "[user] local").unwrap();
+	assert_eq!(status("git"), "modified-locally");
+	run(&["link", "git"])
+		.failure()
+		.stderr(predicate::str::contains("modified locally"));
+	assert_eq!(fs::read_to_string(&copy).unwrap(), "[user] local");
+	run(&["link", "git", "--conflict-strategy", "backup"]).success();
+	assert_eq!(fs::read_to_string(&copy).unwrap(), "[user]");
+
+	fs::write(src.join("git/config"), "[user] new").unwrap();
+	assert_eq!(status("git"), "source-updated");
+	run(&["link", "git"]).success();
+	assert_eq!(fs::read_to_string(&copy).unwrap(), "[user] new");
+
+	// replacing the file breaks the hard link
+	fs::remove_file(&settings).unwrap();
+	fs::write(&settings, "{\"theme\": \"dark\"}").unwrap();
+	assert_eq!(status("app"), "modified-locally");
+
+	run(&["remove", "git"]).success();
+	assert!(!copy.exists());
+	assert!(!dst.join("git").exists());
+}
+
+#[test]
+fn file_mappings_fan_out_and_round_trip() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("git")).unwrap();
+	fs::write(src.join("git/gitconfig"), "[user]").unwrap();
+	fs::write(src.join("git/ignore"), "*.swp").unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.git]
+files = [
+	{{ source = "gitconfig", target = ".gitconfig" }},
+	{{ source = "ignore", target = ".config/git/ignore" }},
+	{{ source = "ignore", target = ".hgignore" }},
+]
+"#,
+			src.display(),
+			dst.display()
+		),
+	);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["link"]).success();
+	for (target, source) in [
+		(".gitconfig", "gitconfig"),
+		(".config/git/ignore", "ignore"),
+		(".hgignore", "ignore"),
+	] {
+		assert_eq!(
+			fs::read_link(dst.join(target)).unwrap(),
+			src.join("git").join(source)
+		);
+	}
+	assert!(!dst.join("git").exists());
+
+	let out = run(&["status", "--json"])
+		.success()
+		.get_output()
+		.stdout
+		.clone();
+	let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+	assert_eq!(v[0]["status"], "ok");
+	assert_eq!(v[0]["target"], dst.display().to_string());
+	assert_eq!(v[0]["files"].as_array().unwrap().len(), 3);
+	let owned = fs::read_to_string(tmp.path().join("owned.toml")).unwrap();
+	assert_eq!(owned.matches("/src/git/ignore\"").count(), 2);
+
+	fs::remove_file(dst.join(".hgignore")).unwrap();
+	run(&["status"])
+		.code(2)
+		.stdout(predicate::str::contains("partially-linked"));
+
+	run(&["remove", "git"]).success();
+	assert!(fs::symlink_metadata(dst.join(".gitconfig")).is_err());
+	assert!(fs::symlink_metadata(dst.join(".config/git/ignore")).is_err());
+}
+
+#[test]
+fn dependencies_are_linked_first_and_removed_last() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	for pkg in ["zsh", "zsh-plugins", "fonts", "mac-fonts"] {
+		fs::create_dir_all(src.join(pkg)).unwrap();
+	}
+	fs::create_dir_all(&dst).unwrap();
+	let base = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.zsh]
+depends = ["zsh-plugins", "fonts"]
+[packages.zsh-plugins]
+depends = ["fonts"]
+[packages.fonts]
+[packages.mac-fonts]
+os = ["none"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = write_cfg(tmp.path(), &base);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+	// The package each dry-run line links or removes, in order.
+	let order = |args: &[&str]| -> Vec<String> {
+		let out = run(args).success().get_output().stdout.clone();
+		String::from_utf8_lossy(&out)
+			.lines()
+			.filter_map(|line| line.strip_prefix("[dry-run] "))
+			.filter_map(|line| line.split(" -> ").next())
+			.filter_map(|path| path.rsplit('/').next().map(str::to_string))
+			.collect()
+	};
+
+	assert_eq!(order(&["link", "zsh", "--dry-run"]), [
+		"fonts",
+		"zsh-plugins",
+		"zsh"
+	]);
+	run(&["link", "zsh"]).success();
+	for pkg in ["zsh", "zsh-plugins", "fonts"] {
+		assert!(dst.join(pkg).is_symlink(), "{pkg} is not linked");
+	}
+	assert_eq!(order(&["remove", "--dry-run"]), [
+		"zsh",
+		"zsh-plugins",
+		"fonts"
+	]);
+	run(&["remove", "zsh"]).success();
+	assert!(!dst.join("zsh").exists());
+	assert!(dst.join("fonts").is_symlink());
+
+	let cfg = base.replace(
+		"[packages.fonts]\n",
+		"[packages.fonts]\ndepends = [\"mac-fonts\"]\n",
 	);
+	fs::write(&cfg_path, cfg).unwrap();
+	run(&["link", "zsh"])
+		.failure()
+		.stderr(predicate::str::contains(
+			"package 'fonts' depends on 'mac-fonts', which is not applied on",
+		));
+	assert!(!dst.join("zsh").exists());
+
+	let cfg = base.replace(
+		"[packages.fonts]\n",
+		"[packages.fonts]\ndepends = [\"zsh\"]\n",
+	);
+	fs::write(&cfg_path, cfg).unwrap();
+	run(&["link", "zsh-plugins"])
+		.failure()
+		.stderr(predicate::str::contains(
+			"dependency cycle: zsh-plugins -> fonts -> zsh -> zsh-plugins",
+		));
+}
+
+#[test]
+fn hooks_run_around_link_and_remove() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let log = tmp.path().join("hooks.log");
+	fs::create_dir_all(src.join("tmux")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{src}"
+target_dir = "{dst}"
+[packages.tmux.hooks]
+pre_link = "test ! -e \"$RINKLE_TARGET\" && echo pre_link >> {log}"
+post_link = "test -L \"$RINKLE_TARGET\" && echo \"post_link $RINKLE_PACKAGE\" | tee -a {log}"
+post_remove = "echo post_remove >> {log}"
+"#,
+		src = src.display(),
+		dst = dst.display(),
+		log = log.display(),
+	);
+	let cfg_path = write_cfg(tmp.path(), &cfg);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+	let logged = || fs::read_to_string(&log).unwrap_or_default();
+
+	run(&["link", "--dry-run"])
+		.success()
+		.stdout(predicate::str::contains(
+			"[dry-run] run pre_link hook of tmux",
+		));
+	run(&["link", "--no-hooks"]).success();
+	run(&["remove", "--no-hooks"]).success();
+	assert_eq!(logged(), "");
+
+	run(&["link"])
+		.success()
+		.stderr(predicate::str::contains("tmux post_link: post_link tmux"));
+	assert!(dst.join("tmux").is_symlink());
+	run(&["remove"]).success();
+	assert_eq!(logged(), "pre_link\npost_link tmux\npost_remove\n");
+
+	// A failing pre hook rolls the run back.
+	fs::write(&cfg_path, cfg.replace("test ! -e", "false &&")).unwrap();
+	run(&["link"])
+		.failure()
+		.stderr(predicate::str::contains("pre_link hook of 'tmux' failed"));
+	assert!(!dst.join("tmux").exists());
+}
+
+#[test]
+fn versions_are_discovered_from_the_template() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	for dir in ["nvim-v0.9.0", "nvim-v0.10.1", "nvim", "zsh"] {
+		fs::create_dir_all(src.join(dir)).unwrap();
+	}
+	fs::create_dir_all(&dst).unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[vsc]
+template = '^(?P<package>.+)-(?P<version>v\d+\.\d+\.\d+)$'
+default_version = "stable"
+[vsc.aliases]
+lts = "v0.9"
+[packages.nvim]
+default_version = "v0.9.0"
+[packages.zsh]
+"#,
+			src.display(),
+			dst.display()
+		),
+	);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	// zsh has no versions, so the global default does not apply to it.
+	run(&["link"]).success();
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim-v0.9.0")
+	);
+	assert_eq!(fs::read_link(dst.join("zsh")).unwrap(), src.join("zsh"));
+
+	run(&["vsc", "nvim", "v0.10.1", "--no-apply"]).success();
+	run(&["vsc", "list", "nvim"])
+		.success()
+		.stdout(predicate::str::contains(
+			"nvim\n  v0.9.0 (default, active)\n  v0.10.1 (pinned)\n",
+		));
+	run(&["vsc", "list"])
+		.success()
+		.stdout(predicate::str::contains("zsh").not());
+	run(&["vsc", "list", "zsh"])
+		.failure()
+		.stderr(predicate::str::contains("no versions of 'zsh' found"));
+
+	run(&["vsc", "nvim", "v1.0.0"])
+		.failure()
+		.stderr(predicate::str::contains(
+			"package 'nvim' has no version 'v1.0.0' (available: v0.9.0, \
+			 v0.10.1; see `rk vsc list nvim`)",
+		));
+	fs::remove_file(dst.join("nvim")).unwrap();
+	run(&["link", "nvim@nightly"])
+		.failure()
+		.stderr(predicate::str::contains("has no version 'nightly'"));
+	assert!(!dst.join("nvim").exists());
+
+	// `latest` keeps tracking new version directories.
+	run(&["vsc", "nvim", "latest", "--no-apply"]).success();
+	run(&["link", "nvim"]).success();
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim-v0.10.1")
+	);
+	std::thread::sleep(std::time::Duration::from_millis(20));
+	fs::create_dir(src.join("nvim-v0.11.0")).unwrap();
+	run(&["link", "nvim"]).success();
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim-v0.11.0")
+	);
+	run(&["vsc", "list", "nvim"])
+		.success()
+		.stdout(predicate::str::contains(
+			"  v0.11.0 (pinned as latest, active)\n",
+		));
+
+	// Ranges and aliases pick the highest version they match.
+	fs::create_dir(src.join("nvim-v0.9.4")).unwrap();
+	run(&["link", "nvim@^0.10"]).success();
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim-v0.10.1")
+	);
+	run(&["vsc", "nvim", "lts"]).success();
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim-v0.9.4")
+	);
+}
+
+#[test]
+fn version_cache_is_refreshed_and_clearable() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let cache_dir = tmp.path().join("cache");
+	fs::create_dir_all(src.join("nvim@stable")).unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			"[global]\nsource_dir = \"{}\"\n[packages.nvim]\n",
+			src.display()
+		),
+	);
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+			.env("RINKLE_CACHE_DIR", &cache_dir)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["cache", "show"])
+		.success()
+		.stdout(predicate::str::contains("is empty"));
+	// A dry run scans without writing the cache.
+	run(&["vsc", "list", "--dry-run"])
+		.success()
+		.stdout(predicate::str::contains("stable"));
+	run(&["link", "nvim@stable", "--dry-run"]).success();
+	assert!(!cache_dir.join("vsc.toml").exists());
+	run(&["vsc", "list"])
+		.success()
+		.stdout(predicate::str::contains("stable"));
+	assert!(cache_dir.join("vsc.toml").exists());
+	run(&["cache", "show"])
+		.success()
+		.stdout(predicate::str::contains(
+			"1 package(s), 1 version(s), fresh",
+		));
+
+	std::thread::sleep(std::time::Duration::from_millis(20));
+	fs::create_dir(src.join("nvim@nightly")).unwrap();
+	run(&["cache", "show"])
+		.success()
+		.stdout(predicate::str::contains("stale"));
+	run(&["vsc", "list"])
+		.success()
+		.stdout(predicate::str::contains("nightly"));
+
+	run(&["cache", "clear", "--dry-run"]).success();
+	assert!(cache_dir.join("vsc.toml").exists());
+	run(&["cache", "clear"])
+		.success()
+		.stdout(predicate::str::contains("Cleared"));
+	assert!(!cache_dir.join("vsc.toml").exists());
+}
+
+#[test]
+fn pinning_a_linked_package_switches_its_links() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	for version in ["stable", "nightly"] {
+		fs::create_dir_all(src.join(format!("nvim@{version}"))).unwrap();
+		let tree = src.join(format!("tmux@{version}"));
+		fs::create_dir_all(&tree).unwrap();
+		fs::write(tree.join(format!("{version}.conf")), version).unwrap();
+	}
+	fs::create_dir_all(&dst).unwrap();
+	let cfg_path = write_cfg(
+		tmp.path(),
+		&format!(
+			r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[vsc]
+default_version = "stable"
+[packages.nvim]
+[packages.tmux]
+mode = "tree"
+"#,
+			src.display(),
+			dst.display()
+		),
+	);
+	let state = tmp.path().join("state.toml");
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.env("RINKLE_DATA_DIR", tmp.path().join("data"))
+			.env("RINKLE_CACHE_DIR", tmp.path().join("cache"))
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+	run(&["link"]).success();
+	let nvim = dst.join("nvim");
+
+	run(&["--dry-run", "vsc", "nvim", "nightly"])
+		.success()
+		.stdout(predicate::str::contains(format!(
+			"[dry-run] re-point {} -> {}",
+			nvim.display(),
+			src.join("nvim@nightly").display()
+		)));
+	assert_eq!(fs::read_link(&nvim).unwrap(), src.join("nvim@stable"));
+
+	run(&["vsc", "nvim", "nightly"]).success().stdout(
+		predicate::str::contains(format!(
+			"1 link(s) changed:\n  {}: {} -> {}",
+			nvim.display(),
+			src.join("nvim@stable").display(),
+			src.join("nvim@nightly").display()
+		)),
+	);
+	assert_eq!(fs::read_link(&nvim).unwrap(), src.join("nvim@nightly"));
+	assert!(fs::read_dir(&dst).unwrap().all(|e| {
+		!e.unwrap()
+			.file_name()
+			.to_string_lossy()
+			.contains(".rinkle-")
+	}));
+
+	// Trees are removed and placed again from the new version.
+	run(&["vsc", "tmux", "nightly"]).success().stdout(
+		predicate::str::contains(format!(
+			"  {}: {} -> {}",
+			dst.join("tmux").display(),
+			src.join("tmux@stable").display(),
+			src.join("tmux@nightly").display()
+		)),
+	);
+	assert!(dst.join("tmux/nightly.conf").exists());
+	assert!(!dst.join("tmux/stable.conf").exists());
+
+	run(&["vsc", "nvim", "stable", "--no-apply"])
+		.success()
+		.stdout(predicate::str::contains("changed").not());
+	assert_eq!(fs::read_link(&nvim).unwrap(), src.join("nvim@nightly"));
+	run(&["status"]).code(2);
+	run(&["link", "nvim"]).success();
+	assert_eq!(fs::read_link(&nvim).unwrap(), src.join("nvim@stable"));
+}
+
+#[test]
+fn missing_versions_fall_back_along_the_chain() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("nvim@stable")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let config = |strict: bool| {
+		format!(
+			r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[vsc]
+fallback = ["nightly", "stable"]
+strict = {strict}
+[packages.nvim]
+"#,
+			src.display(),
+			dst.display()
+		)
+	};
+	let cfg_path = write_cfg(tmp.path(), &config(false));
+	let state = tmp.path().join("state.toml");
+	fs::write(&state, "[pinned_versions]\nnvim = \"beta\"\n").unwrap();
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state)
+			.env("RINKLE_CACHE_DIR", tmp.path().join("cache"))
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert()
+	};
+
+	run(&["link"]).success().stderr(predicate::str::contains(
+		"nvim: pinned version 'beta' not found, falling back to 'stable'",
+	));
+	assert_eq!(
+		fs::read_link(dst.join("nvim")).unwrap(),
+		src.join("nvim@stable")
+	);
+	run(&["status"]).success().stdout(predicate::str::contains(
+		"    version stable (fallback, pinned beta not found)",
+	));
+	run(&["status", "--json"])
+		.success()
+		.stdout(predicate::str::contains(r#""fallback": "stable""#));
+	// A version asked for explicitly never falls back.
+	run(&["vsc", "nvim", "nightly"])
+		.failure()
+		.stderr(predicate::str::contains("has no version 'nightly'"));
+
+	write_cfg(tmp.path(), &config(true));
+	run(&["link"])
+		.failure()
+		.stderr(predicate::str::contains("has no version 'beta'"));
+	run(&["status"]).stdout(predicate::str::contains(
+		"    version unversioned (pinned beta not found)",
+	));
+
+	std::thread::sleep(std::time::Duration::from_millis(20));
+	fs::create_dir(src.join("nvim@beta")).unwrap();
+	run(&["link"]).success();
+	run(&["status"])
+		.success()
+		.stdout(predicate::str::contains("    version beta (pinned)\n"));
 }
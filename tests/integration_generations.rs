@@ -0,0 +1,190 @@
+use std::{
+	fs,
+	path::Path,
+	process::Command,
+};
+
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+/// The binary with its state, data and cache directories inside `dir`, so
+/// tests never touch the real ones.
+fn bin(dir: &Path) -> Command {
+	let mut cmd = Command::cargo_bin("rinkle").unwrap();
+	cmd.env("RINKLE_STATE_PATH", dir.join("state.toml"))
+		.env("RINKLE_DATA_DIR", dir.join("data"))
+		.env("RINKLE_CACHE_DIR", dir.join("cache"));
+	cmd
+}
+
+fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+#[test]
+fn rollback_restores_links_and_profile() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	fs::create_dir_all(src.join("a")).unwrap();
+	fs::create_dir_all(src.join("b")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[profiles]
+work = ["work"]
+[packages.a]
+tags = ["work"]
+[packages.b]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state_path)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert().success()
+	};
+
+	run(&["link", "a", "b"]); // generation 1
+	run(&["remove", "a"]); // generation 2
+	run(&["use-profile", "work"]); // generation 3
+	run(&["link", "b"]); // unchanged, nothing recorded
+	assert!(!dst.join("a").exists());
+
+	run(&["rollback", "1"]);
+	assert!(is_symlink(&dst.join("a")));
+	assert!(is_symlink(&dst.join("b")));
+	let state = fs::read_to_string(&state_path).unwrap();
+	assert!(!state.contains("work"));
+
+	// without an argument, step back to the generation before the current
+	run(&["rollback"]);
+	assert!(!dst.join("a").exists());
+	assert!(is_symlink(&dst.join("b")));
+	let state = fs::read_to_string(&state_path).unwrap();
+	assert!(state.contains("work"));
+
+	run(&["generations"])
+		.stdout(predicate::str::contains("rollback 1"))
+		.stdout(predicate::str::contains("rollback 3"))
+		.stdout(predicate::str::contains("(current)"));
+}
+
+#[test]
+fn rollback_refolds_tree_links() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	fs::create_dir_all(src.join("base/conf.d")).unwrap();
+	fs::create_dir_all(src.join("extra/conf.d")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(src.join("base/conf.d/base.fish"), "base").unwrap();
+	fs::write(src.join("extra/conf.d/extra.fish"), "extra").unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.base]
+target = "fish"
+mode = "tree"
+[packages.extra]
+target = "fish"
+mode = "tree"
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state_path)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert().success()
+	};
+
+	run(&["link", "base"]);
+	run(&["link", "extra"]);
+	assert!(!is_symlink(&dst.join("fish")));
+
+	run(&["rollback"]);
+	assert!(is_symlink(&dst.join("fish")));
+	assert_eq!(
+		fs::read_to_string(dst.join("fish/conf.d/base.fish")).unwrap(),
+		"base"
+	);
+	assert!(!dst.join("fish/conf.d/extra.fish").exists());
+}
+
+#[test]
+fn rollback_restores_copied_files() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	fs::create_dir_all(src.join("git")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(src.join("git/config"), "one").unwrap();
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.git]
+deploy = "copy"
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.args(args).arg("--config").arg(&cfg_path);
+		cmd.assert().success()
+	};
+	let copied = dst.join("git/config");
+
+	run(&["link", "git"]); // generation 1
+	run(&["remove", "git"]); // generation 2
+	assert!(!copied.exists());
+	run(&["generations"]).stdout(predicate::str::contains("files=1"));
+
+	run(&["rollback", "1"]);
+	assert!(!is_symlink(&copied));
+	assert_eq!(fs::read_to_string(&copied).unwrap(), "one");
+	run(&["rollback", "2"]);
+	assert!(!copied.exists());
+
+	// The content of generation 1 is gone from the source.
+	fs::write(src.join("git/config"), "two").unwrap();
+	run(&["rollback", "1"]).stderr(predicate::str::contains("not restoring"));
+	assert!(!copied.exists());
+}
+
+#[test]
+fn rollback_without_history_fails() {
+	let tmp = tempdir().unwrap();
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, "[global]\n").unwrap();
+	bin(tmp.path())
+		.env("RINKLE_STATE_PATH", tmp.path().join("state.toml"))
+		.args(["rollback", "--config"])
+		.arg(&cfg_path)
+		.assert()
+		.failure()
+		.stderr(predicate::str::contains("no previous generation"));
+}
@@ -0,0 +1,187 @@
+use std::{
+	fs,
+	path::Path,
+	process::Command,
+};
+
+use assert_cmd::prelude::*;
+use tempfile::tempdir;
+
+/// The binary with its state, data and cache directories inside `dir`, so
+/// tests never touch the real ones.
+fn bin(dir: &Path) -> Command {
+	let mut cmd = Command::cargo_bin("rinkle").unwrap();
+	cmd.env("RINKLE_STATE_PATH", dir.join("state.toml"))
+		.env("RINKLE_DATA_DIR", dir.join("data"))
+		.env("RINKLE_CACHE_DIR", dir.join("cache"));
+	cmd
+}
+
+fn is_symlink(path: &Path) -> bool {
+	fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+#[test]
+fn tree_mode_shares_directories_between_packages() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	fs::create_dir_all(src.join("fish-base/conf.d")).unwrap();
+	fs::create_dir_all(src.join("fish-extra/conf.d")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(src.join("fish-base/config.fish"), "base").unwrap();
+	fs::write(src.join("fish-base/conf.d/base.fish"), "base").unwrap();
+	fs::write(src.join("fish-extra/conf.d/extra.fish"), "extra").unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+[packages.fish-base]
+source = "fish-base"
+target = "fish"
+mode = "tree"
+[packages.fish-extra]
+source = "fish-extra"
+target = "fish"
+mode = "tree"
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state_path)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert().success();
+	};
+
+	run(&["link", "fish-base"]);
+	assert!(is_symlink(&dst.join("fish")));
+
+	run(&["link", "fish-extra"]);
+	assert!(!is_symlink(&dst.join("fish")));
+	assert!(!is_symlink(&dst.join("fish/conf.d")));
+	assert!(is_symlink(&dst.join("fish/config.fish")));
+	assert_eq!(
+		fs::read_to_string(dst.join("fish/conf.d/base.fish")).unwrap(),
+		"base"
+	);
+	assert_eq!(
+		fs::read_to_string(dst.join("fish/conf.d/extra.fish")).unwrap(),
+		"extra"
+	);
+
+	run(&["remove", "fish-extra"]);
+	assert!(is_symlink(&dst.join("fish")));
+	assert!(!dst.join("fish/conf.d/extra.fish").exists());
+}
+
+#[test]
+fn ignored_files_are_never_linked() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let state_path = tmp.path().join("state.toml");
+	let nvim = src.join("nvim");
+	fs::create_dir_all(nvim.join(".git")).unwrap();
+	fs::create_dir_all(nvim.join("lua/plugins")).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(nvim.join(".git/HEAD"), "ref").unwrap();
+	fs::write(nvim.join("README.md"), "docs").unwrap();
+	fs::write(nvim.join("init.lua"), "init").unwrap();
+	fs::write(nvim.join("init.lua.swp"), "swap").unwrap();
+	fs::write(nvim.join("notes.txt"), "notes").unwrap();
+	fs::write(nvim.join("lua/plugins/a.lua"), "a").unwrap();
+	fs::write(nvim.join(".rinkleignore"), "*.swp\n").unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+ignore = [".git/", "README.md"]
+[packages.nvim]
+ignore = ["*.txt"]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+
+	let run = |args: &[&str]| {
+		let mut cmd = bin(tmp.path());
+		cmd.env("RINKLE_STATE_PATH", &state_path)
+			.args(args)
+			.arg("--config")
+			.arg(&cfg_path);
+		cmd.assert().success()
+	};
+
+	run(&["link", "nvim"]);
+	let target = dst.join("nvim");
+	assert!(!is_symlink(&target));
+	assert!(is_symlink(&target.join("init.lua")));
+	// directories without ignored entries are still folded
+	assert!(is_symlink(&target.join("lua")));
+	for ignored in [
+		".git",
+		"README.md",
+		"init.lua.swp",
+		"notes.txt",
+		".rinkleignore",
+	] {
+		assert!(
+			fs::symlink_metadata(target.join(ignored)).is_err(),
+			"{ignored}"
+		);
+	}
+	run(&["status", "--json"]).stdout(predicates::str::contains("\"ok\""));
+
+	run(&["remove", "nvim"]);
+	assert!(fs::symlink_metadata(&target).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn unfolding_a_directory_link_for_ignored_entries_warns() {
+	let tmp = tempdir().unwrap();
+	let src = tmp.path().join("src");
+	let dst = tmp.path().join("dst");
+	let nvim = src.join("nvim");
+	fs::create_dir_all(&nvim).unwrap();
+	fs::create_dir_all(&dst).unwrap();
+	fs::write(nvim.join("init.lua"), "init").unwrap();
+	fs::write(nvim.join("README.md"), "docs").unwrap();
+	// a whole-directory link left by a version without ignore rules
+	std::os::unix::fs::symlink(&nvim, dst.join("nvim")).unwrap();
+
+	let cfg = format!(
+		r#"[global]
+source_dir = "{}"
+target_dir = "{}"
+ignore = ["README.md"]
+[packages.nvim]
+"#,
+		src.display(),
+		dst.display()
+	);
+	let cfg_path = tmp.path().join("rinkle.toml");
+	fs::write(&cfg_path, cfg).unwrap();
+
+	bin(tmp.path())
+		.args(["link", "nvim", "--config"])
+		.arg(&cfg_path)
+		.assert()
+		.success()
+		.stderr(predicates::str::contains("set mode = \"tree\""));
+	assert!(!is_symlink(&dst.join("nvim")));
+	assert!(is_symlink(&dst.join("nvim/init.lua")));
+	assert!(fs::symlink_metadata(dst.join("nvim/README.md")).is_err());
+}